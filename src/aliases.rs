@@ -0,0 +1,268 @@
+use crate::hardware_manager::{PwmChannel, UserLed};
+use crate::settings;
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::RwLock;
+
+const SETTINGS_NAME: &str = "aliases";
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct PwmAlias {
+    pub channel: PwmChannel,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct UserLedAlias {
+    pub led: UserLed,
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct NeoPixelAlias {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct AliasSettings {
+    #[serde(default)]
+    pub pwm: Vec<PwmAlias>,
+    #[serde(default)]
+    pub user_led: Vec<UserLedAlias>,
+    #[serde(default)]
+    pub neopixel: Option<NeoPixelAlias>,
+}
+
+/// Names of the outputs themselves, like `ch1`, `led2` or `all`, can't be used as aliases
+fn is_reserved(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name == "all"
+        || ["ch", "led"].iter().any(|prefix| {
+            name.strip_prefix(prefix).is_some_and(|number| {
+                !number.is_empty() && number.chars().all(|c| c.is_ascii_digit())
+            })
+        })
+}
+
+impl AliasSettings {
+    pub fn check(&self) -> Result<(), String> {
+        let names = self
+            .pwm
+            .iter()
+            .map(|alias| &alias.name)
+            .chain(self.user_led.iter().map(|alias| &alias.name))
+            .chain(self.neopixel.iter().map(|alias| &alias.name));
+
+        // Aliases are matched ignoring case, like the outputs' names
+        let mut seen = HashSet::new();
+        for name in names {
+            settings::check_name("Alias", name)?;
+            if is_reserved(name) {
+                return Err(format!("{name} is the name of an output"));
+            }
+            if !seen.insert(name.to_lowercase()) {
+                return Err(format!("Alias {name} is used more than once"));
+            }
+        }
+
+        let mut channels = HashSet::new();
+        if let Some(alias) = self
+            .pwm
+            .iter()
+            .find(|alias| !channels.insert(&alias.channel))
+        {
+            return Err(format!("{:?} has more than one alias", alias.channel));
+        }
+        let mut leds = HashSet::new();
+        if let Some(alias) = self.user_led.iter().find(|alias| !leds.insert(&alias.led)) {
+            return Err(format!("{:?} has more than one alias", alias.led));
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref ALIASES: RwLock<AliasSettings> = Default::default();
+}
+
+/// Loads the saved aliases, before any settings that may refer to them
+pub fn init() {
+    let Some(settings) = settings::load::<AliasSettings>(SETTINGS_NAME) else {
+        return;
+    };
+    if let Err(error) = settings.check() {
+        log::warn!("Aliases: ignoring the saved settings: {error}");
+        return;
+    }
+    if let Ok(mut aliases) = ALIASES.write() {
+        *aliases = settings;
+    }
+}
+
+pub fn settings() -> AliasSettings {
+    ALIASES
+        .read()
+        .map(|aliases| aliases.clone())
+        .unwrap_or_default()
+}
+
+/// Applies and saves the aliases
+pub fn set_settings(settings: AliasSettings) -> std::io::Result<()> {
+    settings::save(SETTINGS_NAME, &settings)?;
+    if let Ok(mut aliases) = ALIASES.write() {
+        *aliases = settings;
+    }
+    Ok(())
+}
+
+/// Channel named `name`, ignoring case
+pub fn pwm_channel(name: &str) -> Option<PwmChannel> {
+    let aliases = ALIASES.read().ok()?;
+    aliases
+        .pwm
+        .iter()
+        .find(|alias| alias.name.eq_ignore_ascii_case(name))
+        .map(|alias| alias.channel.clone())
+}
+
+/// LED named `name`, ignoring case
+pub fn user_led(name: &str) -> Option<UserLed> {
+    let aliases = ALIASES.read().ok()?;
+    aliases
+        .user_led
+        .iter()
+        .find(|alias| alias.name.eq_ignore_ascii_case(name))
+        .map(|alias| alias.led.clone())
+}
+
+/// Alias of each channel, or its own name if it has none
+pub fn pwm_names(channels: &[PwmChannel]) -> Vec<String> {
+    let aliases = settings();
+    channels
+        .iter()
+        .map(|channel| {
+            aliases
+                .pwm
+                .iter()
+                .find(|alias| &alias.channel == channel)
+                .map(|alias| alias.name.clone())
+                .unwrap_or_else(|| format!("{channel:?}"))
+        })
+        .collect()
+}
+
+/// Alias of each LED, or its own name if it has none
+pub fn user_led_names(leds: &[UserLed]) -> Vec<String> {
+    let aliases = settings();
+    leds.iter()
+        .map(|led| {
+            aliases
+                .user_led
+                .iter()
+                .find(|alias| &alias.led == led)
+                .map(|alias| alias.name.clone())
+                .unwrap_or_else(|| format!("{led:?}"))
+        })
+        .collect()
+}
+
+pub fn neopixel_name() -> Option<String> {
+    settings().neopixel.map(|alias| alias.name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aliases(pwm: &[(PwmChannel, &str)], user_led: &[(UserLed, &str)]) -> AliasSettings {
+        AliasSettings {
+            pwm: pwm
+                .iter()
+                .map(|(channel, name)| PwmAlias {
+                    channel: channel.clone(),
+                    name: name.to_string(),
+                    description: String::new(),
+                })
+                .collect(),
+            user_led: user_led
+                .iter()
+                .map(|(led, name)| UserLedAlias {
+                    led: led.clone(),
+                    name: name.to_string(),
+                    description: String::new(),
+                })
+                .collect(),
+            neopixel: None,
+        }
+    }
+
+    #[test]
+    fn outputs_names_are_reserved() {
+        for name in ["all", "ALL", "ch1", "Ch16", "led2", "LED3"] {
+            let settings = aliases(&[(PwmChannel::Ch1, name)], &[]);
+            assert!(settings.check().is_err(), "{name}");
+        }
+        // Only the prefixes followed by a number are outputs
+        for name in ["ch", "channel1", "led", "ledx", "light-1"] {
+            assert!(aliases(&[(PwmChannel::Ch1, name)], &[]).check().is_ok());
+        }
+        for name in ["", "left thruster", "gripper/open"] {
+            assert!(aliases(&[(PwmChannel::Ch1, name)], &[]).check().is_err());
+        }
+    }
+
+    #[test]
+    fn aliases_and_outputs_are_unique() {
+        let settings = aliases(
+            &[(PwmChannel::Ch1, "left"), (PwmChannel::Ch2, "right")],
+            &[(UserLed::Led1, "lamp")],
+        );
+        assert!(settings.check().is_ok());
+
+        // Names are compared ignoring case, across the outputs
+        let settings = aliases(&[(PwmChannel::Ch1, "left"), (PwmChannel::Ch2, "LEFT")], &[]);
+        assert!(settings.check().is_err());
+        let settings = aliases(&[(PwmChannel::Ch1, "lamp")], &[(UserLed::Led1, "Lamp")]);
+        assert!(settings.check().is_err());
+        let mut settings = aliases(&[(PwmChannel::Ch1, "left")], &[]);
+        settings.neopixel = Some(NeoPixelAlias {
+            name: "Left".to_string(),
+            description: String::new(),
+        });
+        assert!(settings.check().is_err());
+
+        // Each output has a single alias
+        let settings = aliases(&[(PwmChannel::Ch1, "left"), (PwmChannel::Ch1, "port")], &[]);
+        assert!(settings.check().is_err());
+        let settings = aliases(&[], &[(UserLed::Led2, "lamp"), (UserLed::Led2, "light")]);
+        assert!(settings.check().is_err());
+    }
+
+    #[test]
+    fn outputs_are_found_ignoring_case() {
+        *ALIASES.write().unwrap() =
+            aliases(&[(PwmChannel::Ch3, "Gripper")], &[(UserLed::Led2, "Lamp")]);
+
+        assert_eq!(pwm_channel("gripper"), Some(PwmChannel::Ch3));
+        assert_eq!(pwm_channel("GRIPPER"), Some(PwmChannel::Ch3));
+        assert_eq!(pwm_channel("lamp"), None);
+        assert_eq!(user_led("lAMP"), Some(UserLed::Led2));
+        assert_eq!(user_led("gripper"), None);
+        // Names are given as they were set
+        assert_eq!(
+            pwm_names(&[PwmChannel::Ch3, PwmChannel::Ch4]),
+            vec!["Gripper".to_string(), "Ch4".to_string()]
+        );
+        assert_eq!(user_led_names(&[UserLed::Led2]), vec!["Lamp".to_string()]);
+
+        *ALIASES.write().unwrap() = AliasSettings::default();
+    }
+}
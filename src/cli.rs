@@ -1,23 +1,49 @@
+use crate::data_logger::{
+    Column, LogCompression, LogFormat, RetentionSettings, RotationSettings, Timestamp,
+    TriggerCondition, TriggerSettings, TriggerSource, MAX_TRIGGER_TIME,
+};
+use crate::hardware_manager::{DataloggerSettings, MonitorSettings};
 use clap::{Arg, Command};
+use std::str::FromStr;
 
-#[derive(Debug)]
-pub struct DataloggerSettings {
-    pub directory: String,
-    pub filename: String,
-    pub interval: u64,
+pub struct ServerSettings {
+    pub port: u16,
+    /// Allows taking the outputs' leases held by other clients
+    pub admin_key: Option<String>,
+}
+
+// The admin key is left out of the logs
+impl std::fmt::Debug for ServerSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerSettings")
+            .field("port", &self.port)
+            .field("admin_key", &self.admin_key.as_ref().map(|_| "***"))
+            .finish()
+    }
 }
 
 #[derive(Debug)]
-pub struct MonitorSettings {
-    pub interval: u64,
+pub struct StorageSettings {
+    pub directory: String,
 }
 
 #[derive(Debug)]
-pub struct ServerSettings {
-    pub port: u16,
+pub struct ReplaySettings {
+    /// Datalogger file replayed in place of the navigator's readings
+    pub file: Option<String>,
+    pub speed: f64,
 }
 
-pub fn parse_args() -> (DataloggerSettings, MonitorSettings, ServerSettings) {
+/// Free disk space below which the datalogger pauses, in bytes
+const DEFAULT_MIN_FREE_SPACE: u64 = 50 * 1024 * 1024;
+
+pub fn parse_args() -> (
+    DataloggerSettings,
+    MonitorSettings,
+    ServerSettings,
+    StorageSettings,
+    ReplaySettings,
+) {
     let matches = Command::new("Navigator Assistant")
         .version("1.0")
         .author("BlueRobotics")
@@ -44,6 +70,116 @@ pub fn parse_args() -> (DataloggerSettings, MonitorSettings, ServerSettings) {
                 .value_parser(clap::value_parser!(bool))
                 .required(false),
         )
+        .arg(
+            Arg::new("datalogger_format")
+                .long("datalogger-format")
+                .value_parser(["csv", "jsonl", "msgpack"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_compression")
+                .long("datalogger-compression")
+                .value_parser(["none", "gzip", "zstd"])
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_compression_level")
+                .long("datalogger-compression-level")
+                .value_parser(clap::value_parser!(i32))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_events")
+                .long("datalogger-events")
+                .value_parser(clap::value_parser!(bool))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_timestamps")
+                .long("datalogger-timestamps")
+                .help("Comma separated list of local, utc, epoch and monotonic")
+                .value_delimiter(',')
+                .value_parser(parse_timestamp)
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_columns")
+                .long("datalogger-columns")
+                .help("Comma separated list, like adc,temperature,vertical_speed,pwm")
+                .value_delimiter(',')
+                .value_parser(parse_column)
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_max_file_size")
+                .long("datalogger-max-file-size")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_max_file_duration")
+                .long("datalogger-max-file-duration")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_rotate_on_start")
+                .long("datalogger-rotate-on-start")
+                .value_parser(clap::value_parser!(bool))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_keep_files")
+                .long("datalogger-keep-files")
+                .value_parser(clap::value_parser!(usize))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_keep_days")
+                .long("datalogger-keep-days")
+                .value_parser(clap::value_parser!(u32))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_max_total_size")
+                .long("datalogger-max-total-size")
+                .value_parser(clap::value_parser!(u64))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_min_free_space")
+                .long("datalogger-min-free-space")
+                .value_parser(clap::value_parser!(u64))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_trigger_depth")
+                .long("datalogger-trigger-depth")
+                .help("Writes only while deeper than this, in meters, and around it")
+                .value_parser(clap::value_parser!(f32))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_trigger_leak")
+                .long("datalogger-trigger-leak")
+                .help("Writes only while a leak is detected, and around it")
+                .value_parser(clap::value_parser!(bool))
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_pre_trigger")
+                .long("datalogger-pre-trigger")
+                .help("Seconds written before a trigger")
+                .value_parser(parse_trigger_time)
+                .required(false),
+        )
+        .arg(
+            Arg::new("datalogger_post_trigger")
+                .long("datalogger-post-trigger")
+                .help("Seconds written after a trigger clears")
+                .value_parser(parse_trigger_time)
+                .required(false),
+        )
         .arg(
             Arg::new("monitor_rate")
                 .long("monitor-rate")
@@ -56,12 +192,25 @@ pub fn parse_args() -> (DataloggerSettings, MonitorSettings, ServerSettings) {
                 .value_parser(clap::value_parser!(bool))
                 .required(false),
         )
+        .arg(
+            Arg::new("settings_directory")
+                .long("settings-directory")
+                .required(false),
+        )
         .arg(
             Arg::new("server_port")
                 .long("server-port")
                 .value_parser(clap::value_parser!(u16))
                 .required(false),
         )
+        .arg(Arg::new("admin_key").long("admin-key").required(false))
+        .arg(Arg::new("replay_file").long("replay-file").required(false))
+        .arg(
+            Arg::new("replay_speed")
+                .long("replay-speed")
+                .value_parser(clap::value_parser!(f64))
+                .required(false),
+        )
         .get_matches();
 
     let datalogger_directory = matches
@@ -74,6 +223,48 @@ pub fn parse_args() -> (DataloggerSettings, MonitorSettings, ServerSettings) {
         .map(|f| f.to_string())
         .unwrap_or("data.csv".to_string());
 
+    let datalogger_format = match matches
+        .get_one::<String>("datalogger_format")
+        .map(String::as_str)
+    {
+        Some("jsonl") => LogFormat::JsonLines,
+        Some("msgpack") => LogFormat::MessagePack,
+        _ => LogFormat::Csv,
+    };
+
+    let datalogger_compression = match matches
+        .get_one::<String>("datalogger_compression")
+        .map(String::as_str)
+    {
+        Some("gzip") => LogCompression::Gzip,
+        Some("zstd") => LogCompression::Zstd,
+        _ => LogCompression::None,
+    };
+
+    let datalogger_compression_level = matches
+        .get_one::<i32>("datalogger_compression_level")
+        .copied();
+    if let Some(level) = datalogger_compression_level {
+        let levels = datalogger_compression.levels();
+        if !levels.contains(&level) {
+            panic!(
+                "Error: Invalid compression level, it must be between {} and {}",
+                levels.start(),
+                levels.end()
+            )
+        }
+    }
+
+    let datalogger_timestamps = matches
+        .get_many::<Timestamp>("datalogger_timestamps")
+        .map(|timestamps| timestamps.copied().collect::<Vec<_>>())
+        .unwrap_or_else(Timestamp::defaults);
+
+    let datalogger_columns = matches
+        .get_many::<Column>("datalogger_columns")
+        .map(|columns| columns.copied().collect::<Vec<_>>())
+        .unwrap_or_else(Column::defaults);
+
     let datalogger_rate = matches
         .get_one::<f64>("datalogger_rate")
         .copied()
@@ -84,14 +275,95 @@ pub fn parse_args() -> (DataloggerSettings, MonitorSettings, ServerSettings) {
         .copied()
         .unwrap_or(100.0);
 
+    // A zero rate keeps the service disabled, it can still be enabled later with the default rate
+    let datalogger_enable = matches
+        .get_one::<bool>("datalogger_enable")
+        .copied()
+        .unwrap_or(datalogger_rate != 0.0);
+
+    let monitor_enable = matches
+        .get_one::<bool>("monitor_enable")
+        .copied()
+        .unwrap_or(monitor_rate != 0.0);
+
+    // Triggered logging is enabled by its conditions, more can be added through the API
+    let mut trigger_conditions = vec![];
+    if let Some(&depth) = matches.get_one::<f32>("datalogger_trigger_depth") {
+        trigger_conditions.push(TriggerCondition {
+            source: TriggerSource::Depth,
+            channel: None,
+            above: depth,
+            neutral: 0.0,
+        });
+    }
+    if matches
+        .get_one::<bool>("datalogger_trigger_leak")
+        .copied()
+        .unwrap_or(false)
+    {
+        trigger_conditions.push(TriggerCondition {
+            source: TriggerSource::Leak,
+            channel: None,
+            above: 0.0,
+            neutral: 0.0,
+        });
+    }
+    let default_trigger = TriggerSettings::default();
+    let datalogger_trigger = TriggerSettings {
+        enable: !trigger_conditions.is_empty(),
+        conditions: trigger_conditions,
+        pre_trigger: matches
+            .get_one::<f64>("datalogger_pre_trigger")
+            .copied()
+            .unwrap_or(default_trigger.pre_trigger),
+        post_trigger: matches
+            .get_one::<f64>("datalogger_post_trigger")
+            .copied()
+            .unwrap_or(default_trigger.post_trigger),
+    };
+
     let datalogger_settings = DataloggerSettings {
+        enable: datalogger_enable,
+        rate: validate_rate(datalogger_rate).unwrap_or(1.0),
         directory: datalogger_directory,
         filename: datalogger_filename,
-        interval: hz_to_us(datalogger_rate),
+        format: datalogger_format,
+        compression: datalogger_compression,
+        compression_level: datalogger_compression_level,
+        trigger: datalogger_trigger,
+        events: matches
+            .get_one::<bool>("datalogger_events")
+            .copied()
+            .unwrap_or(true),
+        timestamps: datalogger_timestamps,
+        columns: datalogger_columns,
+        rotation: RotationSettings {
+            max_size: matches.get_one::<u64>("datalogger_max_file_size").copied(),
+            max_duration: matches
+                .get_one::<u64>("datalogger_max_file_duration")
+                .copied(),
+            on_start: matches
+                .get_one::<bool>("datalogger_rotate_on_start")
+                .copied()
+                .unwrap_or(false),
+        },
+        retention: RetentionSettings {
+            max_files: matches
+                .get_one::<usize>("datalogger_keep_files")
+                .copied()
+                .filter(|&files| files > 0),
+            max_days: matches.get_one::<u32>("datalogger_keep_days").copied(),
+            max_total_size: matches.get_one::<u64>("datalogger_max_total_size").copied(),
+        },
+        min_free_space: matches
+            .get_one::<u64>("datalogger_min_free_space")
+            .copied()
+            .unwrap_or(DEFAULT_MIN_FREE_SPACE),
     };
 
     let monitor_settings = MonitorSettings {
-        interval: hz_to_us(monitor_rate),
+        enable: monitor_enable,
+        rate: validate_rate(monitor_rate).unwrap_or(100.0),
     };
 
     let server_port = matches
@@ -99,18 +371,66 @@ pub fn parse_args() -> (DataloggerSettings, MonitorSettings, ServerSettings) {
         .copied()
         .unwrap_or(8080);
 
-    let server_settings = ServerSettings { port: server_port };
+    let server_settings = ServerSettings {
+        port: server_port,
+        admin_key: matches.get_one::<String>("admin_key").cloned(),
+    };
+
+    let settings_directory = matches
+        .get_one::<String>("settings_directory")
+        .map(|d| d.to_string())
+        .unwrap_or("./".to_string());
 
-    (datalogger_settings, monitor_settings, server_settings)
+    let storage_settings = StorageSettings {
+        directory: settings_directory,
+    };
+
+    let replay_speed = matches
+        .get_one::<f64>("replay_speed")
+        .copied()
+        .unwrap_or(1.0);
+    if !(0.01..=100.0).contains(&replay_speed) {
+        panic!("Error: Invalid replay speed, it must be between 0.01 and 100")
+    }
+
+    let replay_settings = ReplaySettings {
+        file: matches.get_one::<String>("replay_file").cloned(),
+        speed: replay_speed,
+    };
+
+    (
+        datalogger_settings,
+        monitor_settings,
+        server_settings,
+        storage_settings,
+        replay_settings,
+    )
 }
 
-fn hz_to_us(rate_hz: f64) -> u64 {
+fn validate_rate(rate_hz: f64) -> Option<f64> {
     if rate_hz == 0.0 {
-        return 0;
+        return None;
     };
-    if rate_hz > 200.0 {
+    if !(0.0..=200.0).contains(&rate_hz) {
         panic!("Error: Invalid rate used on inputs")
     };
-    let us_per_second = 1_000_000.0;
-    (us_per_second / rate_hz) as u64
+    Some(rate_hz)
+}
+
+/// Columns are named like `vertical_speed` or `VerticalSpeed`
+fn parse_column(name: &str) -> Result<Column, String> {
+    Column::from_str(&name.replace(['_', '-'], ""))
+        .map_err(|_| format!("{name} is not a datalogger column"))
+}
+
+fn parse_timestamp(name: &str) -> Result<Timestamp, String> {
+    Timestamp::from_str(name).map_err(|_| format!("{name} is not a datalogger timestamp"))
+}
+
+fn parse_trigger_time(seconds: &str) -> Result<f64, String> {
+    let seconds: f64 = seconds.parse().map_err(|error| format!("{error}"))?;
+    if !(0.0..=MAX_TRIGGER_TIME).contains(&seconds) {
+        return Err(format!("must be between 0 and {MAX_TRIGGER_TIME} seconds"));
+    }
+    Ok(seconds)
 }
@@ -1,57 +1,1428 @@
-use chrono::{DateTime, Local};
+use crate::event_log::EventRecord;
+use crate::hardware_manager::{DataloggerSettings, OutputStates, PwmChannel, SampleTime};
+use crate::heading::{self, Heading};
+use crate::log_files::{self, LiveSummary};
+use crate::server::protocols::v1::structures::{
+    AnsPackage, InputDeviceType, InputDevices, InputRequest, Operation, Value,
+};
+use crate::vertical_speed;
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use lazy_static::lazy_static;
+use navigator_rs::{AxisData, SensorData};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::fs::OpenOptions;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use strum_macros::{EnumIter, EnumString};
+
+/// Time appended to the names of rotated files, like `data_20240131-235959.csv`
+const STAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+lazy_static! {
+    /// Monotonic times count from here, the start of the datalogger
+    pub static ref SESSION_START: Instant = Instant::now();
+}
+
+/// Compressed blocks are written once they reach this size, in bytes
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Compressed blocks are written at least this often
+const BLOCK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Groups of columns that can be logged, after the time
+#[derive(
+    Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, EnumIter, EnumString,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum Column {
+    Adc,
+    Temperature,
+    Pressure,
+    Accelerometer,
+    Magnetometer,
+    Gyroscope,
+    /// True and magnetic, from the accelerometer and magnetometer
+    Heading,
+    /// From the pressure and accelerometer
+    VerticalSpeed,
+    Leak,
+    PwmEnable,
+    /// Values written to each PWM channel
+    Pwm,
+    UserLeds,
+}
+
+impl Column {
+    /// Columns logged unless others are selected
+    pub fn defaults() -> Vec<Column> {
+        vec![
+            Column::Adc,
+            Column::Temperature,
+            Column::Pressure,
+            Column::Accelerometer,
+            Column::Magnetometer,
+            Column::Gyroscope,
+            Column::Heading,
+            Column::VerticalSpeed,
+        ]
+    }
+
+    /// Columns of the first files, written before they could be selected, after a local time
+    pub fn legacy() -> Vec<Column> {
+        vec![
+            Column::Adc,
+            Column::Temperature,
+            Column::Pressure,
+            Column::Accelerometer,
+            Column::Magnetometer,
+            Column::Gyroscope,
+        ]
+    }
+
+    fn names(&self) -> Vec<String> {
+        let numbered = |prefix: &str, count: usize| {
+            (1..=count)
+                .map(|number| format!("{prefix}{number}"))
+                .collect()
+        };
+        let axes = |prefix: &str| {
+            ["X", "Y", "Z"]
+                .map(|axis| format!("{prefix}_{axis}"))
+                .to_vec()
+        };
+        match self {
+            Column::Adc => numbered("ADC_Ch", 4),
+            Column::Temperature => vec!["Temperature".to_string()],
+            Column::Pressure => vec!["Pressure".to_string()],
+            Column::Accelerometer => axes("Acc"),
+            Column::Magnetometer => axes("Mag"),
+            Column::Gyroscope => axes("Gyro"),
+            Column::Heading => vec!["Heading_True".to_string(), "Heading_Magnetic".to_string()],
+            Column::VerticalSpeed => vec!["Vertical_Speed".to_string()],
+            Column::Leak => vec!["Leak".to_string()],
+            Column::PwmEnable => vec!["PWM_Enable".to_string()],
+            Column::Pwm => numbered("PWM_Ch", 16),
+            Column::UserLeds => numbered("LED", 3),
+        }
+    }
+
+    fn input_type(&self) -> InputDeviceType {
+        match self {
+            Column::Adc => InputDeviceType::Adc,
+            Column::Temperature => InputDeviceType::Temperature,
+            Column::Pressure => InputDeviceType::Pressure,
+            Column::Accelerometer => InputDeviceType::Accelerometer,
+            Column::Magnetometer => InputDeviceType::Magnetometer,
+            Column::Gyroscope => InputDeviceType::Gyroscope,
+            Column::Heading => InputDeviceType::Heading,
+            Column::VerticalSpeed => InputDeviceType::VerticalSpeed,
+            Column::Leak => InputDeviceType::Leak,
+            Column::PwmEnable => InputDeviceType::PwmEnable,
+            Column::Pwm => InputDeviceType::Pwm,
+            Column::UserLeds => InputDeviceType::UserLeds,
+        }
+    }
+}
+
+/// Times that can start each record, all taken when the monitor read the sample
+#[derive(
+    Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, EnumIter, EnumString,
+)]
+#[strum(ascii_case_insensitive)]
+pub enum Timestamp {
+    /// Local time with a one second resolution, like the files written before
+    Local,
+    /// ISO 8601 in UTC with microseconds, like `2024-01-31T23:59:59.123456Z`
+    Utc,
+    /// Seconds since Unix epoch with microseconds
+    Epoch,
+    /// Seconds since the datalogger started with microseconds, unaffected by clock changes
+    Monotonic,
+}
+
+impl Timestamp {
+    pub fn defaults() -> Vec<Timestamp> {
+        vec![Timestamp::Utc, Timestamp::Epoch, Timestamp::Monotonic]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Timestamp::Local => "Time",
+            Timestamp::Utc => "Time_UTC",
+            Timestamp::Epoch => "Time_Epoch",
+            Timestamp::Monotonic => "Time_Monotonic",
+        }
+    }
+
+    fn text(&self, record: &Record) -> String {
+        match self {
+            Timestamp::Local => record
+                .time
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            Timestamp::Utc => record
+                .time
+                .to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            Timestamp::Epoch => format!(
+                "{}.{:06}",
+                record.time.timestamp(),
+                record.time.timestamp_subsec_micros()
+            ),
+            Timestamp::Monotonic => format!(
+                "{}.{:06}",
+                record.monotonic.as_secs(),
+                record.monotonic.subsec_micros()
+            ),
+        }
+    }
+
+    /// Microseconds since Unix epoch, or since the datalogger started for monotonic times,
+    /// of a time written as text
+    pub fn parse(&self, text: &str) -> Option<i64> {
+        let text = text.trim();
+        match self {
+            Timestamp::Local => NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+                .ok()?
+                .and_local_timezone(Local)
+                .earliest()
+                .map(|time| time.timestamp_micros()),
+            Timestamp::Utc => DateTime::parse_from_rfc3339(text)
+                .ok()
+                .map(|time| time.timestamp_micros()),
+            Timestamp::Epoch | Timestamp::Monotonic => {
+                let (seconds, fraction) = text.split_once('.').unwrap_or((text, ""));
+                if !fraction.chars().all(|c| c.is_ascii_digit()) {
+                    return None;
+                }
+                let micros: String = fraction.chars().chain("000000".chars()).take(6).collect();
+                let micros = micros.parse::<i64>().ok()?;
+                let seconds = seconds.parse::<i64>().ok()?.checked_mul(1_000_000)?;
+                if text.starts_with('-') {
+                    seconds.checked_sub(micros)
+                } else {
+                    seconds.checked_add(micros)
+                }
+            }
+        }
+    }
+}
+
+/// Value of a column
+enum Field {
+    Float(f32),
+    Integer(u16),
+    Bool(bool),
+    /// Values that can't be computed yet, like the vertical speed
+    Missing,
+}
+
+impl Field {
+    fn as_f32(&self) -> Option<f32> {
+        match self {
+            Field::Float(value) => Some(*value),
+            Field::Integer(value) => Some(f32::from(*value)),
+            Field::Bool(value) => Some(if *value { 1.0 } else { 0.0 }),
+            Field::Missing => None,
+        }
+    }
+}
+
+/// Names of the columns, starting with the times
+pub fn header_names(timestamps: &[Timestamp], columns: &[Column]) -> Vec<String> {
+    timestamps
+        .iter()
+        .map(|timestamp| timestamp.name().to_string())
+        .chain(columns.iter().flat_map(Column::names))
+        .collect()
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum LogFormat {
+    #[default]
+    Csv,
+    /// One input package per line, like the ones sent to websocket clients
+    JsonLines,
+    /// A header array with the column names, then an array per record
+    MessagePack,
+}
+
+impl LogFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LogFormat::Csv => "csv",
+            LogFormat::JsonLines => "jsonl",
+            LogFormat::MessagePack => "msgpack",
+        }
+    }
+
+    fn writer(&self, timestamps: &[Timestamp], columns: &[Column]) -> Box<dyn RecordWriter> {
+        let timestamps = timestamps.to_vec();
+        let columns = columns.to_vec();
+        match self {
+            LogFormat::Csv => Box::new(CsvWriter {
+                timestamps,
+                columns,
+            }),
+            LogFormat::JsonLines => Box::new(JsonLinesWriter {
+                timestamps,
+                columns,
+            }),
+            LogFormat::MessagePack => Box::new(MessagePackWriter {
+                timestamps,
+                columns,
+            }),
+        }
+    }
+
+    /// Header written at the start of new files, empty for formats without one
+    pub fn header(&self, timestamps: &[Timestamp], columns: &[Column]) -> std::io::Result<Vec<u8>> {
+        self.writer(timestamps, columns).header()
+    }
+}
+
+fn axis(data: &AxisData) -> [f32; 3] {
+    [data.x, data.y, data.z]
+}
+
+/// Compression of the log files. Records are compressed in blocks, each one a complete gzip
+/// member or zstd frame, so every file stays a valid archive and a crash only loses the
+/// block being filled.
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+pub enum LogCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl LogCompression {
+    /// Suffix appended to the format's extension
+    pub fn extension(&self) -> &'static str {
+        match self {
+            LogCompression::None => "",
+            LogCompression::Gzip => ".gz",
+            LogCompression::Zstd => ".zst",
+        }
+    }
+
+    pub fn levels(&self) -> std::ops::RangeInclusive<i32> {
+        match self {
+            LogCompression::None => 0..=0,
+            LogCompression::Gzip => 0..=9,
+            LogCompression::Zstd => 1..=22,
+        }
+    }
+
+    pub fn default_level(&self) -> i32 {
+        match self {
+            LogCompression::None => 0,
+            LogCompression::Gzip => 6,
+            LogCompression::Zstd => 3,
+        }
+    }
+
+    fn compress(&self, level: i32, block: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            LogCompression::None => Ok(block.to_vec()),
+            LogCompression::Gzip => {
+                let level = flate2::Compression::new(level.clamp(0, 9) as u32);
+                let mut encoder = flate2::write::GzEncoder::new(vec![], level);
+                encoder.write_all(block)?;
+                encoder.finish()
+            }
+            LogCompression::Zstd => zstd::bulk::compress(block, level),
+        }
+    }
+}
+
+/// Sample written to the log
+struct Record<'a> {
+    time: DateTime<Utc>,
+    /// Since the datalogger started
+    monotonic: Duration,
+    state: &'a SensorData,
+    heading: Heading,
+    vertical_speed: Option<f32>,
+    outputs: &'a OutputStates,
+}
+
+impl Record<'_> {
+    fn fields(&self, column: Column) -> Vec<Field> {
+        let state = self.state;
+        let floats = |values: &[f32]| values.iter().copied().map(Field::Float).collect();
+        match column {
+            Column::Adc => floats(&state.adc.channel),
+            Column::Temperature => vec![Field::Float(state.temperature)],
+            Column::Pressure => vec![Field::Float(state.pressure)],
+            Column::Accelerometer => floats(&axis(&state.accelerometer)),
+            Column::Magnetometer => floats(&axis(&state.magnetometer)),
+            Column::Gyroscope => floats(&axis(&state.gyro)),
+            Column::Heading => floats(&[self.heading.true_north, self.heading.magnetic_north]),
+            Column::VerticalSpeed => {
+                vec![self.vertical_speed.map_or(Field::Missing, Field::Float)]
+            }
+            Column::Leak => vec![Field::Bool(state.leak)],
+            Column::PwmEnable => vec![Field::Bool(self.outputs.pwm_enable)],
+            Column::Pwm => self
+                .outputs
+                .pwm
+                .iter()
+                .copied()
+                .map(Field::Integer)
+                .collect(),
+            Column::UserLeds => self
+                .outputs
+                .user_leds
+                .iter()
+                .copied()
+                .map(Field::Bool)
+                .collect(),
+        }
+    }
+}
+
+/// Encodes the records of a log file, each format has its own writer
+trait RecordWriter: Send {
+    /// Written at the start of the file
+    fn header(&self) -> std::io::Result<Vec<u8>>;
+    fn record(&self, record: &Record) -> std::io::Result<Vec<u8>>;
+}
+
+struct CsvWriter {
+    timestamps: Vec<Timestamp>,
+    columns: Vec<Column>,
+}
+
+impl RecordWriter for CsvWriter {
+    fn header(&self) -> std::io::Result<Vec<u8>> {
+        let names = header_names(&self.timestamps, &self.columns);
+        Ok(format!("{}\n", names.join(",")).into_bytes())
+    }
+
+    fn record(&self, record: &Record) -> std::io::Result<Vec<u8>> {
+        let times: Vec<String> = self
+            .timestamps
+            .iter()
+            .map(|timestamp| timestamp.text(record))
+            .collect();
+        let mut line = times.join(",");
+        for field in self
+            .columns
+            .iter()
+            .flat_map(|column| record.fields(*column))
+        {
+            if !line.is_empty() {
+                line.push(',');
+            }
+            match field {
+                Field::Float(value) => line.push_str(&value.to_string()),
+                Field::Integer(value) => line.push_str(&value.to_string()),
+                Field::Bool(value) => line.push(if value { '1' } else { '0' }),
+                Field::Missing => {}
+            }
+        }
+        line.push('\n');
+        Ok(line.into_bytes())
+    }
+}
+
+struct JsonLinesWriter {
+    timestamps: Vec<Timestamp>,
+    columns: Vec<Column>,
+}
+
+/// Line of a JSON Lines file, the package followed by the selected times
+#[derive(Serialize)]
+struct JsonLine {
+    #[serde(flatten)]
+    package: AnsPackage,
+    #[serde(flatten)]
+    times: serde_json::Map<String, serde_json::Value>,
+}
+
+impl RecordWriter for JsonLinesWriter {
+    fn header(&self) -> std::io::Result<Vec<u8>> {
+        Ok(vec![])
+    }
+
+    fn record(&self, record: &Record) -> std::io::Result<Vec<u8>> {
+        let time = record.time;
+        // Columns without a value yet are left out, like the sensors in websocket packages
+        let input = self
+            .columns
+            .iter()
+            .filter_map(|column| {
+                let values: Option<Vec<f32>> =
+                    record.fields(*column).iter().map(Field::as_f32).collect();
+                let value = match values? {
+                    values if values.len() == 1 => Value::Single(values[0]),
+                    values => Value::Array(values),
+                };
+                Some(InputDevices::new(column.input_type(), value))
+            })
+            .collect();
+
+        // Packages always carry the UTC and epoch times of the sample
+        let package = AnsPackage::unsent(Operation::Input(InputRequest {
+            timestamp: time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            timestamp_us: time.timestamp_micros(),
+            age_us: 0,
+            input,
+        }));
+        let times = self
+            .timestamps
+            .iter()
+            .map(|timestamp| {
+                let value = match timestamp {
+                    Timestamp::Local | Timestamp::Utc => timestamp.text(record).into(),
+                    Timestamp::Epoch => record.time.timestamp_micros().into(),
+                    Timestamp::Monotonic => (record.monotonic.as_micros() as u64).into(),
+                };
+                (binary_name(*timestamp), value)
+            })
+            .collect();
+        let mut line = serde_json::to_vec(&JsonLine { package, times })?;
+        line.push(b'\n');
+        Ok(line)
+    }
+}
+
+struct MessagePackWriter {
+    timestamps: Vec<Timestamp>,
+    columns: Vec<Column>,
+}
+
+/// Name of a time in MessagePack and JSON Lines records, epoch and monotonic times are integer
+/// microseconds
+fn binary_name(timestamp: Timestamp) -> String {
+    match timestamp {
+        Timestamp::Local | Timestamp::Utc => timestamp.name().to_string(),
+        Timestamp::Epoch | Timestamp::Monotonic => format!("{}_us", timestamp.name()),
+    }
+}
+
+impl RecordWriter for MessagePackWriter {
+    fn header(&self) -> std::io::Result<Vec<u8>> {
+        let names: Vec<String> = self
+            .timestamps
+            .iter()
+            .copied()
+            .map(binary_name)
+            .chain(self.columns.iter().flat_map(Column::names))
+            .collect();
+
+        let mut header = vec![];
+        rmp::encode::write_array_len(&mut header, names.len() as u32)?;
+        for name in names {
+            rmp::encode::write_str(&mut header, &name)?;
+        }
+        Ok(header)
+    }
+
+    fn record(&self, record: &Record) -> std::io::Result<Vec<u8>> {
+        let fields: Vec<Field> = self
+            .columns
+            .iter()
+            .flat_map(|column| record.fields(*column))
+            .collect();
+
+        let mut buffer = Vec::with_capacity(9 * self.timestamps.len() + 5 * fields.len());
+        rmp::encode::write_array_len(&mut buffer, (self.timestamps.len() + fields.len()) as u32)?;
+        for timestamp in &self.timestamps {
+            match timestamp {
+                Timestamp::Local | Timestamp::Utc => {
+                    rmp::encode::write_str(&mut buffer, &timestamp.text(record))?
+                }
+                Timestamp::Epoch => {
+                    rmp::encode::write_sint(&mut buffer, record.time.timestamp_micros())?;
+                }
+                Timestamp::Monotonic => {
+                    rmp::encode::write_uint(&mut buffer, record.monotonic.as_micros() as u64)?;
+                }
+            }
+        }
+        for field in fields {
+            match field {
+                Field::Float(value) => rmp::encode::write_f32(&mut buffer, value)?,
+                Field::Integer(value) => rmp::encode::write_u16(&mut buffer, value)?,
+                Field::Bool(value) => rmp::encode::write_bool(&mut buffer, value)?,
+                Field::Missing => rmp::encode::write_nil(&mut buffer)?,
+            }
+        }
+        Ok(buffer)
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct RotationSettings {
+    /// Starts a new file once the current one reaches this size, in bytes
+    pub max_size: Option<u64>,
+    /// Starts a new file once the current one covers this long, in seconds
+    pub max_duration: Option<u64>,
+    /// Starts a new file each time the service starts
+    #[serde(default)]
+    pub on_start: bool,
+}
+
+impl RotationSettings {
+    /// Rotated files have timestamped names, otherwise a single file is appended to
+    pub fn is_enabled(&self) -> bool {
+        self.max_size.is_some() || self.max_duration.is_some() || self.on_start
+    }
+}
+
+/// Limits over the rotated files, the oldest ones beyond any of them are deleted
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct RetentionSettings {
+    pub max_files: Option<usize>,
+    pub max_days: Option<u32>,
+    /// Total size of the files, in bytes
+    pub max_total_size: Option<u64>,
+}
+
+/// Longest pre-trigger and post-trigger times, in seconds, the pre-trigger samples are kept
+/// in memory
+pub const MAX_TRIGGER_TIME: f64 = 600.0;
+
+/// What a triggered datalogger watches for
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum TriggerSource {
+    /// Depth from the pressure, in meters below the sea level pressure
+    Depth,
+    Leak,
+    /// Value written to a PWM channel
+    Pwm,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TriggerCondition {
+    pub source: TriggerSource,
+    /// Channel of `Pwm` conditions, any channel if `None` or `All`
+    #[serde(default)]
+    pub channel: Option<PwmChannel>,
+    /// The condition is true above this depth or PWM deviation from `neutral`, unused by
+    /// `Leak`
+    #[serde(default)]
+    pub above: f32,
+    /// PWM value a `Pwm` condition measures from, in both directions, so thrusters trigger
+    /// forward and in reverse
+    #[serde(default)]
+    pub neutral: f32,
+}
+
+impl TriggerCondition {
+    fn is_met(&self, sample: &Sample) -> bool {
+        match self.source {
+            TriggerSource::Depth => vertical_speed::depth(sample.state.pressure) > self.above,
+            TriggerSource::Leak => sample.state.leak,
+            TriggerSource::Pwm => {
+                let channel = self.channel.clone().unwrap_or(PwmChannel::All);
+                sample
+                    .outputs
+                    .pwm_values(&channel)
+                    .iter()
+                    .any(|value| (f32::from(*value) - self.neutral).abs() > self.above)
+            }
+        }
+    }
+}
+
+/// Records are only written around the times a condition is true, or a manual trigger is
+/// held, instead of all the time
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct TriggerSettings {
+    pub enable: bool,
+    /// Any of them triggers
+    pub conditions: Vec<TriggerCondition>,
+    /// Seconds of records kept in memory and written first once triggered
+    pub pre_trigger: f64,
+    /// Seconds of records written after the conditions clear
+    pub post_trigger: f64,
+}
+
+impl Default for TriggerSettings {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            conditions: vec![],
+            pre_trigger: 30.0,
+            post_trigger: 30.0,
+        }
+    }
+}
+
+/// What a record is made of
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub time: SampleTime,
+    pub state: SensorData,
+    pub vertical_speed: Option<f32>,
+    pub outputs: OutputStates,
+}
+
+/// Holds the samples and events of a triggered datalogger until they should be written
+#[derive(Default)]
+pub struct Trigger {
+    /// Samples within the pre-trigger time, oldest first
+    samples: VecDeque<Sample>,
+    events: VecDeque<EventRecord>,
+    /// Last time a condition was true, while writing
+    last_active: Option<Instant>,
+}
+
+impl Trigger {
+    /// Records are written until the post-trigger time after the last trigger passes
+    pub fn is_writing(&self) -> bool {
+        self.last_active.is_some()
+    }
+
+    /// Takes a new sample and the events since the last one, returns what should be written
+    /// now, oldest first
+    pub fn update(
+        &mut self,
+        settings: &TriggerSettings,
+        sample: Sample,
+        events: Vec<EventRecord>,
+        manual: bool,
+    ) -> (Vec<Sample>, Vec<EventRecord>) {
+        let now = sample.time.instant;
+        if manual
+            || settings
+                .conditions
+                .iter()
+                .any(|condition| condition.is_met(&sample))
+        {
+            self.last_active = Some(now);
+        }
+        let post_trigger = Duration::from_secs_f64(settings.post_trigger);
+        self.last_active = self
+            .last_active
+            .filter(|last| now.saturating_duration_since(*last) <= post_trigger);
+
+        self.samples.push_back(sample);
+        self.events.extend(events);
+        if self.is_writing() {
+            return (
+                self.samples.drain(..).collect(),
+                self.events.drain(..).collect(),
+            );
+        }
+
+        let pre_trigger = Duration::from_secs_f64(settings.pre_trigger);
+        let expired = |time: &SampleTime| now.saturating_duration_since(time.instant) > pre_trigger;
+        while self
+            .samples
+            .front()
+            .is_some_and(|sample| expired(&sample.time))
+        {
+            self.samples.pop_front();
+        }
+        while self
+            .events
+            .front()
+            .is_some_and(|event| expired(event.time()))
+        {
+            self.events.pop_front();
+        }
+        (vec![], vec![])
+    }
+}
+
+/// A file written by the datalogger
+pub struct LogFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
 
 pub struct DataLogger {
     file: std::fs::File,
+    writer: Box<dyn RecordWriter>,
+    compression: LogCompression,
+    level: i32,
+    /// Records waiting to be compressed
+    block: Vec<u8>,
+    /// Times of the records in the block, summarized once it's written
+    block_times: Vec<Option<i64>>,
+    block_started: Instant,
+    path: PathBuf,
+    /// Bytes in the file, including the ones written before it was opened
+    size: u64,
+    /// Time of the file's first sample
+    started: DateTime<Local>,
+    /// Records have a wall-clock time the file listing can read
+    wall_clock: bool,
+    summary: LiveSummary,
+    /// Opened with the first event
+    events: Option<(std::fs::File, LiveSummary)>,
 }
 
-impl DataLogger {
-    pub fn new(file_name: PathBuf) -> Result<DataLogger, std::io::Error> {
-        let mut options = OpenOptions::new();
-        options.write(true).create(true).append(true);
-
-        let file = if file_name.exists() {
-            let mut file = options.open(file_name)?;
-            writeln!(
-                &mut file,
-                "Time,ADC_Ch1,ADC_Ch2,ADC_Ch3,ADC_Ch4,Temperature,Pressure,Acc_X,Acc_Y,Acc_Z,Mag_X,Mag_Y,Mag_Z,Gyro_X,Gyro_Y,Gyro_Z"
-            )?;
-            file
+/// Splits `data.csv` into `data` and `.csv`, or `data.csv.gz` into `data` and `.csv.gz`
+fn split_name(filename: &str) -> (&str, &str) {
+    let uncompressed = [LogCompression::Gzip, LogCompression::Zstd]
+        .iter()
+        .find_map(|compression| filename.strip_suffix(compression.extension()))
+        .unwrap_or(filename);
+    match uncompressed.rfind('.') {
+        Some(index) if index > 0 => filename.split_at(index),
+        _ => filename.split_at(uncompressed.len()),
+    }
+}
+
+/// Event log of a datalogger file, like `data.events.jsonl` for `data.csv.gz`
+pub fn events_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (stem, _) = split_name(&name);
+    path.with_file_name(format!("{stem}.events.jsonl"))
+}
+
+/// Start time of a rotated file named after `filename`, `None` for any other file
+fn rotated_stamp(filename: &str, name: &str) -> Option<DateTime<Local>> {
+    let (stem, extension) = split_name(filename);
+    let stamp = name
+        .strip_prefix(stem)?
+        .strip_prefix('_')?
+        .strip_suffix(extension)?;
+    parse_stamp(stamp)
+}
+
+fn parse_stamp(stamp: &str) -> Option<DateTime<Local>> {
+    // Files started within the same second are numbered, like `data_20240131-235959-1.csv`
+    let stamp = match stamp.rsplit_once('-') {
+        Some((time, number))
+            if time.contains('-') && number.chars().all(|c| c.is_ascii_digit()) =>
+        {
+            time
+        }
+        _ => stamp,
+    };
+    NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT)
+        .ok()?
+        .and_local_timezone(Local)
+        .earliest()
+}
+
+/// Rotated files named after `filename`, from the oldest to the newest
+pub fn rotated_files(directory: &Path, filename: &str) -> std::io::Result<Vec<LogFile>> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if rotated_stamp(filename, &name.to_string_lossy()).is_none() {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            files.push(LogFile {
+                path: entry.path(),
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+    }
+    files.sort_by(|a, b| (a.modified, &a.path).cmp(&(b.modified, &b.path)));
+    Ok(files)
+}
+
+/// Path for a new rotated file, stamped with the current time
+pub fn new_rotated_file(directory: &Path, filename: &str) -> PathBuf {
+    let (stem, extension) = split_name(filename);
+    let stamp = Local::now().format(STAMP_FORMAT);
+    let mut path = directory.join(format!("{stem}_{stamp}{extension}"));
+    let mut number = 0;
+    while path.exists() {
+        number += 1;
+        path = directory.join(format!("{stem}_{stamp}-{number}{extension}"));
+    }
+    path
+}
+
+/// Deletes the oldest rotated files beyond the retention limits, `current` is always kept.
+/// Returns the deleted files.
+pub fn apply_retention(
+    directory: &Path,
+    filename: &str,
+    current: Option<&Path>,
+    retention: &RetentionSettings,
+) -> std::io::Result<Vec<PathBuf>> {
+    let files = rotated_files(directory, filename)?;
+    let now = SystemTime::now();
+    let max_age = retention
+        .max_days
+        .map(|days| Duration::from_secs(u64::from(days) * 24 * 60 * 60));
+
+    let (mut count, mut total) = (0, 0);
+    if let Some(file) = current.and_then(|current| files.iter().find(|file| file.path == current)) {
+        count += 1;
+        total += file.size;
+    }
+
+    let mut deleted = vec![];
+    for file in files.iter().rev() {
+        if Some(file.path.as_path()) == current {
+            continue;
+        }
+        let age = now.duration_since(file.modified).unwrap_or_default();
+        let expired = retention.max_files.is_some_and(|max| count >= max)
+            || max_age.is_some_and(|max| age > max)
+            || retention
+                .max_total_size
+                .is_some_and(|max| total + file.size > max);
+        if expired {
+            std::fs::remove_file(&file.path)?;
+            deleted.push(file.path.clone());
+            let events = events_path(&file.path);
+            if events.exists() {
+                std::fs::remove_file(&events)?;
+                deleted.push(events);
+            }
         } else {
-            options.open(file_name)?
+            count += 1;
+            total += file.size;
+        }
+    }
+    Ok(deleted)
+}
+
+/// Whether records with the columns of `settings` can be appended to `path`. Files written
+/// before the header was fixed may start without one, they're only appended to with a single
+/// time and the legacy columns.
+pub fn can_append(path: &Path, settings: &DataloggerSettings) -> bool {
+    let Ok(expected) = settings
+        .format
+        .header(&settings.timestamps, &settings.columns)
+    else {
+        return true;
+    };
+    match log_files::header(path) {
+        Ok(Some(header)) if header.is_empty() => {
+            let legacy = match settings.format {
+                LogFormat::MessagePack => Timestamp::Epoch,
+                _ => Timestamp::Local,
+            };
+            expected.is_empty()
+                || (settings.timestamps == [legacy] && settings.columns == Column::legacy())
+        }
+        Ok(Some(header)) => header == expected,
+        Ok(None) | Err(_) => true,
+    }
+}
+
+impl DataLogger {
+    /// Opens `file_name` to append records with `settings`' layout. New files start with the
+    /// format's header.
+    pub fn new(
+        file_name: PathBuf,
+        settings: &DataloggerSettings,
+    ) -> Result<DataLogger, std::io::Error> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file_name)?;
+
+        let writer = settings
+            .format
+            .writer(&settings.timestamps, &settings.columns);
+        let compression = settings.compression;
+        let level = settings
+            .compression_level
+            .unwrap_or(compression.default_level());
+
+        let mut size = file.metadata()?.len();
+        if size == 0 {
+            let header = writer.header()?;
+            // An empty block keeps new files valid archives until their first block is written
+            if !header.is_empty() || compression != LogCompression::None {
+                let header = compression.compress(level, &header)?;
+                file.write_all(&header)?;
+                size = header.len() as u64;
+            }
+        }
+        let path = file_name;
+        // Rotated files resumed after a restart keep counting from their stamp
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let started = split_name(&name)
+            .0
+            .rsplit_once('_')
+            .and_then(|(_, stamp)| parse_stamp(stamp))
+            .unwrap_or_else(Local::now);
+        let wall_clock = settings
+            .timestamps
+            .iter()
+            .any(|timestamp| *timestamp != Timestamp::Monotonic);
+        Ok(DataLogger {
+            file,
+            writer,
+            compression,
+            level,
+            block: vec![],
+            block_times: vec![],
+            block_started: Instant::now(),
+            summary: LiveSummary::open(&path),
+            path,
+            size,
+            started,
+            wall_clock,
+            events: None,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the file reached any of the rotation limits
+    pub fn is_full(&self, rotation: &RotationSettings) -> bool {
+        let age = (Local::now() - self.started).num_seconds();
+        rotation.max_size.is_some_and(|max| self.size >= max)
+            || rotation.max_duration.is_some_and(|max| age >= max as i64)
+    }
+
+    pub fn log_data(&mut self, sample: &Sample) -> Result<(), std::io::Error> {
+        let heading = heading::compute(
+            axis(&sample.state.accelerometer),
+            axis(&sample.state.magnetometer),
+        );
+        let record = Record {
+            time: sample.time.timestamp,
+            monotonic: sample
+                .time
+                .instant
+                .saturating_duration_since(*SESSION_START),
+            state: &sample.state,
+            heading,
+            vertical_speed: sample.vertical_speed,
+            outputs: &sample.outputs,
         };
 
-        Ok(DataLogger { file })
+        let bytes = self.writer.record(&record)?;
+        let time = Some(sample.time.timestamp.timestamp_micros()).filter(|_| self.wall_clock);
+        if self.compression == LogCompression::None {
+            self.file.write_all(&bytes)?;
+            self.size += bytes.len() as u64;
+            self.summary.add(time);
+            return Ok(());
+        }
+        if self.block.is_empty() {
+            self.block_started = Instant::now();
+        }
+        self.block.extend_from_slice(&bytes);
+        self.block_times.push(time);
+        if self.block.len() >= BLOCK_SIZE || self.block_started.elapsed() >= BLOCK_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
     }
 
-    pub fn log_data(
-        &mut self,
-        sensors_data: &navigator_rs::SensorData,
-    ) -> Result<(), std::io::Error> {
-        let current_time: DateTime<Local> = Local::now();
-        let time_str = current_time.format("%Y-%m-%d %H:%M:%S").to_string();
-
-        writeln!(
-            &mut self.file,
-            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
-            time_str,
-            sensors_data.adc.channel[0],
-            sensors_data.adc.channel[1],
-            sensors_data.adc.channel[2],
-            sensors_data.adc.channel[3],
-            sensors_data.temperature,
-            sensors_data.pressure,
-            sensors_data.accelerometer.x,
-            sensors_data.accelerometer.y,
-            sensors_data.accelerometer.z,
-            sensors_data.magnetometer.x,
-            sensors_data.magnetometer.y,
-            sensors_data.magnetometer.z,
-            sensors_data.gyro.x,
-            sensors_data.gyro.y,
-            sensors_data.gyro.z
-        )
+    /// Appends events to the file next to the records, uncompressed as they're few
+    pub fn log_events(&mut self, events: &[EventRecord]) -> Result<(), std::io::Error> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let (file, summary) = match self.events.as_mut() {
+            Some(events) => events,
+            None => {
+                let path = events_path(&self.path);
+                let file = OpenOptions::new().create(true).append(true).open(&path)?;
+                self.events.insert((file, LiveSummary::open(&path)))
+            }
+        };
+        let mut lines = vec![];
+        for event in events {
+            lines.extend(event.line()?);
+        }
+        file.write_all(&lines)?;
+        for event in events {
+            summary.add(Some(event.time().timestamp.timestamp_micros()));
+        }
+        Ok(())
+    }
+
+    /// Compresses and writes the pending records
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let compressed = self.compression.compress(self.level, &self.block)?;
+        self.block.clear();
+        let times = std::mem::take(&mut self.block_times);
+        self.file.write_all(&compressed)?;
+        self.size += compressed.len() as u64;
+        for time in times {
+            self.summary.add(time);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DataLogger {
+    fn drop(&mut self) {
+        if let Err(error) = self.flush() {
+            log::error!(
+                "Datalogger: Failed to write the last block of {}: {error}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event_log::Event;
+    use std::io::Read;
+
+    #[test]
+    fn timestamp_parse_rejects_overflow() {
+        let epoch = Timestamp::Epoch;
+        assert_eq!(epoch.parse("1700000000.5"), Some(1_700_000_000_500_000));
+        assert_eq!(epoch.parse("-0.25"), Some(-250_000));
+        // Microseconds written in a seconds column
+        assert_eq!(epoch.parse("1700000000000000"), None);
+        assert_eq!(epoch.parse("1.-5"), None);
+        assert_eq!(Timestamp::Monotonic.parse("12.000001"), Some(12_000_001));
+    }
+
+    /// Writes `size` bytes to `name`, last modified `age` seconds ago
+    fn write_file(directory: &Path, name: &str, size: usize, age: u64) -> PathBuf {
+        let path = directory.join(name);
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_len(size as u64).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(age))
+            .unwrap();
+        path
+    }
+
+    fn test_settings(directory: &Path, rotation: RotationSettings) -> DataloggerSettings {
+        let mut settings: DataloggerSettings = serde_json::from_value(serde_json::json!({
+            "enable": true,
+            "rate": 10.0,
+            "directory": directory,
+            "filename": "data",
+        }))
+        .unwrap();
+        settings.rotation = rotation;
+        settings
+    }
+
+    fn sample(instant: Instant) -> Sample {
+        Sample {
+            time: SampleTime {
+                timestamp: Utc::now(),
+                instant,
+            },
+            state: SensorData::default(),
+            vertical_speed: None,
+            outputs: OutputStates::default(),
+        }
+    }
+
+    #[test]
+    fn rotated_names() {
+        assert_eq!(split_name("data.csv"), ("data", ".csv"));
+        assert_eq!(split_name("data.csv.zst"), ("data", ".csv.zst"));
+        assert_eq!(split_name("data"), ("data", ""));
+        assert_eq!(
+            events_path(Path::new("logs/data_20240131-235959.csv.gz")),
+            Path::new("logs/data_20240131-235959.events.jsonl")
+        );
+
+        assert!(rotated_stamp("data.csv", "data_20240131-235959.csv").is_some());
+        assert!(rotated_stamp("data.csv", "data_20240131-235959-12.csv").is_some());
+        assert!(rotated_stamp("data.csv", "data_20240131-235959.jsonl").is_none());
+        assert!(rotated_stamp("data.csv", "other_20240131-235959.csv").is_none());
+        assert!(rotated_stamp("data.csv", "data.csv").is_none());
+
+        // Files started within the same second are numbered
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let first = new_rotated_file(directory, "data.csv");
+        std::fs::File::create(&first).unwrap();
+        let second = new_rotated_file(directory, "data.csv");
+        assert_ne!(first, second);
+        let name = second.file_name().unwrap().to_string_lossy();
+        assert!(name.ends_with("-1.csv"), "{name}");
+        assert!(rotated_stamp("data.csv", &name).is_some());
+    }
+
+    #[test]
+    fn retention_deletes_the_oldest_files() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let oldest = write_file(directory, "data_20240101-000000.csv", 100, 400);
+        let old_events = write_file(directory, "data_20240101-000000.events.jsonl", 10, 400);
+        let old = write_file(directory, "data_20240102-000000.csv", 100, 300);
+        let recent = write_file(directory, "data_20240103-000000.csv", 100, 200);
+        let current = write_file(directory, "data_20240104-000000.csv", 100, 100);
+        let unrelated = write_file(directory, "notes.csv", 100, 500);
+
+        let by_count = RetentionSettings {
+            max_files: Some(3),
+            ..Default::default()
+        };
+        let deleted = apply_retention(directory, "data.csv", Some(&current), &by_count).unwrap();
+        assert_eq!(deleted, vec![oldest, old_events]);
+
+        // The current file counts towards the size and is kept even beyond it
+        let by_size = RetentionSettings {
+            max_total_size: Some(200),
+            ..Default::default()
+        };
+        let deleted = apply_retention(directory, "data.csv", Some(&current), &by_size).unwrap();
+        assert_eq!(deleted, vec![old]);
+        let tiny = RetentionSettings {
+            max_total_size: Some(1),
+            ..Default::default()
+        };
+        let deleted = apply_retention(directory, "data.csv", Some(&current), &tiny).unwrap();
+        assert_eq!(deleted, vec![recent]);
+        assert!(current.exists() && unrelated.exists());
+    }
+
+    #[test]
+    fn rotates_at_the_size_limit() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let path = directory.join("data_20240101-000000.csv");
+        let settings = test_settings(directory, RotationSettings::default());
+        let mut logger = DataLogger::new(path.clone(), &settings).unwrap();
+        logger.log_data(&sample(Instant::now())).unwrap();
+        let size = std::fs::metadata(&path).unwrap().len();
+        assert!(size > 0);
+
+        let rotation = |max_size| RotationSettings {
+            max_size: Some(max_size),
+            ..Default::default()
+        };
+        assert!(!logger.is_full(&rotation(size + 1)));
+        assert!(logger.is_full(&rotation(size)));
+        // Resumed files keep their size and their start from the stamp
+        drop(logger);
+        let resumed = DataLogger::new(path, &settings).unwrap();
+        assert!(resumed.is_full(&rotation(size)));
+        assert!(resumed.is_full(&RotationSettings {
+            max_duration: Some(3600),
+            ..Default::default()
+        }));
+        assert!(!RotationSettings::default().is_enabled());
+    }
+
+    #[test]
+    fn json_lines_carry_the_selected_times() {
+        let temporary = tempfile::tempdir().unwrap();
+        let path = temporary.path().join("data.jsonl");
+        let mut settings = test_settings(temporary.path(), RotationSettings::default());
+        settings.format = LogFormat::JsonLines;
+        settings.timestamps = vec![Timestamp::Local, Timestamp::Monotonic];
+        let mut logger = DataLogger::new(path.clone(), &settings).unwrap();
+        let sample = sample(*SESSION_START + Duration::from_micros(1_500_000));
+        logger.log_data(&sample).unwrap();
+        drop(logger);
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(content.trim()).unwrap();
+        assert_eq!(line["Time_Monotonic_us"], 1_500_000);
+        let local = sample.time.timestamp.with_timezone(&Local);
+        assert_eq!(line["Time"], local.format("%Y-%m-%d %H:%M:%S").to_string());
+        assert!(line.get("Time_UTC").is_none() && line.get("Time_Epoch_us").is_none());
+        // Along with the package's own times
+        assert_eq!(
+            line["timestamp_us"],
+            sample.time.timestamp.timestamp_micros()
+        );
+    }
+
+    #[test]
+    fn compressed_records_are_listed_once_written() {
+        for compression in [LogCompression::Gzip, LogCompression::Zstd] {
+            let temporary = tempfile::tempdir().unwrap();
+            let directory = temporary.path();
+            let mut settings = test_settings(directory, RotationSettings::default());
+            settings.compression = compression;
+            let path = directory.join(settings.log_filename());
+            let rows = || log_files::list(directory, Some(&path)).unwrap()[0].rows;
+
+            let mut logger = DataLogger::new(path.clone(), &settings).unwrap();
+            for index in 0..3 {
+                let mut sample = sample(Instant::now());
+                sample.state.pressure = index as f32;
+                logger.log_data(&sample).unwrap();
+            }
+            // Still in the block, the file has only the header
+            assert_eq!(rows(), 0);
+            logger.flush().unwrap();
+            assert_eq!(rows(), 3);
+            let file = &log_files::list(directory, Some(&path)).unwrap()[0];
+            assert!(file.start.is_some() && file.end.is_some());
+            drop(logger);
+
+            let mut content = String::new();
+            log_files::csv_reader(&path)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            let lines: Vec<&str> = content.lines().collect();
+            let header = header_names(&settings.timestamps, &settings.columns).join(",");
+            assert_eq!(lines.len(), 4);
+            assert_eq!(lines[0], header);
+            let pressure = header
+                .split(',')
+                .position(|name| name == "Pressure")
+                .unwrap();
+            for (line, index) in lines[1..].iter().zip(0..) {
+                assert_eq!(
+                    line.split(',').nth(pressure),
+                    Some(index.to_string().as_str())
+                );
+            }
+            // Closed files are summarized the same way
+            assert_eq!(log_files::list(directory, None).unwrap()[0].rows, 3);
+        }
+    }
+
+    #[test]
+    fn appends_to_files_without_a_header() {
+        let temporary = tempfile::tempdir().unwrap();
+        let path = temporary.path().join("data.csv");
+        let line = format!("2024-01-31 23:59:59{}\n", ",0".repeat(15));
+        std::fs::write(&path, &line).unwrap();
+
+        let mut settings = test_settings(temporary.path(), RotationSettings::default());
+        assert!(!can_append(&path, &settings));
+        settings.timestamps = vec![Timestamp::Local];
+        assert!(!can_append(&path, &settings));
+        settings.columns = Column::legacy();
+        assert!(can_append(&path, &settings));
+
+        let mut logger = DataLogger::new(path.clone(), &settings).unwrap();
+        logger.log_data(&sample(Instant::now())).unwrap();
+        drop(logger);
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], line.trim_end());
+        assert_eq!(lines[1].split(',').count(), 16);
+    }
+
+    fn trigger_settings(conditions: Vec<TriggerCondition>) -> TriggerSettings {
+        TriggerSettings {
+            enable: true,
+            conditions,
+            pre_trigger: 1.0,
+            post_trigger: 0.5,
+        }
+    }
+
+    /// Instants of the samples written, in tenths of seconds from `start`
+    fn tenths(start: Instant, samples: &[Sample]) -> Vec<u64> {
+        samples
+            .iter()
+            .map(|sample| (sample.time.instant - start).as_millis() as u64 / 100)
+            .collect()
+    }
+
+    #[test]
+    fn trigger_writes_the_pre_buffer_first() {
+        let settings = trigger_settings(vec![]);
+        let start = Instant::now();
+        let at = |tenths: u64| start + Duration::from_millis(100 * tenths);
+        let mut trigger = Trigger::default();
+
+        let event =
+            |tenths| EventRecord::new(sample(at(tenths)).time, Event::PwmEnable { enable: true });
+        for tenths in 0..30 {
+            let events = if tenths % 10 == 0 {
+                vec![event(tenths)]
+            } else {
+                vec![]
+            };
+            let (samples, events) = trigger.update(&settings, sample(at(tenths)), events, false);
+            assert!(samples.is_empty() && events.is_empty());
+        }
+        assert!(!trigger.is_writing());
+
+        // Held for a sample, with the last second buffered before it
+        let (samples, events) = trigger.update(&settings, sample(at(30)), vec![], true);
+        assert_eq!(tenths(start, &samples), (19..=30).collect::<Vec<_>>());
+        let events: Vec<Instant> = events.iter().map(|event| event.time().instant).collect();
+        assert_eq!(events, vec![at(20)]);
+
+        // Then written until the post-trigger time passes
+        for tenths in 31..=35 {
+            let (samples, _) = trigger.update(&settings, sample(at(tenths)), vec![], false);
+            assert_eq!(samples.len(), 1);
+        }
+        let (samples, _) = trigger.update(&settings, sample(at(36)), vec![], false);
+        assert!(samples.is_empty());
+        assert!(!trigger.is_writing());
+    }
+
+    #[test]
+    fn trigger_without_pre_buffer() {
+        let settings = TriggerSettings {
+            pre_trigger: 0.0,
+            post_trigger: 0.0,
+            ..trigger_settings(vec![])
+        };
+        let start = Instant::now();
+        let mut trigger = Trigger::default();
+        let (samples, events) = trigger.update(&settings, sample(start), vec![], true);
+        assert_eq!((samples.len(), events.len()), (1, 0));
+
+        let later = start + Duration::from_millis(100);
+        for instant in [later, later + Duration::from_millis(100)] {
+            let (samples, _) = trigger.update(&settings, sample(instant), vec![], false);
+            assert!(samples.is_empty());
+        }
+        assert_eq!(trigger.samples.len(), 1);
+    }
+
+    #[test]
+    fn trigger_conditions_at_their_threshold() {
+        let pwm = |channel: Option<PwmChannel>| TriggerCondition {
+            source: TriggerSource::Pwm,
+            channel,
+            above: 100.0,
+            neutral: 1500.0,
+        };
+        let with_pwm = |ch1: u16, ch2: u16| {
+            let mut sample = sample(Instant::now());
+            sample.outputs.pwm[0] = ch1;
+            sample.outputs.pwm[1] = ch2;
+            sample
+        };
+
+        let ch2 = pwm(Some(PwmChannel::Ch2));
+        assert!(!ch2.is_met(&with_pwm(1500, 1600)));
+        assert!(ch2.is_met(&with_pwm(1500, 1601)));
+        // Reverse thrust triggers as well
+        assert!(!ch2.is_met(&with_pwm(1500, 1400)));
+        assert!(ch2.is_met(&with_pwm(1500, 1399)));
+        assert!(!ch2.is_met(&with_pwm(1900, 1500)));
+        assert!(pwm(None).is_met(&with_pwm(1900, 1500)));
+        assert!(pwm(Some(PwmChannel::All)).is_met(&with_pwm(1500, 1399)));
+
+        let depth = TriggerCondition {
+            source: TriggerSource::Depth,
+            channel: None,
+            above: 2.0,
+            neutral: 0.0,
+        };
+        let mut deep = sample(Instant::now());
+        deep.state.pressure = 101.325 + 3.0 * 1025.0 * 9.80665 / 1000.0;
+        assert!(depth.is_met(&deep));
+        deep.state.pressure = 101.325 + 1.0 * 1025.0 * 9.80665 / 1000.0;
+        assert!(!depth.is_met(&deep));
+
+        let leak = TriggerCondition {
+            source: TriggerSource::Leak,
+            ..depth
+        };
+        deep.state.leak = true;
+        assert!(leak.is_met(&deep));
     }
 }
@@ -0,0 +1,216 @@
+use crate::data_logger::SESSION_START;
+use crate::hardware_manager::{PwmChannel, SampleTime, UserLed};
+use lazy_static::lazy_static;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Events kept while the datalogger can't write them, like on low disk space
+const MAX_PENDING: usize = 10000;
+
+/// Conditions that need attention, logged when they start and end
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+pub enum Alarm {
+    Leak,
+    /// The datalogger is paused below its minimum free space
+    LowDiskSpace,
+}
+
+impl std::fmt::Display for Alarm {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Alarm::Leak => write!(f, "Leak detected"),
+            Alarm::LowDiskSpace => write!(f, "Datalogger is low on disk space"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub enum Event {
+    /// Value written to a channel, `All` when every channel got the same one
+    PwmValue {
+        channel: PwmChannel,
+        value: u16,
+    },
+    /// Frequency in Hz, as set by the prescaler
+    PwmFrequency {
+        frequency: f32,
+    },
+    PwmEnable {
+        enable: bool,
+    },
+    UserLed {
+        led: UserLed,
+        state: bool,
+    },
+    NeoPixel {
+        rgb: Vec<[u8; 3]>,
+    },
+    Arming {
+        armed: bool,
+    },
+    Alarm {
+        alarm: Alarm,
+        active: bool,
+    },
+    /// Websocket clients, by their address
+    ClientConnected {
+        client: String,
+    },
+    ClientDisconnected {
+        client: String,
+    },
+}
+
+/// Event with the time it happened
+#[derive(Debug, Clone)]
+pub struct EventRecord {
+    time: SampleTime,
+    event: Event,
+}
+
+impl EventRecord {
+    #[cfg(test)]
+    pub fn new(time: SampleTime, event: Event) -> Self {
+        Self { time, event }
+    }
+
+    pub fn time(&self) -> &SampleTime {
+        &self.time
+    }
+
+    /// JSON line with the same times as the datalogger's records
+    pub fn line(&self) -> serde_json::Result<Vec<u8>> {
+        #[derive(Serialize)]
+        struct Line<'a> {
+            timestamp: String,
+            timestamp_us: i64,
+            /// Since the datalogger started
+            monotonic_us: u64,
+            event: &'a Event,
+        }
+
+        let monotonic = self.time.instant.saturating_duration_since(*SESSION_START);
+        let mut line = serde_json::to_vec(&Line {
+            timestamp: self
+                .time
+                .timestamp
+                .to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+            timestamp_us: self.time.timestamp.timestamp_micros(),
+            monotonic_us: monotonic.as_micros() as u64,
+            event: &self.event,
+        })?;
+        line.push(b'\n');
+        Ok(line)
+    }
+}
+
+/// Events are only kept while the datalogger writes them
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Events waiting for the datalogger, the oldest are dropped beyond `MAX_PENDING`
+#[derive(Default)]
+struct Queue {
+    events: VecDeque<EventRecord>,
+    /// Events dropped since the queue last overflowed, warned about once per overflow
+    dropped: usize,
+}
+
+impl Queue {
+    fn push(&mut self, record: EventRecord) {
+        if self.events.len() >= MAX_PENDING {
+            if self.dropped == 0 {
+                log::warn!("Event log: Too many pending events, dropping the oldest");
+            }
+            self.dropped += 1;
+            self.events.pop_front();
+        }
+        self.events.push_back(record);
+    }
+
+    /// Events queued since the last call, oldest first, and how many were dropped
+    fn take(&mut self) -> (Vec<EventRecord>, usize) {
+        let dropped = std::mem::take(&mut self.dropped);
+        (self.events.drain(..).collect(), dropped)
+    }
+}
+
+lazy_static! {
+    static ref PENDING: Mutex<Queue> = Default::default();
+}
+
+/// Pending events are dropped when disabled
+pub fn set_enabled(enabled: bool) {
+    let was_enabled = ENABLED.swap(enabled, Ordering::Relaxed);
+    if was_enabled && !enabled {
+        if let Ok(mut pending) = PENDING.lock() {
+            *pending = Queue::default();
+        }
+    }
+}
+
+/// Queues an event for the datalogger, timed now
+pub fn record(event: Event) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Ok(mut pending) = PENDING.lock() {
+        pending.push(EventRecord {
+            time: SampleTime::now(),
+            event,
+        });
+    }
+}
+
+/// Events queued since the last call, oldest first
+pub fn take() -> Vec<EventRecord> {
+    let (events, dropped) = PENDING
+        .lock()
+        .map(|mut pending| pending.take())
+        .unwrap_or_default();
+    if dropped > 0 {
+        log::warn!("Event log: Dropped {dropped} events");
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(value: u16) -> EventRecord {
+        EventRecord::new(
+            SampleTime::now(),
+            Event::PwmValue {
+                channel: PwmChannel::Ch1,
+                value,
+            },
+        )
+    }
+
+    fn value(record: &EventRecord) -> u16 {
+        match record.event {
+            Event::PwmValue { value, .. } => value,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn full_queue_drops_the_oldest_events() {
+        let mut queue = Queue::default();
+        for index in 0..MAX_PENDING + 5 {
+            queue.push(event(index as u16));
+        }
+        let (events, dropped) = queue.take();
+        assert_eq!((events.len(), dropped), (MAX_PENDING, 5));
+        assert_eq!(value(&events[0]), 5);
+        assert_eq!(value(events.last().unwrap()), (MAX_PENDING + 4) as u16);
+
+        // The count starts over after each take
+        queue.push(event(0));
+        let (events, dropped) = queue.take();
+        assert_eq!((events.len(), dropped), (1, 0));
+        assert_eq!(queue.take().0.len(), 0);
+    }
+}
@@ -0,0 +1,412 @@
+use crate::hardware_manager::{PwmChannel, UserLed};
+use crate::settings;
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+const SETTINGS_NAME: &str = "gamepad";
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Source {
+    /// Analog axis, between -1 and 1
+    Axis,
+    Button,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// Pulse width around the neutral, by the input's value
+    Pwm,
+    /// On while the input's value is above half
+    UserLed,
+    /// Color scaled by the input's value
+    NeoPixel,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct Mapping {
+    pub source: Source,
+    /// Axis or button index, as in the browser's Gamepad API
+    pub index: usize,
+    pub target: Target,
+    /// Channel of `Pwm` targets
+    #[serde(default)]
+    pub channel: Option<PwmChannel>,
+    /// LED of `UserLed` targets
+    #[serde(default)]
+    pub led: Option<UserLed>,
+    /// Red, green and blue at full scale of `NeoPixel` targets
+    #[serde(default)]
+    pub color: Option<Vec<u8>>,
+    /// Axis values below it are ignored, the remaining travel is rescaled
+    #[serde(default)]
+    pub deadzone: f32,
+    /// Blend between a linear (0) and a cubic (1) response
+    #[serde(default)]
+    pub expo: f32,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub invert: bool,
+    /// Buttons flip the output on each press instead of holding it
+    #[serde(default)]
+    pub toggle: bool,
+    /// Pulse width of `Pwm` targets at rest, in microseconds
+    #[serde(default = "default_neutral")]
+    pub neutral: f32,
+    /// Pulse width variation of `Pwm` targets at full scale, in microseconds
+    #[serde(default = "default_range")]
+    pub range: f32,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn default_neutral() -> f32 {
+    1500.0
+}
+
+fn default_range() -> f32 {
+    400.0
+}
+
+impl Mapping {
+    fn check(&self) -> Result<(), String> {
+        match self.target {
+            Target::Pwm if self.channel.is_none() => Err("Pwm targets require a channel"),
+            Target::UserLed if self.led.is_none() => Err("UserLed targets require a led"),
+            Target::NeoPixel if self.color.as_ref().is_none_or(|color| color.len() != 3) => {
+                Err("NeoPixel targets require a red, green and blue color")
+            }
+            _ if !(0.0..1.0).contains(&self.deadzone) => Err("Deadzone must be in [0, 1)"),
+            _ if !(0.0..=1.0).contains(&self.expo) => Err("Expo must be in [0, 1]"),
+            _ => Ok(()),
+        }
+        .map_err(|error| format!("{:?} {}: {error}", self.source, self.index))
+    }
+
+    /// Shapes an axis value, between -1 and 1
+    fn shape(&self, value: f32) -> f32 {
+        let value = value.clamp(-1.0, 1.0);
+        let magnitude = ((value.abs() - self.deadzone) / (1.0 - self.deadzone)).max(0.0);
+        let value = value.signum() * magnitude;
+        let value = (1.0 - self.expo) * value + self.expo * value.powi(3);
+        let value = if self.invert { -value } else { value };
+        (value * self.scale).clamp(-1.0, 1.0)
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub mappings: Vec<Mapping>,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GamepadSettings {
+    /// Name of the profile in use, none is used if empty
+    pub active: String,
+    pub profiles: Vec<Profile>,
+}
+
+impl GamepadSettings {
+    pub fn check(&self) -> Result<(), String> {
+        if !self.active.is_empty() && self.profile().is_none() {
+            return Err(format!("Profile {} does not exist", self.active));
+        }
+        for profile in &self.profiles {
+            for mapping in &profile.mappings {
+                mapping
+                    .check()
+                    .map_err(|error| format!("Profile {}: {error}", profile.name))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn profile(&self) -> Option<&Profile> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.name == self.active)
+    }
+}
+
+/// Output change requested by a mapping
+pub enum Command {
+    /// Pulse width in microseconds
+    Pwm(PwmChannel, f32),
+    UserLed(UserLed, bool),
+    NeoPixel([u8; 3]),
+}
+
+/// Command of a mapping whose value changed, passed back to `applied` once written
+pub struct Change {
+    mapping: usize,
+    value: f32,
+    pub command: Command,
+}
+
+#[derive(Default)]
+struct Gamepad {
+    settings: GamepadSettings,
+    /// Previous state of each mapping's button, to detect presses
+    pressed: Vec<bool>,
+    /// Latched state of each mapping's toggle
+    toggled: Vec<bool>,
+    /// Last value applied by each mapping, so only changes reach the outputs
+    last: Vec<Option<f32>>,
+}
+
+impl Gamepad {
+    fn new(settings: GamepadSettings) -> Self {
+        let count = settings
+            .profile()
+            .map(|profile| profile.mappings.len())
+            .unwrap_or_default();
+        Self {
+            settings,
+            pressed: vec![false; count],
+            toggled: vec![false; count],
+            last: vec![None; count],
+        }
+    }
+
+    fn update(&mut self, axes: &[f32], buttons: &[bool]) -> Vec<Change> {
+        let Some(profile) = self.settings.profile() else {
+            return vec![];
+        };
+
+        let mut changes = vec![];
+        for (index, mapping) in profile.mappings.iter().enumerate() {
+            let value = match mapping.source {
+                Source::Axis => match axes.get(mapping.index) {
+                    Some(value) => mapping.shape(*value),
+                    None => continue,
+                },
+                Source::Button => {
+                    let Some(&pressed) = buttons.get(mapping.index) else {
+                        continue;
+                    };
+                    let state = if mapping.toggle {
+                        if pressed && !self.pressed[index] {
+                            self.toggled[index] = !self.toggled[index];
+                        }
+                        self.toggled[index]
+                    } else {
+                        pressed
+                    };
+                    self.pressed[index] = pressed;
+                    let value = if state { mapping.scale } else { 0.0 };
+                    if mapping.invert {
+                        -value
+                    } else {
+                        value
+                    }
+                }
+            };
+
+            if self.last[index] == Some(value) {
+                continue;
+            }
+
+            let command = match mapping.target {
+                Target::Pwm => mapping
+                    .channel
+                    .clone()
+                    .map(|channel| Command::Pwm(channel, mapping.neutral + value * mapping.range)),
+                Target::UserLed => mapping
+                    .led
+                    .clone()
+                    .map(|led| Command::UserLed(led, value > 0.5)),
+                Target::NeoPixel => mapping.color.as_ref().map(|color| {
+                    let brightness = value.abs().min(1.0);
+                    Command::NeoPixel(
+                        [0, 1, 2].map(|channel| (color[channel] as f32 * brightness).round() as u8),
+                    )
+                }),
+            };
+            changes.extend(command.map(|command| Change {
+                mapping: index,
+                value,
+                command,
+            }));
+        }
+        changes
+    }
+
+    fn applied(&mut self, change: &Change) {
+        if let Some(last) = self.last.get_mut(change.mapping) {
+            *last = Some(change.value);
+        }
+    }
+}
+
+lazy_static! {
+    static ref GAMEPAD: Mutex<Gamepad> = Default::default();
+}
+
+/// Loads the saved profiles
+pub fn init() {
+    let Some(settings) = settings::load::<GamepadSettings>(SETTINGS_NAME) else {
+        return;
+    };
+    if let Err(error) = settings.check() {
+        log::warn!("Gamepad: ignoring the saved settings: {error}");
+        return;
+    }
+    log::info!("Gamepad: using profile {:?}", settings.active);
+    if let Ok(mut gamepad) = GAMEPAD.lock() {
+        *gamepad = Gamepad::new(settings);
+    }
+}
+
+/// Maps the gamepad's state with the active profile, returning the outputs that changed
+/// since they were last applied
+pub fn update(axes: &[f32], buttons: &[bool]) -> Vec<Change> {
+    match GAMEPAD.lock() {
+        Ok(mut gamepad) => gamepad.update(axes, buttons),
+        Err(error) => {
+            log::error!("Gamepad: {error}");
+            vec![]
+        }
+    }
+}
+
+/// Marks a change as written, failed ones are sent again on the next update
+pub fn applied(change: &Change) {
+    if let Ok(mut gamepad) = GAMEPAD.lock() {
+        gamepad.applied(change);
+    }
+}
+
+pub fn settings() -> GamepadSettings {
+    GAMEPAD
+        .lock()
+        .map(|gamepad| gamepad.settings.clone())
+        .unwrap_or_default()
+}
+
+/// Applies and saves the profiles, restarting toggles and the outputs' tracking
+pub fn set_settings(settings: GamepadSettings) -> std::io::Result<()> {
+    settings::save(SETTINGS_NAME, &settings)?;
+    if let Ok(mut gamepad) = GAMEPAD.lock() {
+        *gamepad = Gamepad::new(settings);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn mapping(value: serde_json::Value) -> Mapping {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn gamepad(mappings: Vec<Mapping>) -> Gamepad {
+        Gamepad::new(GamepadSettings {
+            active: "test".to_string(),
+            profiles: vec![Profile {
+                name: "test".to_string(),
+                mappings,
+            }],
+        })
+    }
+
+    /// Applies every change, returning the PWM pulses and LED states requested
+    fn apply(gamepad: &mut Gamepad, axes: &[f32], buttons: &[bool]) -> Vec<String> {
+        let changes = gamepad.update(axes, buttons);
+        changes.iter().for_each(|change| gamepad.applied(change));
+        changes
+            .into_iter()
+            .map(|change| match change.command {
+                Command::Pwm(channel, pulse) => format!("{channel:?} {pulse}"),
+                Command::UserLed(led, state) => format!("{led:?} {state}"),
+                Command::NeoPixel(rgb) => format!("{rgb:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shapes_axes() {
+        let linear = mapping(
+            json!({ "source": "Axis", "index": 0, "target": "Pwm", "channel": "Ch1", "deadzone": 0.2 }),
+        );
+        assert_eq!(linear.shape(0.1), 0.0);
+        assert_eq!(linear.shape(-0.2), 0.0);
+        assert!((linear.shape(0.6) - 0.5).abs() < 1e-6);
+        assert_eq!(linear.shape(-3.0), -1.0);
+
+        let shaped = mapping(json!({
+            "source": "Axis", "index": 0, "target": "Pwm", "channel": "Ch1",
+            "expo": 1.0, "scale": 4.0, "invert": true
+        }));
+        assert_eq!(shaped.shape(0.5), -0.5);
+        assert_eq!(shaped.shape(-1.0), 1.0);
+    }
+
+    #[test]
+    fn sends_only_changes() {
+        let mut gamepad = gamepad(vec![mapping(
+            json!({ "source": "Axis", "index": 1, "target": "Pwm", "channel": "Ch2" }),
+        )]);
+        assert_eq!(apply(&mut gamepad, &[0.0, 0.5], &[]), vec!["Ch2 1700"]);
+        assert!(apply(&mut gamepad, &[0.0, 0.5], &[]).is_empty());
+        // Missing axes leave the output as it was
+        assert!(apply(&mut gamepad, &[0.0], &[]).is_empty());
+
+        // Changes that weren't applied are sent again
+        assert_eq!(gamepad.update(&[0.0, -1.0], &[]).len(), 1);
+        assert_eq!(apply(&mut gamepad, &[0.0, -1.0], &[]), vec!["Ch2 1100"]);
+    }
+
+    #[test]
+    fn toggles_on_presses() {
+        let mut gamepad = gamepad(vec![
+            mapping(
+                json!({ "source": "Button", "index": 0, "target": "UserLed", "led": "Led1", "toggle": true }),
+            ),
+            mapping(
+                json!({ "source": "Button", "index": 1, "target": "NeoPixel", "color": [255, 100, 0], "scale": 0.5 }),
+            ),
+        ]);
+        assert_eq!(
+            apply(&mut gamepad, &[], &[false, false]),
+            vec!["Led1 false", "[0, 0, 0]"]
+        );
+        assert_eq!(
+            apply(&mut gamepad, &[], &[true, true]),
+            vec!["Led1 true", "[128, 50, 0]"]
+        );
+        // Held, then released: the toggle stays on and the held button goes off
+        assert!(apply(&mut gamepad, &[], &[true, true]).is_empty());
+        assert_eq!(apply(&mut gamepad, &[], &[false, false]), vec!["[0, 0, 0]"]);
+        assert_eq!(apply(&mut gamepad, &[], &[true, false]), vec!["Led1 false"]);
+    }
+
+    #[test]
+    fn checks_profiles() {
+        let mut settings = GamepadSettings {
+            active: "missing".to_string(),
+            profiles: vec![],
+        };
+        assert!(settings.check().is_err());
+        // No active profile maps nothing
+        settings.active.clear();
+        assert!(settings.check().is_ok());
+        assert!(Gamepad::new(settings).update(&[1.0], &[true]).is_empty());
+
+        let invalid = [
+            json!({ "source": "Axis", "index": 0, "target": "Pwm" }),
+            json!({ "source": "Axis", "index": 0, "target": "NeoPixel", "color": [1, 2] }),
+            json!({ "source": "Axis", "index": 0, "target": "Pwm", "channel": "Ch1", "deadzone": 1.0 }),
+            json!({ "source": "Axis", "index": 0, "target": "UserLed", "led": "Led1", "expo": -0.1 }),
+        ];
+        for value in invalid {
+            assert!(mapping(value).check().is_err());
+        }
+    }
+}
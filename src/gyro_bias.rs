@@ -0,0 +1,248 @@
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct GyroBiasSettings {
+    /// Updates the bias automatically while stationary
+    pub enable: bool,
+    /// Period used to evaluate the variances, in seconds
+    pub window: f64,
+    /// Maximum accelerometer magnitude variance to be considered stationary, (m/s2)^2
+    pub accel_variance_threshold: f32,
+    /// Maximum gyroscope variance, summed over the axes, to be considered stationary, (rad/s)^2
+    pub gyro_variance_threshold: f32,
+    /// Time constant of the bias update while stationary, in seconds
+    pub time_constant: f64,
+}
+
+impl Default for GyroBiasSettings {
+    fn default() -> Self {
+        Self {
+            enable: true,
+            window: 1.0,
+            accel_variance_threshold: 0.005,
+            gyro_variance_threshold: 0.0001,
+            time_constant: 10.0,
+        }
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct GyroBiasState {
+    /// Bias subtracted from the gyroscope outputs, rad/s
+    pub bias: Vec<f32>,
+    pub stationary: bool,
+    pub settings: GyroBiasSettings,
+}
+
+struct Sample {
+    instant: Instant,
+    accel_magnitude: f32,
+    gyro: [f32; 3],
+}
+
+#[derive(Default)]
+struct GyroBiasEstimator {
+    settings: GyroBiasSettings,
+    samples: VecDeque<Sample>,
+    last_update: Option<Instant>,
+    bias: [f32; 3],
+    stationary: bool,
+}
+
+lazy_static! {
+    static ref ESTIMATOR: Mutex<GyroBiasEstimator> = Default::default();
+}
+
+fn mean_variance(values: impl Iterator<Item = f32> + Clone) -> (f32, f32) {
+    let count = values.clone().count().max(1) as f32;
+    let mean = values.clone().sum::<f32>() / count;
+    let variance = values.map(|value| (value - mean).powi(2)).sum::<f32>() / count;
+    (mean, variance)
+}
+
+impl GyroBiasEstimator {
+    fn gyro_mean(&self) -> Option<[f32; 3]> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut mean = [0.0; 3];
+        for (axis, value) in mean.iter_mut().enumerate() {
+            *value = mean_variance(self.samples.iter().map(|sample| sample.gyro[axis])).0;
+        }
+        Some(mean)
+    }
+
+    fn update(&mut self, accel: [f32; 3], gyro: [f32; 3], instant: Instant) {
+        let window = Duration::from_secs_f64(self.settings.window);
+        self.samples.push_back(Sample {
+            instant,
+            accel_magnitude: accel.iter().map(|value| value.powi(2)).sum::<f32>().sqrt(),
+            gyro,
+        });
+        while self
+            .samples
+            .front()
+            .is_some_and(|sample| instant.duration_since(sample.instant) > window)
+        {
+            self.samples.pop_front();
+        }
+
+        let elapsed = self
+            .last_update
+            .map(|last| instant.duration_since(last).as_secs_f64())
+            .unwrap_or(0.0);
+        self.last_update = Some(instant);
+
+        // Variances are only meaningful once the window is filled
+        let filled = self
+            .samples
+            .front()
+            .is_some_and(|sample| instant.duration_since(sample.instant) >= window.mul_f64(0.9));
+        if !filled {
+            self.stationary = false;
+            return;
+        }
+
+        let (_, accel_variance) =
+            mean_variance(self.samples.iter().map(|sample| sample.accel_magnitude));
+        let gyro_variance: f32 = (0..3)
+            .map(|axis| mean_variance(self.samples.iter().map(|sample| sample.gyro[axis])).1)
+            .sum();
+
+        self.stationary = accel_variance < self.settings.accel_variance_threshold
+            && gyro_variance < self.settings.gyro_variance_threshold;
+
+        if !(self.settings.enable && self.stationary) {
+            return;
+        }
+
+        if let Some(mean) = self.gyro_mean() {
+            let alpha = (elapsed / (self.settings.time_constant + elapsed)) as f32;
+            for (bias, mean) in self.bias.iter_mut().zip(mean) {
+                *bias += alpha * (mean - *bias);
+            }
+        }
+    }
+}
+
+/// Feeds a raw monitor sample to the stationary detection and bias estimation
+pub fn update(accel: [f32; 3], gyro: [f32; 3], instant: Instant) {
+    match ESTIMATOR.lock() {
+        Ok(mut estimator) => estimator.update(accel, gyro, instant),
+        Err(error) => log::error!("Gyro bias: {error}"),
+    }
+}
+
+/// Removes the estimated bias from a raw gyroscope reading
+pub fn correct(gyro: [f32; 3]) -> [f32; 3] {
+    let bias = ESTIMATOR
+        .lock()
+        .map(|estimator| estimator.bias)
+        .unwrap_or_default();
+    [gyro[0] - bias[0], gyro[1] - bias[1], gyro[2] - bias[2]]
+}
+
+/// Takes the mean of the current window as bias, regardless of the stationary state
+pub fn capture() -> Option<[f32; 3]> {
+    let mut estimator = ESTIMATOR.lock().ok()?;
+    let mean = estimator.gyro_mean()?;
+    estimator.bias = mean;
+    Some(mean)
+}
+
+pub fn state() -> GyroBiasState {
+    ESTIMATOR
+        .lock()
+        .map(|estimator| GyroBiasState {
+            bias: estimator.bias.to_vec(),
+            stationary: estimator.stationary,
+            settings: estimator.settings.clone(),
+        })
+        .unwrap_or_else(|_| GyroBiasState {
+            bias: vec![0.0; 3],
+            stationary: false,
+            settings: GyroBiasSettings::default(),
+        })
+}
+
+pub fn set_settings(settings: GyroBiasSettings) {
+    if let Ok(mut estimator) = ESTIMATOR.lock() {
+        estimator.settings = settings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRAVITY: [f32; 3] = [0.0, 0.0, 9.81];
+
+    /// Feeds `seconds` of samples at 100 Hz, the gyroscope given by the sample's index
+    fn feed(
+        estimator: &mut GyroBiasEstimator,
+        start: Instant,
+        seconds: u64,
+        gyro: impl Fn(u64) -> [f32; 3],
+    ) {
+        for n in 0..seconds * 100 {
+            let instant = start + Duration::from_millis(10 * n);
+            estimator.update(GRAVITY, gyro(n), instant);
+        }
+    }
+
+    #[test]
+    fn converges_while_stationary() {
+        let mut estimator = GyroBiasEstimator::default();
+        feed(&mut estimator, Instant::now(), 60, |_| [0.01, -0.02, 0.005]);
+
+        assert!(estimator.stationary);
+        // Five time constants after the window is filled
+        for (bias, expected) in estimator.bias.iter().zip([0.01, -0.02, 0.005]) {
+            assert!((bias - expected).abs() < 0.01 * expected.abs(), "{bias}");
+        }
+    }
+
+    #[test]
+    fn keeps_bias_while_moving_or_disabled() {
+        let mut moving = GyroBiasEstimator::default();
+        feed(&mut moving, Instant::now(), 5, |n| {
+            [if n % 2 == 0 { 0.5 } else { -0.5 }, 0.0, 0.0]
+        });
+        assert!(!moving.stationary);
+        assert_eq!(moving.bias, [0.0; 3]);
+
+        let mut disabled = GyroBiasEstimator {
+            settings: GyroBiasSettings {
+                enable: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        feed(&mut disabled, Instant::now(), 5, |_| [0.01, 0.0, 0.0]);
+        assert!(disabled.stationary);
+        assert_eq!(disabled.bias, [0.0; 3]);
+    }
+
+    #[test]
+    fn waits_for_a_filled_window() {
+        let mut estimator = GyroBiasEstimator::default();
+        assert_eq!(estimator.gyro_mean(), None);
+
+        let start = Instant::now();
+        estimator.update(GRAVITY, [0.01; 3], start);
+        estimator.update(GRAVITY, [0.01; 3], start + Duration::from_millis(500));
+        assert!(!estimator.stationary);
+        assert_eq!(estimator.bias, [0.0; 3]);
+        assert_eq!(estimator.gyro_mean(), Some([0.01; 3]));
+
+        // Samples older than the window are dropped
+        estimator.update(GRAVITY, [0.03; 3], start + Duration::from_millis(1400));
+        assert_eq!(estimator.samples.len(), 2);
+        assert!((estimator.gyro_mean().unwrap()[0] - 0.02).abs() < 1e-6);
+    }
+}
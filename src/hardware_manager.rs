@@ -1,30 +1,124 @@
-use crate::data_logger::DataLogger;
-use crate::server::protocols::v1::packages;
+use crate::data_logger::{
+    self, Column, DataLogger, LogCompression, LogFormat, RetentionSettings, RotationSettings,
+    Sample, Timestamp, Trigger, TriggerSettings,
+};
+use crate::event_log::{self, Alarm, Event};
+use crate::heading::{self, Heading};
+use crate::lease::{self, Access};
+use crate::server::protocols::v1::{errors::Error, packages};
+use crate::vibration::{self, VibrationAnalysis};
+use crate::{gyro_bias, mounting, replay, settings, vertical_speed};
+use chrono::{DateTime, Utc};
 use lazy_static::lazy_static;
 use paperclip::actix::Apiv2Schema;
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::convert::From;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex, RwLock};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
 use std::thread;
+use std::time::Instant;
+use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 #[derive(Default)]
 struct NavigationManager {
-    navigator: navigator_rs::Navigator,
-    monitor: Option<std::thread::JoinHandle<()>>,
-    datalogger: Option<std::thread::JoinHandle<()>>,
+    navigator: Option<navigator_rs::Navigator>,
+    initialized: bool,
+    available: HashSet<Device>,
+    monitor: Option<Worker<MonitorSettings>>,
+    datalogger: Option<Worker<DataloggerSettings>>,
+    recovery: Option<std::thread::JoinHandle<()>>,
+    /// PWM frequency in Hz, `None` while at the navigator's default
+    pwm_frequency: Option<f32>,
+    /// Channels follow the writes only while armed, otherwise they hold their safe values
+    armed: bool,
+    arming: ArmingSettings,
+    outputs: OutputStates,
+}
+
+/// Outputs as last written
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputStates {
+    pub pwm_enable: bool,
+    /// Value of each channel, from `Ch1` to `Ch16`
+    pub pwm: [u16; 16],
+    /// State of each LED, from `Led1` to `Led3`
+    pub user_leds: [bool; 3],
+}
+
+/// Setters write the changes to the event log
+impl OutputStates {
+    /// Values of `channel`, or of every channel for `All`
+    pub fn pwm_values(&self, channel: &PwmChannel) -> &[u16] {
+        match PwmChannel::iter().position(|other| other == *channel) {
+            Some(index) if index < self.pwm.len() => &self.pwm[index..=index],
+            _ => &self.pwm,
+        }
+    }
+
+    fn set_pwm(&mut self, channel: &PwmChannel, value: u16) {
+        let changed = match PwmChannel::iter().position(|other| other == *channel) {
+            Some(index) if index < self.pwm.len() => {
+                std::mem::replace(&mut self.pwm[index], value) != value
+            }
+            _ => std::mem::replace(&mut self.pwm, [value; 16]) != [value; 16],
+        };
+        if changed {
+            event_log::record(Event::PwmValue {
+                channel: channel.clone(),
+                value,
+            });
+        }
+    }
+
+    fn set_pwm_enable(&mut self, enable: bool) {
+        if std::mem::replace(&mut self.pwm_enable, enable) != enable {
+            event_log::record(Event::PwmEnable { enable });
+        }
+    }
+
+    fn set_user_led(&mut self, led: &UserLed, state: bool) {
+        if let Some(index) = UserLed::iter().position(|other| other == *led) {
+            if std::mem::replace(&mut self.user_leds[index], state) != state {
+                event_log::record(Event::UserLed {
+                    led: led.clone(),
+                    state,
+                });
+            }
+        }
+    }
+}
+
+/// A background thread that receives its new settings through a command channel
+struct Worker<S> {
+    handle: std::thread::JoinHandle<()>,
+    sender: Sender<S>,
+    settings: S,
+}
+
+impl<S: Clone> Worker<S> {
+    fn configure(&mut self, settings: S) -> Result<(), Error> {
+        if self.handle.is_finished() {
+            return Err(Error::Internal("Worker is not running".to_string()));
+        }
+        self.sender
+            .send(settings.clone())
+            .map_err(|error| Error::Internal(format!("Worker is not running: {error}")))?;
+        self.settings = settings;
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Default, Copy)]
 struct Data {
     state: navigator_rs::SensorData,
-}
-
-macro_rules! with_navigator {
-    () => {
-        NavigationManager::get_instance().lock().unwrap().navigator
-    };
+    /// Wall-clock and monotonic time of the sample, `None` until the monitor runs
+    sample_time: Option<SampleTime>,
+    /// Vertical speed in m/s, positive up, `None` until estimated
+    vertical_speed: Option<f32>,
 }
 
 macro_rules! impl_from_enum {
@@ -49,74 +143,485 @@ lazy_static! {
     static ref DATA: Arc<RwLock<Data>> = Default::default();
 }
 
+lazy_static! {
+    static ref DATALOGGER_STATUS: RwLock<DataloggerStatus> = Default::default();
+}
+
+/// PWM oscillator of the navigator, in Hz
+const PWM_CLOCK: f32 = 24_576_000.0;
+
+/// PWM frequency set by the navigator's initialization, with a prescale of 100
+const DEFAULT_PWM_FREQUENCY: f32 = PWM_CLOCK / (4096.0 * 101.0);
+
+/// Interval between checks of the datalogger's free disk space and retention limits
+const DISK_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Interval between initialization attempts of the devices marked as unavailable
+const RECOVERY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
 impl NavigationManager {
     pub fn get_instance() -> &'static Mutex<Self> {
         &NAVIGATOR
     }
 
-    pub fn init_monitor(refresh_interval: u64) {
-        NavigationManager::get_instance().lock().unwrap().monitor = Some(
-            thread::Builder::new()
-                .name("Monitor".into())
-                .spawn(move || NavigationManager::monitor(refresh_interval))
-                .expect("Error: Navigator service: Monitor can't setup thread"),
-        )
+    fn lock() -> Result<MutexGuard<'static, Self>, Error> {
+        Self::get_instance()
+            .lock()
+            .map_err(|error| Error::Internal(format!("Navigator manager is poisoned: {error}")))
     }
 
-    pub fn init_datalogger(refresh_interval: u64, file_path: PathBuf) {
-        NavigationManager::get_instance().lock().unwrap().datalogger = Some(
-            thread::Builder::new()
-                .name("Datalogger".into())
-                .spawn(move || NavigationManager::data_logger(refresh_interval, file_path))
-                .expect("Error: Navigator service: Datalogger can't setup thread"),
-        )
+    /// Runs `operation` over the navigator if `device` is available.
+    /// Any panic raised by the navigator's driver is caught and the device is marked as unavailable,
+    /// so it can be recovered later by the recovery thread.
+    fn with_device<T>(
+        device: Device,
+        operation: impl FnOnce(&mut navigator_rs::Navigator) -> T,
+    ) -> Result<T, Error> {
+        Self::lock()?.run(device, operation)
     }
 
-    fn monitor(refresh_interval_us: u64) {
-        log::info!("Monitor: Started");
+    /// Same as `with_device`, for callers already holding the manager
+    fn run<T>(
+        &mut self,
+        device: Device,
+        operation: impl FnOnce(&mut navigator_rs::Navigator) -> T,
+    ) -> Result<T, Error> {
+        if !self.available.contains(&device) {
+            return Err(Error::HardwareUnavailable(format!(
+                "{device:?} is not available"
+            )));
+        }
+
+        let Some(navigator) = self.navigator.as_mut() else {
+            return Err(Error::HardwareUnavailable(format!(
+                "{device:?} is not available"
+            )));
+        };
+
+        match panic::catch_unwind(AssertUnwindSafe(|| operation(navigator))) {
+            Ok(value) => Ok(value),
+            Err(_) => {
+                log::error!("{device:?}: operation failed, marking it as unavailable");
+                self.available.remove(&device);
+                Err(Error::HardwareUnavailable(format!(
+                    "{device:?} failed to respond"
+                )))
+            }
+        }
+    }
+
+    /// Safe value of `channel`, or of every channel for `All`
+    fn safe_values(&self, channel: &PwmChannel) -> Vec<(PwmChannel, u16)> {
+        if *channel != PwmChannel::All {
+            return vec![(channel.clone(), self.arming.safe_value(channel))];
+        }
+        PwmChannel::iter()
+            .filter(|channel| *channel != PwmChannel::All)
+            .map(|channel| {
+                let value = self.arming.safe_value(&channel);
+                (channel, value)
+            })
+            .collect()
+    }
+
+    /// Values the channels get for a write of `value` to `channel`, their safe ones while disarmed
+    fn pwm_values(&self, channel: &PwmChannel, value: u16) -> Vec<(PwmChannel, u16)> {
+        if self.armed {
+            return vec![(channel.clone(), value)];
+        }
+        self.safe_values(channel)
+    }
+
+    /// Writes the values to their channels, returning the value written, or the default safe
+    /// value when each channel got its own
+    fn write_pwm(&mut self, values: &[(PwmChannel, u16)]) -> Result<u16, Error> {
+        for (channel, value) in values {
+            self.run(Device::Pwm, |nav| {
+                nav.set_pwm_channel_value(channel.clone().into(), *value)
+            })?;
+            self.outputs.set_pwm(channel, *value);
+        }
+        Ok(match values {
+            [(_, value)] => *value,
+            _ => self.arming.safe_value,
+        })
+    }
+
+    /// Writes the safe value of `channel`, or of every channel for `All`, returning the value written
+    fn hold_safe_value(&mut self, channel: &PwmChannel) -> Result<u16, Error> {
+        let values = self.safe_values(channel);
+        self.write_pwm(&values)
+    }
+
+    /// Writes `value` to `channel`, or its safe value while disarmed, returning the value written
+    fn set_pwm(&mut self, channel: &PwmChannel, value: u16) -> Result<u16, Error> {
+        let values = self.pwm_values(channel, value);
+        self.write_pwm(&values)
+    }
+
+    fn set_pwm_enable(&mut self, state: bool) -> Result<(), Error> {
+        if state && !self.armed {
+            return Err(Error::BadRequest(
+                "PWM can't be enabled while disarmed".to_string(),
+            ));
+        }
+        self.run(Device::Pwm, |nav| nav.set_pwm_enable(state))?;
+        self.outputs.set_pwm_enable(state);
+        Ok(())
+    }
+
+    /// Alarms active now, for callers already holding the manager
+    fn alarms(&mut self) -> Vec<Alarm> {
+        let mut alarms = vec![];
+        if self
+            .run(Device::LeakSensor, |nav| nav.read_leak())
+            .unwrap_or(false)
+        {
+            alarms.push(Alarm::Leak);
+        }
+        if datalogger_status().low_disk_space {
+            alarms.push(Alarm::LowDiskSpace);
+        }
+        alarms
+    }
+
+    /// Reasons to refuse arming, none if it's safe: missing devices and any active alarm
+    fn pre_arm_checks(&mut self) -> Vec<String> {
+        let mut failed = vec![];
+        if !self.available.contains(&Device::Pwm) {
+            failed.push("PWM is not available".to_string());
+        }
+        if !self.available.contains(&Device::LeakSensor) {
+            failed.push("Leak sensor is not available".to_string());
+        }
+        failed.extend(self.alarms().iter().map(Alarm::to_string));
+        failed
+    }
+
+    fn arm(&mut self) -> Result<(), Error> {
+        let failed = self.pre_arm_checks();
+        if !failed.is_empty() {
+            return Err(Error::BadRequest(format!(
+                "Pre-arm checks failed: {}",
+                failed.join(", ")
+            )));
+        }
+        if !self.armed {
+            event_log::record(Event::Arming { armed: true });
+        }
+        self.armed = true;
+        self.show_armed(true);
+        log::info!("Arming: armed");
+        Ok(())
+    }
+
+    /// Turns the arming LED on or off, if there is one
+    fn show_armed(&mut self, armed: bool) {
+        if let Some(led) = self.arming.led.clone() {
+            match self.run(Device::UserLed, |nav| {
+                nav.set_led(led.clone().into(), armed)
+            }) {
+                Ok(()) => self.outputs.set_user_led(&led, armed),
+                Err(error) => log::warn!("Arming: can't show the state on the LED: {error}"),
+            }
+        }
+    }
+
+    /// Creates the navigator if needed and probes every device that is not yet available.
+    fn init_devices(&mut self) {
+        if self.navigator.is_none() {
+            self.navigator = panic::catch_unwind(navigator_rs::Navigator::new).ok();
+        }
+
+        let Some(navigator) = self.navigator.as_mut() else {
+            log::error!("Navigator: failed to create the navigator");
+            return;
+        };
+
+        if !self.initialized {
+            self.initialized = panic::catch_unwind(AssertUnwindSafe(|| navigator.init())).is_ok();
+            self.pwm_frequency = None;
+            if !self.initialized {
+                log::error!("Navigator: initialization failed, probing devices individually");
+            }
+        }
+
+        for device in Device::iter() {
+            if self.available.contains(&device) {
+                continue;
+            }
+
+            let probe = panic::catch_unwind(AssertUnwindSafe(|| match device {
+                Device::Adc => {
+                    navigator.read_adc_all();
+                }
+                Device::Barometer => {
+                    navigator.read_pressure();
+                }
+                Device::Imu => {
+                    navigator.read_accel();
+                }
+                Device::Magnetometer => {
+                    navigator.read_mag();
+                }
+                Device::LeakSensor => {
+                    navigator.read_leak();
+                }
+                Device::UserLed => {
+                    navigator.get_led(navigator_rs::UserLed::Led1);
+                }
+                // Output only devices, there is nothing to probe without changing their state
+                Device::NeoPixel | Device::Pwm => {}
+            }));
+
+            match probe {
+                Ok(_) => {
+                    log::info!("{device:?}: available");
+                    self.available.insert(device);
+                }
+                Err(_) => log::warn!("{device:?}: unavailable"),
+            }
+        }
+    }
+
+    /// Accelerometer in the vehicle frame
+    fn read_accel() -> Result<[f32; 3], Error> {
+        Self::with_device(Device::Imu, |nav| nav.read_accel())
+            .map(|accel| mounting::rotate(to_array(accel)))
+    }
+
+    /// Gyroscope in the vehicle frame, before the bias removal
+    fn read_raw_gyro() -> Result<[f32; 3], Error> {
+        Self::with_device(Device::Imu, |nav| nav.read_gyro())
+            .map(|gyro| mounting::rotate(to_array(gyro)))
+    }
+
+    /// Magnetometer in the vehicle frame
+    fn read_mag() -> Result<[f32; 3], Error> {
+        Self::with_device(Device::Magnetometer, |nav| nav.read_mag())
+            .map(|mag| mounting::rotate(to_array(mag)))
+    }
+
+    pub fn init_monitor(settings: MonitorSettings) -> Result<(), Error> {
+        let (sender, receiver) = mpsc::channel();
+        let thread_settings = settings.clone();
+        let handle = thread::Builder::new()
+            .name("Monitor".into())
+            .spawn(move || NavigationManager::monitor(receiver, thread_settings))
+            .map_err(|error| Error::Internal(format!("Monitor can't setup thread: {error}")))?;
+        Self::lock()?.monitor = Some(Worker {
+            handle,
+            sender,
+            settings,
+        });
+        Ok(())
+    }
+
+    pub fn init_datalogger(settings: DataloggerSettings) -> Result<(), Error> {
+        let (sender, receiver) = mpsc::channel();
+        let thread_settings = settings.clone();
+        let handle = thread::Builder::new()
+            .name("Datalogger".into())
+            .spawn(move || NavigationManager::data_logger(receiver, thread_settings))
+            .map_err(|error| Error::Internal(format!("Datalogger can't setup thread: {error}")))?;
+        Self::lock()?.datalogger = Some(Worker {
+            handle,
+            sender,
+            settings,
+        });
+        Ok(())
+    }
+
+    pub fn init_recovery() -> Result<(), Error> {
+        let handle = thread::Builder::new()
+            .name("Recovery".into())
+            .spawn(NavigationManager::recovery)
+            .map_err(|error| Error::Internal(format!("Recovery can't setup thread: {error}")))?;
+        Self::lock()?.recovery = Some(handle);
+        Ok(())
+    }
+
+    fn recovery() {
+        log::info!("Recovery: Started");
         loop {
-            let time_start = std::time::Instant::now();
+            thread::sleep(RECOVERY_INTERVAL);
 
-            let mut lock = Self::get_instance().lock().unwrap();
+            match Self::lock() {
+                Ok(mut manager) => {
+                    if manager.available.len() < Device::iter().count() {
+                        manager.init_devices();
+                    }
+                }
+                Err(error) => log::error!("Recovery: {error}"),
+            }
+        }
+    }
 
-            let reading = navigator_rs::SensorData {
-                adc: {
-                    if refresh_interval_us < 10000 {
-                        navigator_rs::ADCData { channel: [0.0; 4] }
-                    } else {
-                        lock.navigator.read_adc_all()
+    /// Polls the available devices, unavailable ones keep their value from `reading`.
+    /// Returns the new sample and the vibration analysis it completed, if any.
+    fn read_devices(
+        mut reading: navigator_rs::SensorData,
+        read_adc: bool,
+    ) -> (Data, Option<VibrationAnalysis>) {
+        if read_adc {
+            if let Ok(adc) = Self::with_device(Device::Adc, |nav| nav.read_adc_all()) {
+                reading.adc = adc;
+            }
+        }
+        if let Ok(temperature) = Self::with_device(Device::Barometer, |nav| nav.read_temperature())
+        {
+            reading.temperature = temperature;
+        }
+        let pressure = Self::with_device(Device::Barometer, |nav| nav.read_pressure());
+        if let Ok(pressure) = pressure {
+            reading.pressure = pressure;
+        }
+        let accelerometer = Self::read_accel();
+        if let Ok(accelerometer) = accelerometer {
+            reading.accelerometer = from_array(accelerometer);
+        }
+        if let Ok(magnetometer) = Self::read_mag() {
+            reading.magnetometer = from_array(magnetometer);
+        }
+        let gyro = Self::read_raw_gyro();
+        if let Ok(leak) = Self::with_device(Device::LeakSensor, |nav| nav.read_leak()) {
+            if leak != reading.leak {
+                event_log::record(Event::Alarm {
+                    alarm: Alarm::Leak,
+                    active: leak,
+                });
+            }
+            reading.leak = leak;
+        }
+
+        let sample_time = SampleTime::now();
+
+        // Raw samples feed the bias estimation, the gyroscope is stored with its bias removed
+        if let (Ok(accelerometer), Ok(gyro)) = (&accelerometer, &gyro) {
+            gyro_bias::update(*accelerometer, *gyro, sample_time.instant);
+        }
+        let gyro = gyro.map(gyro_bias::correct);
+        if let Ok(gyro) = gyro {
+            reading.gyro = from_array(gyro);
+        }
+
+        let vertical_speed = pressure.ok().and_then(|pressure| {
+            let accelerometer = accelerometer.as_ref().ok().copied();
+            vertical_speed::update(pressure, accelerometer, sample_time.instant)
+        });
+
+        // Only fresh IMU samples are used on the vibration analysis
+        let analysis = match (accelerometer, gyro) {
+            (Ok(accelerometer), Ok(gyro)) => {
+                vibration::push(accelerometer, gyro, sample_time.instant)
+            }
+            _ => None,
+        };
+
+        let sample = Data {
+            state: reading,
+            sample_time: Some(sample_time),
+            vertical_speed,
+        };
+        (sample, analysis)
+    }
+
+    fn monitor(receiver: Receiver<MonitorSettings>, mut settings: MonitorSettings) {
+        log::info!("Monitor: Started");
+        loop {
+            if !settings.enable {
+                log::info!("Monitor: Paused");
+                match receiver.recv() {
+                    Ok(new_settings) => {
+                        settings = new_settings;
+                        continue;
                     }
-                },
-                temperature: lock.navigator.read_temperature(),
-                pressure: lock.navigator.read_pressure(),
-                accelerometer: lock.navigator.read_accel(),
-                magnetometer: lock.navigator.read_mag(),
-                gyro: lock.navigator.read_gyro(),
-                leak: lock.navigator.read_leak(),
+                    Err(_) => break,
+                }
+            }
+
+            let refresh_interval_us = settings.interval().as_micros() as u64;
+            let time_start = std::time::Instant::now();
+
+            // Unavailable devices keep their last known value
+            let reading = match DATA.read() {
+                Ok(data) => data.state,
+                Err(error) => {
+                    log::error!("Monitor: {error}");
+                    break;
+                }
             };
 
-            drop(lock);
+            // A replayed file replaces the navigator's readings
+            let (sample, analysis) = match replay::sample() {
+                Some(sample) => (
+                    Data {
+                        state: sample.state,
+                        sample_time: Some(SampleTime::now()),
+                        vertical_speed: sample.vertical_speed,
+                    },
+                    None,
+                ),
+                None => Self::read_devices(reading, refresh_interval_us >= 10000),
+            };
 
-            DATA.write().unwrap().state = reading;
+            match DATA.write() {
+                Ok(mut data) => *data = sample,
+                Err(error) => {
+                    log::error!("Monitor: {error}");
+                    break;
+                }
+            }
 
             let time_elapsed = time_start.elapsed().as_micros() as u64;
 
             NavigationManager::websocket_broadcast();
 
-            if time_elapsed > (refresh_interval_us * 2) {
+            if let Some(analysis) = analysis {
+                let _package = packages::vibration_package(analysis);
+            }
+
+            let wait = if time_elapsed > (refresh_interval_us * 2) {
                 log::info!("Monitor: Something went wrong, measurements not concluded with reading interval {refresh_interval_us} us, time elapsed: {time_elapsed} us");
-                continue;
+                0
+            } else {
+                refresh_interval_us.saturating_sub(time_elapsed)
+            };
+
+            // Waits for the next reading while listening for new settings
+            match receiver.recv_timeout(std::time::Duration::from_micros(wait)) {
+                Ok(new_settings) => settings = new_settings,
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
             }
+        }
+        log::info!("Monitor: Stopped");
+    }
 
-            if refresh_interval_us > time_elapsed {
-                let wait = refresh_interval_us.wrapping_sub(time_elapsed);
-                thread::sleep(std::time::Duration::from_micros(wait));
+    /// Disables the datalogger from its own thread, in the shared settings too, and tells the
+    /// clients why
+    fn stop_data_logger(settings: &mut DataloggerSettings, error: String) {
+        log::error!("Datalogger: {error}");
+        settings.enable = false;
+        match Self::lock() {
+            Ok(mut manager) => {
+                if let Some(worker) = manager.datalogger.as_mut() {
+                    worker.settings.enable = false;
+                }
             }
+            Err(error) => log::error!("Datalogger: {error}"),
         }
+        set_datalogger_status(|status| status.error = Some(error));
+        let _package = packages::datalogger_package(datalogger_status());
     }
 
-    fn data_logger(refresh_interval: u64, file_path: PathBuf) {
-        let mut logger = DataLogger::new(file_path).expect("Failed to create/open CSV file");
+    fn data_logger(receiver: Receiver<DataloggerSettings>, mut settings: DataloggerSettings) {
+        let mut logger: Option<DataLogger> = None;
+        // The last rotated file is resumed, unless a new one is due on start or after a limit
+        let mut rotate = settings.rotation.on_start;
+        let mut disk_check: Option<Instant> = None;
+        let mut trigger = Trigger::default();
+        lazy_static::initialize(&data_logger::SESSION_START);
 
         // Just let monitor run before
         thread::sleep(std::time::Duration::from_millis(500));
@@ -124,18 +629,244 @@ impl NavigationManager {
         log::info!("Datalogger started");
 
         loop {
-            let reading = DATA.read().unwrap().state;
+            event_log::set_enabled(settings.enable && settings.events);
+            if !settings.enable {
+                logger = None;
+                trigger = Trigger::default();
+                set_datalogger_status(|status| {
+                    status.file = None;
+                    status.triggered = false;
+                });
+                log::info!("Datalogger: Paused");
+                match receiver.recv() {
+                    Ok(new_settings) => {
+                        settings = new_settings;
+                        disk_check = None;
+                        continue;
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            if disk_check.is_none_or(|time| time.elapsed() >= DISK_CHECK_INTERVAL) {
+                disk_check = Some(Instant::now());
+                Self::check_disk(&settings, logger.as_ref().map(DataLogger::path));
+            }
+
+            // Records carry the time the monitor read the sample
+            let data = match DATA.read() {
+                Ok(data) => *data,
+                Err(error) => {
+                    log::error!("Datalogger: {error}");
+                    break;
+                }
+            };
+            let sample = Sample {
+                time: data.sample_time.unwrap_or_else(SampleTime::now),
+                state: data.state,
+                vertical_speed: data.vertical_speed,
+                outputs: output_states().unwrap_or_default(),
+            };
+
+            // Triggered logging holds the samples and events back until a trigger
+            let (samples, events) = if settings.trigger.enable {
+                let was_writing = trigger.is_writing();
+                let manual = datalogger_status().manual_trigger;
+                let pending = trigger.update(&settings.trigger, sample, event_log::take(), manual);
+                if trigger.is_writing() != was_writing {
+                    let writing = trigger.is_writing();
+                    set_datalogger_status(|status| status.triggered = writing);
+                    if writing {
+                        log::info!("Datalogger: Triggered");
+                    } else {
+                        log::info!("Datalogger: Trigger cleared, waiting for the next one");
+                        // Each capture gets its own file when rotating
+                        if logger.take().is_some() {
+                            set_datalogger_status(|status| status.file = None);
+                        }
+                        rotate = true;
+                    }
+                }
+                (pending.0, Some(pending.1))
+            } else {
+                (vec![sample], None)
+            };
+
+            let low_disk_space = datalogger_status().low_disk_space;
+            if low_disk_space {
+                if logger.take().is_some() {
+                    set_datalogger_status(|status| status.file = None);
+                }
+            } else if logger.is_none() && !samples.is_empty() {
+                let directory = Path::new(&settings.directory);
+                let path = if settings.rotation.is_enabled() {
+                    // Files with other columns aren't resumed
+                    let last = data_logger::rotated_files(directory, &settings.log_filename())
+                        .ok()
+                        .and_then(|mut files| files.pop())
+                        .filter(|file| !rotate && data_logger::can_append(&file.path, &settings));
+                    match last {
+                        Some(file) => file.path,
+                        None => data_logger::new_rotated_file(directory, &settings.log_filename()),
+                    }
+                } else {
+                    // Files with other columns are kept with a timestamped name
+                    let path = settings.file_path();
+                    if !data_logger::can_append(&path, &settings) {
+                        let archive =
+                            data_logger::new_rotated_file(directory, &settings.log_filename());
+                        // The events stay with their records
+                        let events = data_logger::events_path(&path);
+                        if events.exists() {
+                            if let Err(error) =
+                                std::fs::rename(&events, data_logger::events_path(&archive))
+                            {
+                                log::warn!(
+                                    "Datalogger: Failed to move {}: {error}",
+                                    events.display()
+                                );
+                            }
+                        }
+                        match std::fs::rename(&path, &archive) {
+                            Ok(()) => log::info!(
+                                "Datalogger: Columns changed, {} moved to {}",
+                                path.display(),
+                                archive.display()
+                            ),
+                            Err(error) => {
+                                Self::stop_data_logger(
+                                    &mut settings,
+                                    format!("Failed to move {}: {error}", path.display()),
+                                );
+                                continue;
+                            }
+                        }
+                    }
+                    path
+                };
+                rotate = false;
+                match DataLogger::new(path, &settings) {
+                    Ok(new_logger) => {
+                        log::info!("Datalogger: Logging to {}", new_logger.path().display());
+                        Self::apply_retention(&settings, Some(new_logger.path()));
+                        let file = new_logger.path().display().to_string();
+                        set_datalogger_status(|status| status.file = Some(file));
+                        logger = Some(new_logger);
+                    }
+                    Err(error) => {
+                        Self::stop_data_logger(
+                            &mut settings,
+                            format!("Failed to create/open the log file: {error}"),
+                        );
+                        continue;
+                    }
+                }
+            }
 
-            logger.log_data(&reading).expect("Failed to log data");
+            if let Some(current) = logger.as_mut() {
+                if let Err(error) = samples
+                    .iter()
+                    .try_for_each(|sample| current.log_data(sample))
+                {
+                    log::error!("Datalogger: Failed to log data: {error}");
+                    // Running out of space is noticed right away
+                    disk_check = None;
+                }
+                let events = events.unwrap_or_else(event_log::take);
+                if let Err(error) = current.log_events(&events) {
+                    log::error!("Datalogger: Failed to log events: {error}");
+                }
+                if current.is_full(&settings.rotation) {
+                    log::info!("Datalogger: Rotating {}", current.path().display());
+                    logger = None;
+                    rotate = true;
+                    disk_check = None;
+                }
+            }
+
+            match receiver.recv_timeout(settings.interval()) {
+                Ok(new_settings) => {
+                    // A new file is opened when the destination, the naming or the columns change
+                    let layout_changed = new_settings.columns != settings.columns
+                        || new_settings.timestamps != settings.timestamps;
+                    if new_settings.file_path() != settings.file_path()
+                        || new_settings.rotation.is_enabled() != settings.rotation.is_enabled()
+                        || layout_changed
+                    {
+                        logger = None;
+                        rotate = layout_changed;
+                    }
+                    if new_settings.trigger.enable != settings.trigger.enable {
+                        trigger = Trigger::default();
+                        set_datalogger_status(|status| status.triggered = false);
+                    }
+                    settings = new_settings;
+                    disk_check = None;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+                Err(RecvTimeoutError::Timeout) => {}
+            }
+        }
+        set_datalogger_status(|status| status.file = None);
+        log::info!("Datalogger: Stopped");
+    }
 
-            thread::sleep(std::time::Duration::from_micros(refresh_interval));
+    /// Deletes the rotated files beyond the retention limits, keeping `current`
+    fn apply_retention(settings: &DataloggerSettings, current: Option<&Path>) {
+        match data_logger::apply_retention(
+            Path::new(&settings.directory),
+            &settings.log_filename(),
+            current,
+            &settings.retention,
+        ) {
+            Ok(deleted) => {
+                for file in deleted {
+                    log::info!("Datalogger: Deleted {} by retention", file.display());
+                }
+            }
+            Err(error) => log::warn!("Datalogger: Failed to apply retention: {error}"),
         }
     }
 
+    /// Applies the retention, then pauses logging while the free space is below its minimum
+    fn check_disk(settings: &DataloggerSettings, current: Option<&Path>) {
+        Self::apply_retention(settings, current);
+
+        let directory = Path::new(&settings.directory);
+        // Bytes available to the service, not counting the ones reserved for root
+        let free_space = match fs2::available_space(directory) {
+            Ok(free_space) => free_space,
+            Err(error) => {
+                log::warn!("Datalogger: Failed to check free space: {error}");
+                return;
+            }
+        };
+        let low_disk_space = free_space < settings.min_free_space;
+        let was_low = datalogger_status().low_disk_space;
+        if low_disk_space && !was_low {
+            log::warn!(
+                "Datalogger: Paused, {free_space} bytes left in {} is below the {} bytes minimum",
+                settings.directory,
+                settings.min_free_space
+            );
+        } else if !low_disk_space && was_low {
+            log::info!("Datalogger: Resumed, {free_space} bytes are free");
+        }
+        if low_disk_space != was_low {
+            event_log::record(Event::Alarm {
+                alarm: Alarm::LowDiskSpace,
+                active: low_disk_space,
+            });
+        }
+        set_datalogger_status(|status| {
+            status.free_space = Some(free_space);
+            status.low_disk_space = low_disk_space;
+        });
+    }
+
     fn websocket_broadcast() {
         // This package is broadcasted when it's created
-        let _package: crate::server::protocols::v1::structures::AnsPackage =
-            packages::reading(packages::Sensors::All, true);
+        let _package = packages::reading(packages::Sensors::All, true);
     }
 }
 
@@ -161,6 +892,18 @@ impl_from_enum!(
     All
 );
 
+fn to_array(axis: navigator_rs::AxisData) -> [f32; 3] {
+    [axis.x, axis.y, axis.z]
+}
+
+fn from_array(values: [f32; 3]) -> navigator_rs::AxisData {
+    navigator_rs::AxisData {
+        x: values[0],
+        y: values[1],
+        z: values[2],
+    }
+}
+
 impl From<navigator_rs::AxisData> for AxisData {
     fn from(read_axis: navigator_rs::AxisData) -> Self {
         Self {
@@ -184,7 +927,8 @@ impl From<navigator_rs::ADCData> for ADCData {
     }
 }
 
-#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+/// Deserialized from its name or alias, ignoring case
+#[derive(Apiv2Schema, Debug, Serialize, Clone, PartialEq, Eq, Hash, EnumIter)]
 pub enum PwmChannel {
     Ch1,
     Ch2,
@@ -205,13 +949,29 @@ pub enum PwmChannel {
     All,
 }
 
-#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, EnumIter)]
+/// Deserialized from its name or alias, ignoring case
+#[derive(Apiv2Schema, Debug, Serialize, Clone, PartialEq, Eq, Hash, EnumIter)]
 pub enum UserLed {
     Led1,
     Led2,
     Led3,
 }
 
+macro_rules! impl_deserialize_from_str {
+    ($($type:ty),+ $(,)?) => {
+        $(
+            impl<'de> Deserialize<'de> for $type {
+                fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                    let name = String::deserialize(deserializer)?;
+                    name.parse().map_err(serde::de::Error::custom)
+                }
+            }
+        )+
+    };
+}
+
+impl_deserialize_from_str!(PwmChannel, UserLed);
+
 impl_from_enum!(UserLed, navigator_rs::UserLed, Led1, Led2, Led3);
 
 pub struct AxisData {
@@ -224,92 +984,511 @@ pub struct ADCData {
     channel: [f32; 4],
 }
 
-pub fn init() {
-    with_navigator!().init()
+/// Navigator's peripherals, each one can be independently unavailable
+#[derive(
+    Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, EnumIter,
+)]
+pub enum Device {
+    Adc,
+    Barometer,
+    Imu,
+    Magnetometer,
+    LeakSensor,
+    UserLed,
+    NeoPixel,
+    Pwm,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct DeviceStatus {
+    pub device: Device,
+    pub available: bool,
+}
+
+/// Initializes the navigator, devices that fail are marked as unavailable.
+/// Returns an error only if no device could be initialized.
+pub fn init() -> Result<(), Error> {
+    let mut manager = NavigationManager::lock()?;
+    manager.init_devices();
+
+    if manager.available.is_empty() {
+        return Err(Error::HardwareUnavailable(
+            "Navigator failed to initialize".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Time of a sample, as wall-clock for reporting and monotonic for intervals and ages
+#[derive(Debug, Clone, Copy)]
+pub struct SampleTime {
+    pub timestamp: DateTime<Utc>,
+    pub instant: Instant,
+}
+
+impl SampleTime {
+    pub fn now() -> Self {
+        Self {
+            timestamp: Utc::now(),
+            instant: Instant::now(),
+        }
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct MonitorSettings {
+    pub enable: bool,
+    /// Reading rate in Hz
+    pub rate: f64,
+}
+
+impl MonitorSettings {
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(1.0 / self.rate)
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct DataloggerSettings {
+    pub enable: bool,
+    /// Logging rate in Hz
+    pub rate: f64,
+    pub directory: String,
+    /// Name of the file, or the base of the timestamped names while rotating.
+    /// Its extension follows the format.
+    pub filename: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default)]
+    pub compression: LogCompression,
+    /// Compression level, the algorithm's default if `None`
+    #[serde(default)]
+    pub compression_level: Option<i32>,
+    /// Writes only around triggers when enabled
+    #[serde(default)]
+    pub trigger: TriggerSettings,
+    /// Output changes, alarms and client connections are written next to each file
+    #[serde(default = "default_events")]
+    pub events: bool,
+    /// Times starting each record, in order
+    #[serde(default = "Timestamp::defaults")]
+    pub timestamps: Vec<Timestamp>,
+    /// Logged after the times, in order
+    #[serde(default = "Column::defaults")]
+    pub columns: Vec<Column>,
+    #[serde(default)]
+    pub rotation: RotationSettings,
+    #[serde(default)]
+    pub retention: RetentionSettings,
+    /// Logging pauses while less space is free in the directory, in bytes
+    #[serde(default)]
+    pub min_free_space: u64,
+}
+
+fn default_events() -> bool {
+    true
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DataloggerStatus {
+    /// File being written, `None` while paused
+    pub file: Option<String>,
+    /// Bytes free in the directory at the last check
+    pub free_space: Option<u64>,
+    /// Logging is paused until space is freed
+    pub low_disk_space: bool,
+    /// Records are being written around a trigger, in triggered mode
+    pub triggered: bool,
+    /// Held by a client, triggers like any condition
+    pub manual_trigger: bool,
+    /// Why the datalogger disabled itself, cleared when enabled again
+    pub error: Option<String>,
+}
+
+fn set_datalogger_status(update: impl FnOnce(&mut DataloggerStatus)) {
+    if let Ok(mut status) = DATALOGGER_STATUS.write() {
+        update(&mut status);
+    }
+}
+
+/// Holds or releases the manual trigger of a triggered datalogger
+pub fn set_datalogger_trigger(active: bool) -> DataloggerStatus {
+    set_datalogger_status(|status| status.manual_trigger = active);
+    datalogger_status()
+}
+
+pub fn datalogger_status() -> DataloggerStatus {
+    DATALOGGER_STATUS
+        .read()
+        .map(|status| status.clone())
+        .unwrap_or_default()
+}
+
+impl DataloggerSettings {
+    pub fn interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs_f64(1.0 / self.rate)
+    }
+
+    /// Filename with the format's and compression's extensions, like `data.jsonl.gz`
+    pub fn log_filename(&self) -> String {
+        let filename = Path::new(&self.filename).with_extension(self.format.extension());
+        format!("{}{}", filename.display(), self.compression.extension())
+    }
+
+    pub fn file_path(&self) -> PathBuf {
+        PathBuf::from(&self.directory).join(self.log_filename())
+    }
+}
+
+/// Starts the monitor thread, it stays paused while disabled
+pub fn init_monitor(settings: MonitorSettings) -> Result<(), Error> {
+    NavigationManager::init_monitor(settings)
+}
+
+/// Starts the datalogger thread, it stays paused while disabled
+pub fn init_datalogger(settings: DataloggerSettings) -> Result<(), Error> {
+    NavigationManager::init_datalogger(settings)
+}
+
+pub fn monitor_settings() -> Result<MonitorSettings, Error> {
+    match &NavigationManager::lock()?.monitor {
+        Some(worker) => Ok(worker.settings.clone()),
+        None => Err(Error::Internal("Monitor is not running".to_string())),
+    }
+}
+
+pub fn set_monitor_settings(settings: MonitorSettings) -> Result<MonitorSettings, Error> {
+    match &mut NavigationManager::lock()?.monitor {
+        Some(worker) => {
+            worker.configure(settings.clone())?;
+            Ok(settings)
+        }
+        None => Err(Error::Internal("Monitor is not running".to_string())),
+    }
+}
+
+pub fn datalogger_settings() -> Result<DataloggerSettings, Error> {
+    match &NavigationManager::lock()?.datalogger {
+        Some(worker) => Ok(worker.settings.clone()),
+        None => Err(Error::Internal("Datalogger is not running".to_string())),
+    }
+}
+
+pub fn set_datalogger_settings(settings: DataloggerSettings) -> Result<DataloggerSettings, Error> {
+    if !std::path::Path::new(&settings.directory).is_dir() {
+        return Err(Error::BadRequest(format!(
+            "{} is not a valid directory",
+            settings.directory
+        )));
+    }
+    match &mut NavigationManager::lock()?.datalogger {
+        Some(worker) => {
+            worker.configure(settings.clone())?;
+            if settings.enable {
+                set_datalogger_status(|status| status.error = None);
+            }
+            Ok(settings)
+        }
+        None => Err(Error::Internal("Datalogger is not running".to_string())),
+    }
+}
+
+/// Starts the background thread that retries the initialization of unavailable devices
+pub fn init_recovery() -> Result<(), Error> {
+    NavigationManager::init_recovery()
+}
+
+pub fn is_available(device: Device) -> bool {
+    NavigationManager::lock()
+        .map(|manager| manager.available.contains(&device))
+        .unwrap_or(false)
+}
+
+pub fn devices_status() -> Result<Vec<DeviceStatus>, Error> {
+    let manager = NavigationManager::lock()?;
+    Ok(Device::iter()
+        .map(|device| DeviceStatus {
+            device,
+            available: manager.available.contains(&device),
+        })
+        .collect())
+}
+
+pub fn set_led(select: UserLed, state: bool) -> Result<(), Error> {
+    lease::check_owner(Access::Lights)?;
+    let mut manager = NavigationManager::lock()?;
+    if manager.arming.led.as_ref() == Some(&select) {
+        return Err(Error::Conflict(format!(
+            "{select:?} shows the arming state"
+        )));
+    }
+    manager.run(Device::UserLed, |nav| {
+        nav.set_led(select.clone().into(), state)
+    })?;
+    manager.outputs.set_user_led(&select, state);
+    Ok(())
+}
+
+pub fn get_led(select: UserLed) -> Result<bool, Error> {
+    NavigationManager::with_device(Device::UserLed, |nav| nav.get_led(select.into()))
 }
 
-pub fn init_monitor(refresh_interval: u64) {
-    NavigationManager::init_monitor(refresh_interval);
+pub fn set_neopixel(rgb_array: Vec<[u8; 3]>) -> Result<(), Error> {
+    lease::check_owner(Access::Lights)?;
+    NavigationManager::with_device(Device::NeoPixel, |nav| nav.set_neopixel(&rgb_array))?;
+    event_log::record(Event::NeoPixel { rgb: rgb_array });
+    Ok(())
 }
 
-pub fn init_datalogger(refresh_interval: u64, file_path: PathBuf) {
-    NavigationManager::init_datalogger(refresh_interval, file_path);
+pub fn read_accel() -> Result<AxisData, Error> {
+    Ok(from_array(NavigationManager::read_accel()?).into())
 }
 
-pub fn set_led(select: UserLed, state: bool) {
-    with_navigator!().set_led(select.into(), state)
+pub fn read_gyro() -> Result<AxisData, Error> {
+    let gyro = NavigationManager::read_raw_gyro()?;
+    Ok(from_array(gyro_bias::correct(gyro)).into())
 }
 
-pub fn get_led(select: UserLed) -> bool {
-    with_navigator!().get_led(select.into())
+pub fn read_mag() -> Result<AxisData, Error> {
+    Ok(from_array(NavigationManager::read_mag()?).into())
 }
 
-pub fn set_neopixel(rgb_array: Vec<[u8; 3]>) {
-    with_navigator!().set_neopixel(&rgb_array)
+/// Tilt-compensated heading from the accelerometer and magnetometer
+pub fn read_heading() -> Result<Heading, Error> {
+    let accel = NavigationManager::read_accel()?;
+    let mag = NavigationManager::read_mag()?;
+    Ok(heading::compute(accel, mag))
 }
 
-pub fn read_accel() -> AxisData {
-    with_navigator!().read_accel().into()
+/// Vertical speed is estimated by the monitor, so it's the same as the cached reading
+pub fn read_vertical_speed() -> Result<f32, Error> {
+    cached::read_vertical_speed()
 }
 
-pub fn read_gyro() -> AxisData {
-    with_navigator!().read_gyro().into()
+pub fn read_leak() -> Result<bool, Error> {
+    NavigationManager::with_device(Device::LeakSensor, |nav| nav.read_leak())
 }
 
-pub fn read_mag() -> AxisData {
-    with_navigator!().read_mag().into()
+/// Alarms active now, the same ones written to the event log
+pub fn active_alarms() -> Vec<Alarm> {
+    NavigationManager::lock()
+        .map(|mut manager| manager.alarms())
+        .unwrap_or_default()
 }
 
-pub fn read_temperature() -> f32 {
-    with_navigator!().read_temperature()
+pub fn read_temperature() -> Result<f32, Error> {
+    NavigationManager::with_device(Device::Barometer, |nav| nav.read_temperature())
 }
 
-pub fn read_pressure() -> f32 {
-    with_navigator!().read_pressure()
+pub fn read_pressure() -> Result<f32, Error> {
+    NavigationManager::with_device(Device::Barometer, |nav| nav.read_pressure())
 }
 
-pub fn read_adc_all() -> ADCData {
-    with_navigator!().read_adc_all().into()
+pub fn read_adc_all() -> Result<ADCData, Error> {
+    NavigationManager::with_device(Device::Adc, |nav| nav.read_adc_all().into())
 }
 
 pub mod cached {
-    use super::{ADCData, AxisData, DATA};
+    use super::{to_array, ADCData, AxisData, Data, Device, Error, SampleTime, DATA};
+    use crate::heading::{self, Heading};
+    use crate::replay;
+
+    /// Reads the monitor's last sample, if the device is currently available or a file is replayed
+    fn read<T>(device: Device, select: impl FnOnce(&Data) -> T) -> Result<T, Error> {
+        if !super::is_available(device) && !replay::is_active() {
+            return Err(Error::HardwareUnavailable(format!(
+                "{device:?} is not available"
+            )));
+        }
+        let data = DATA
+            .read()
+            .map_err(|error| Error::Internal(format!("Cached data is poisoned: {error}")))?;
+        Ok(select(&data))
+    }
+
+    pub fn read_accel() -> Result<AxisData, Error> {
+        read(Device::Imu, |data| data.state.accelerometer.into())
+    }
 
-    pub fn read_accel() -> AxisData {
-        DATA.read().unwrap().state.accelerometer.into()
+    pub fn read_gyro() -> Result<AxisData, Error> {
+        read(Device::Imu, |data| data.state.gyro.into())
     }
 
-    pub fn read_gyro() -> AxisData {
-        DATA.read().unwrap().state.gyro.into()
+    pub fn read_mag() -> Result<AxisData, Error> {
+        read(Device::Magnetometer, |data| data.state.magnetometer.into())
     }
 
-    pub fn read_mag() -> AxisData {
-        DATA.read().unwrap().state.magnetometer.into()
+    pub fn read_heading() -> Result<Heading, Error> {
+        let accel = read(Device::Imu, |data| data.state.accelerometer)?;
+        let mag = read(Device::Magnetometer, |data| data.state.magnetometer)?;
+        Ok(heading::compute(to_array(accel), to_array(mag)))
     }
 
-    pub fn read_temperature() -> f32 {
-        DATA.read().unwrap().state.temperature
+    pub fn read_vertical_speed() -> Result<f32, Error> {
+        read(Device::Barometer, |data| data.vertical_speed)?.ok_or_else(|| {
+            Error::HardwareUnavailable("Vertical speed requires the monitor running".to_string())
+        })
+    }
+
+    pub fn read_temperature() -> Result<f32, Error> {
+        read(Device::Barometer, |data| data.state.temperature)
+    }
+
+    pub fn read_pressure() -> Result<f32, Error> {
+        read(Device::Barometer, |data| data.state.pressure)
+    }
+
+    pub fn read_adc_all() -> Result<ADCData, Error> {
+        read(Device::Adc, |data| data.state.adc.into())
+    }
+
+    /// Time of the monitor's last sample
+    pub fn read_sample_time() -> Result<SampleTime, Error> {
+        DATA.read()
+            .map_err(|error| Error::Internal(format!("Cached data is poisoned: {error}")))?
+            .sample_time
+            .ok_or_else(|| {
+                Error::HardwareUnavailable(
+                    "Monitor has no samples yet, check that it's enabled".to_string(),
+                )
+            })
+    }
+}
+
+/// While disarmed the channel holds its safe value instead, returns the value written.
+/// Refused while the channel is leased to another client than the thread's owner.
+pub fn set_pwm_channel_value(channel: PwmChannel, value: u16) -> Result<u16, Error> {
+    lease::check_owner(Access::Channels(std::slice::from_ref(&channel)))?;
+    NavigationManager::lock()?.set_pwm(&channel, value)
+}
+
+pub fn set_pwm_freq_hz(freq: f32) -> Result<(), Error> {
+    lease::check_owner(Access::Everything)?;
+    NavigationManager::with_device(Device::Pwm, |nav| nav.set_pwm_freq_hz(freq))?;
+
+    // The frequency is limited by the prescale resolution, as done by the navigator
+    let prescale = (PWM_CLOCK / (4096.0 * freq.clamp(24.0, 1526.0))).round() - 1.0;
+    let frequency = PWM_CLOCK / (4096.0 * (prescale + 1.0));
+    let previous = NavigationManager::lock()?.pwm_frequency.replace(frequency);
+    if previous.unwrap_or(DEFAULT_PWM_FREQUENCY) != frequency {
+        event_log::record(Event::PwmFrequency { frequency });
+    }
+    Ok(())
+}
+
+/// Current PWM frequency, in Hz
+pub fn pwm_frequency() -> Result<f32, Error> {
+    Ok(NavigationManager::lock()?
+        .pwm_frequency
+        .unwrap_or(DEFAULT_PWM_FREQUENCY))
+}
+
+/// PWM channel value of a pulse width in microseconds, at the current frequency
+pub fn pwm_pulse_value(pulse: f32) -> Result<u16, Error> {
+    let value = pulse * 1e-6 * pwm_frequency()? * 4096.0;
+    Ok(value.round().clamp(0.0, 4095.0) as u16)
+}
+
+/// Enabling is refused while disarmed
+pub fn set_pwm_enable(state: bool) -> Result<(), Error> {
+    lease::check_owner(Access::Everything)?;
+    NavigationManager::lock()?.set_pwm_enable(state)
+}
+
+pub fn output_states() -> Result<OutputStates, Error> {
+    Ok(NavigationManager::lock()?.outputs)
+}
+
+/// Value held by a channel while disarmed
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct SafeValue {
+    pub channel: PwmChannel,
+    pub value: u16,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ArmingSettings {
+    /// Value held by the channels while disarmed, 0 stops their pulses
+    pub safe_value: u16,
+    /// Channels holding other values while disarmed, like thrusters at their neutral
+    pub safe_values: Vec<SafeValue>,
+    /// LED turned on while armed, user writes to it are refused
+    pub led: Option<UserLed>,
+}
+
+impl ArmingSettings {
+    fn safe_value(&self, channel: &PwmChannel) -> u16 {
+        self.safe_values
+            .iter()
+            .find(|safe| safe.channel == *channel)
+            .map(|safe| safe.value)
+            .unwrap_or(self.safe_value)
     }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct ArmingStatus {
+    pub armed: bool,
+    /// Pre-arm checks failing now, arming is refused while there are any
+    pub failed_checks: Vec<String>,
+}
+
+const ARMING_SETTINGS_NAME: &str = "arming";
 
-    pub fn read_pressure() -> f32 {
-        DATA.read().unwrap().state.pressure
+/// Loads the saved arming settings and holds the outputs disarmed
+pub fn init_arming() {
+    if let Some(settings) = settings::load::<ArmingSettings>(ARMING_SETTINGS_NAME) {
+        if let Ok(mut manager) = NavigationManager::lock() {
+            manager.arming = settings;
+        }
+    }
+    if let Err(error) = disarm() {
+        log::warn!("Arming: {error}");
     }
+}
 
-    pub fn read_adc_all() -> ADCData {
-        DATA.read().unwrap().state.adc.into()
+/// Lets the channels follow the writes, refused if any pre-arm check fails
+pub fn arm() -> Result<(), Error> {
+    NavigationManager::lock()?.arm()
+}
+
+/// Disables the PWM and holds every channel at its safe value
+pub fn disarm() -> Result<(), Error> {
+    let mut manager = NavigationManager::lock()?;
+    if manager.armed {
+        log::info!("Arming: disarmed");
+        event_log::record(Event::Arming { armed: false });
     }
+    manager.armed = false;
+    manager.show_armed(false);
+    manager.run(Device::Pwm, |nav| nav.set_pwm_enable(false))?;
+    manager.outputs.set_pwm_enable(false);
+    manager.hold_safe_value(&PwmChannel::All)?;
+    Ok(())
 }
 
-pub fn set_pwm_channel_value(channel: PwmChannel, value: u16) {
-    with_navigator!().set_pwm_channel_value(channel.into(), value)
+pub fn arming_status() -> Result<ArmingStatus, Error> {
+    let mut manager = NavigationManager::lock()?;
+    Ok(ArmingStatus {
+        armed: manager.armed,
+        failed_checks: manager.pre_arm_checks(),
+    })
 }
 
-pub fn set_pwm_freq_hz(freq: f32) {
-    with_navigator!().set_pwm_freq_hz(freq)
+pub fn arming_settings() -> Result<ArmingSettings, Error> {
+    Ok(NavigationManager::lock()?.arming.clone())
 }
 
-pub fn set_pwm_enable(state: bool) {
-    with_navigator!().set_pwm_enable(state)
+/// Applies and saves the arming settings, the new safe values are held right away if disarmed
+pub fn set_arming_settings(settings: ArmingSettings) -> Result<ArmingSettings, Error> {
+    settings::save(ARMING_SETTINGS_NAME, &settings)
+        .map_err(|error| Error::Internal(format!("Failed to save arming settings: {error}")))?;
+    let mut manager = NavigationManager::lock()?;
+    manager.arming = settings.clone();
+    if !manager.armed {
+        manager.hold_safe_value(&PwmChannel::All)?;
+    }
+    Ok(settings)
 }
 
 impl From<AxisData> for Vec<f32> {
@@ -328,3 +1507,148 @@ impl From<ADCData> for Vec<f32> {
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workers_take_new_settings_while_running() {
+        let (sender, receiver) = mpsc::channel::<MonitorSettings>();
+        let (applied, settings_applied) = mpsc::channel();
+        // Takes a single change of settings, then ends
+        let handle = thread::spawn(move || {
+            if let Ok(settings) = receiver.recv() {
+                let _ = applied.send(settings.rate);
+            }
+        });
+        let mut worker = Worker {
+            handle,
+            sender,
+            settings: MonitorSettings {
+                enable: true,
+                rate: 10.0,
+            },
+        };
+
+        worker
+            .configure(MonitorSettings {
+                enable: true,
+                rate: 50.0,
+            })
+            .unwrap();
+        assert_eq!(worker.settings.rate, 50.0);
+        let received = settings_applied.recv_timeout(std::time::Duration::from_secs(5));
+        assert_eq!(received, Ok(50.0));
+
+        while !worker.handle.is_finished() {
+            thread::sleep(std::time::Duration::from_millis(1));
+        }
+        let stopped = worker.configure(MonitorSettings {
+            enable: false,
+            rate: 1.0,
+        });
+        assert!(matches!(stopped, Err(Error::Internal(_))));
+        assert_eq!(worker.settings.rate, 50.0);
+    }
+
+    #[test]
+    fn datalogger_settings_need_a_directory() {
+        let settings: DataloggerSettings = serde_json::from_value(serde_json::json!({
+            "enable": true,
+            "rate": 10.0,
+            "directory": "/nonexistent/navigator-webassistant",
+            "filename": "data",
+        }))
+        .unwrap();
+        let result = set_datalogger_settings(settings);
+        assert!(matches!(result, Err(Error::BadRequest(_))));
+    }
+
+    /// Disarmed manager, without a navigator, holding 1229 on `Ch1` and 0 on the others
+    fn disarmed() -> NavigationManager {
+        NavigationManager {
+            arming: ArmingSettings {
+                safe_value: 0,
+                safe_values: vec![SafeValue {
+                    channel: PwmChannel::Ch1,
+                    value: 1229,
+                }],
+                led: None,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn arming_is_refused_by_missing_devices_and_alarms() {
+        let mut manager = disarmed();
+        let refused = manager.arm();
+        assert!(matches!(refused, Err(Error::BadRequest(_))));
+        assert!(!manager.armed);
+        assert_eq!(
+            manager.pre_arm_checks(),
+            ["PWM is not available", "Leak sensor is not available"]
+        );
+
+        DATALOGGER_STATUS.write().unwrap().low_disk_space = true;
+        let failed = manager.pre_arm_checks();
+        DATALOGGER_STATUS.write().unwrap().low_disk_space = false;
+        assert_eq!(failed.last().unwrap(), &Alarm::LowDiskSpace.to_string());
+        assert!(manager.arm().is_err());
+        assert!(!manager.armed);
+    }
+
+    #[test]
+    fn safe_values_are_held_while_disarmed() {
+        let mut manager = disarmed();
+        assert_eq!(
+            manager.pwm_values(&PwmChannel::Ch1, 400),
+            [(PwmChannel::Ch1, 1229)]
+        );
+        assert_eq!(
+            manager.pwm_values(&PwmChannel::Ch2, 400),
+            [(PwmChannel::Ch2, 0)]
+        );
+        let all = manager.pwm_values(&PwmChannel::All, 400);
+        assert_eq!(all.len(), 16);
+        assert!(all.contains(&(PwmChannel::Ch1, 1229)));
+        assert!(all.contains(&(PwmChannel::Ch16, 0)));
+
+        // Nothing is recorded as written when the PWM can't be reached
+        assert!(manager.set_pwm(&PwmChannel::Ch2, 400).is_err());
+        assert_eq!(manager.outputs.pwm, [0; 16]);
+
+        manager.armed = true;
+        assert_eq!(
+            manager.pwm_values(&PwmChannel::Ch1, 400),
+            [(PwmChannel::Ch1, 400)]
+        );
+        assert_eq!(
+            manager.pwm_values(&PwmChannel::All, 400),
+            [(PwmChannel::All, 400)]
+        );
+    }
+
+    #[test]
+    fn pwm_enable_is_refused_while_disarmed() {
+        let mut manager = disarmed();
+        // Refused before reaching the navigator
+        assert!(matches!(
+            manager.set_pwm_enable(true),
+            Err(Error::BadRequest(_))
+        ));
+        assert!(!manager.outputs.pwm_enable);
+        // Disabling is always let through
+        assert!(matches!(
+            manager.set_pwm_enable(false),
+            Err(Error::HardwareUnavailable(_))
+        ));
+
+        manager.armed = true;
+        assert!(matches!(
+            manager.set_pwm_enable(true),
+            Err(Error::HardwareUnavailable(_))
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct HeadingSettings {
+    /// Magnetic declination, positive to the east, in degrees
+    pub declination: f32,
+    /// Board yaw relative to the vehicle's forward direction, in degrees
+    pub yaw_offset: f32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Heading {
+    /// Heading relative to the true north, in degrees
+    pub true_north: f32,
+    /// Heading relative to the magnetic north, in degrees
+    pub magnetic_north: f32,
+}
+
+lazy_static! {
+    static ref SETTINGS: RwLock<HeadingSettings> = Default::default();
+}
+
+/// Wraps to [0, 360), small negative angles round up to 360 in `rem_euclid`
+fn wrap_360(degrees: f32) -> f32 {
+    let wrapped = degrees.rem_euclid(360.0);
+    if wrapped < 360.0 {
+        wrapped
+    } else {
+        0.0
+    }
+}
+
+/// Tilt-compensated heading, for a frame with x forward, y right and z down.
+/// The accelerometer is expected to report the specific force, reading -g on z when level.
+pub fn compute(accel: [f32; 3], mag: [f32; 3]) -> Heading {
+    tilt_compensated(accel, mag, &settings())
+}
+
+fn tilt_compensated(accel: [f32; 3], mag: [f32; 3], settings: &HeadingSettings) -> Heading {
+    // Gravity points opposite to the measured specific force
+    let [gx, gy, gz] = accel.map(|value| -value);
+    let [mx, my, mz] = mag;
+
+    let roll = gy.atan2(gz);
+    let pitch = (-gx).atan2(gy * roll.sin() + gz * roll.cos());
+
+    // Magnetic field projected on the horizontal plane
+    let horizontal_x =
+        mx * pitch.cos() + my * pitch.sin() * roll.sin() + mz * pitch.sin() * roll.cos();
+    let horizontal_y = mz * roll.sin() - my * roll.cos();
+
+    let magnetic_north =
+        wrap_360(horizontal_y.atan2(horizontal_x).to_degrees() + settings.yaw_offset);
+
+    Heading {
+        true_north: wrap_360(magnetic_north + settings.declination),
+        magnetic_north,
+    }
+}
+
+pub fn settings() -> HeadingSettings {
+    SETTINGS
+        .read()
+        .map(|settings| settings.clone())
+        .unwrap_or_default()
+}
+
+pub fn set_settings(settings: HeadingSettings) {
+    if let Ok(mut current) = SETTINGS.write() {
+        *current = settings;
+    }
+}
+
+impl From<Heading> for Vec<f32> {
+    fn from(heading: Heading) -> Self {
+        vec![heading.true_north, heading.magnetic_north]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Earth frame vector seen by a board at the given yaw, pitch and roll, in degrees
+    fn to_board(vector: [f32; 3], yaw: f32, pitch: f32, roll: f32) -> [f32; 3] {
+        let (sy, cy) = yaw.to_radians().sin_cos();
+        let (sp, cp) = pitch.to_radians().sin_cos();
+        let (sr, cr) = roll.to_radians().sin_cos();
+        let [x, y, z] = vector;
+        let [x, y] = [cy * x + sy * y, -sy * x + cy * y];
+        let [x, z] = [cp * x - sp * z, sp * x + cp * z];
+        let [y, z] = [cr * y + sr * z, -sr * y + cr * z];
+        [x, y, z]
+    }
+
+    /// Heading of a board at the given attitude, in a field inclined by 60 degrees
+    fn board_heading(yaw: f32, pitch: f32, roll: f32, settings: &HeadingSettings) -> Heading {
+        let (inclination_sin, inclination_cos) = 60f32.to_radians().sin_cos();
+        let field = [0.4 * inclination_cos, 0.0, 0.4 * inclination_sin];
+        let specific_force = [0.0, 0.0, -9.81];
+        tilt_compensated(
+            to_board(specific_force, yaw, pitch, roll),
+            to_board(field, yaw, pitch, roll),
+            settings,
+        )
+    }
+
+    fn angle_difference(a: f32, b: f32) -> f32 {
+        (a - b + 180.0).rem_euclid(360.0) - 180.0
+    }
+
+    #[test]
+    fn compensates_tilt() {
+        let settings = HeadingSettings::default();
+        for yaw in [0.0, 45.0, 90.0, 180.0, 270.0, 359.0] {
+            for (pitch, roll) in [(0.0, 0.0), (30.0, 0.0), (0.0, -40.0), (-20.0, 25.0)] {
+                let heading = board_heading(yaw, pitch, roll, &settings);
+                assert!(
+                    angle_difference(heading.magnetic_north, yaw).abs() < 0.01,
+                    "yaw {yaw} pitch {pitch} roll {roll}: {heading:?}"
+                );
+                assert!((0.0..360.0).contains(&heading.magnetic_north));
+                assert_eq!(heading.true_north, heading.magnetic_north);
+            }
+        }
+    }
+
+    #[test]
+    fn applies_declination_and_offset() {
+        let settings = HeadingSettings {
+            declination: 10.0,
+            yaw_offset: -90.0,
+        };
+        let heading = board_heading(95.0, 10.0, 10.0, &settings);
+        assert!(angle_difference(heading.magnetic_north, 5.0).abs() < 0.01);
+        assert!(angle_difference(heading.true_north, 15.0).abs() < 0.01);
+
+        // Wraps around the north
+        let settings = HeadingSettings {
+            declination: -20.0,
+            yaw_offset: 0.0,
+        };
+        let heading = board_heading(10.0, 0.0, 0.0, &settings);
+        assert!((heading.true_north - 350.0).abs() < 0.01, "{heading:?}");
+        assert_eq!(wrap_360(-1e-6), 0.0);
+        assert_eq!(wrap_360(720.0), 0.0);
+    }
+}
@@ -0,0 +1,487 @@
+use crate::hardware_manager::PwmChannel;
+use crate::server::protocols::v1::packages;
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::sync::{Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+
+/// Interval between checks for expired leases
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct Lease {
+    /// Name given by the holder, shown to the other clients
+    pub client: String,
+    /// Leased channels, every output if `None`
+    pub channels: Option<Vec<PwmChannel>>,
+    /// Time until the lease expires, in seconds
+    pub expires_in: f64,
+}
+
+/// Lease as seen by its holder, the token is required to write to the leased outputs
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct LeaseGrant {
+    pub token: String,
+    pub lease: Lease,
+}
+
+/// Outputs touched by a write
+pub enum Access<'a> {
+    Channels(&'a [PwmChannel]),
+    /// User LEDs and NeoPixel
+    Lights,
+    /// Outputs shared by all channels, like the PWM frequency, or unknown beforehand
+    Everything,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LeaseError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Held(String),
+}
+
+struct Held {
+    token: String,
+    client: String,
+    channels: Option<Vec<PwmChannel>>,
+    expires: Instant,
+}
+
+impl Held {
+    fn is_expired(&self) -> bool {
+        self.expires <= Instant::now()
+    }
+
+    fn covers(&self, access: &Access) -> bool {
+        let Some(leased) = &self.channels else {
+            return true;
+        };
+        match access {
+            Access::Channels(channels) => channels.iter().any(|channel| {
+                leased.contains(channel)
+                    || leased.contains(&PwmChannel::All)
+                    || (*channel == PwmChannel::All && !leased.is_empty())
+            }),
+            Access::Lights => false,
+            Access::Everything => true,
+        }
+    }
+
+    fn lease(&self) -> Lease {
+        Lease {
+            client: self.client.clone(),
+            channels: self.channels.clone(),
+            expires_in: self
+                .expires
+                .saturating_duration_since(Instant::now())
+                .as_secs_f64(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref LEASES: Mutex<Vec<Held>> = Default::default();
+    static ref ADMIN_KEY: RwLock<Option<String>> = Default::default();
+}
+
+thread_local! {
+    /// Lease token the current thread writes to the outputs with
+    static OWNER: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Puts the previous owner back, even if the operation panics
+struct OwnerGuard(Option<String>);
+
+impl Drop for OwnerGuard {
+    fn drop(&mut self) {
+        OWNER.with(|owner| *owner.borrow_mut() = self.0.take());
+    }
+}
+
+fn lock() -> Result<MutexGuard<'static, Vec<Held>>, LeaseError> {
+    LEASES
+        .lock()
+        .map_err(|error| LeaseError::Invalid(error.to_string()))
+}
+
+/// 128 bits from the operating system's secure generator, as hex
+fn new_token() -> String {
+    let mut bytes = [0u8; 16];
+    rand::rngs::OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Sets the key that allows taking leases held by other clients,
+/// and starts the thread that announces expired leases
+pub fn init(admin_key: Option<String>) {
+    if let Ok(mut key) = ADMIN_KEY.write() {
+        *key = admin_key;
+    }
+
+    let spawned = std::thread::Builder::new()
+        .name("Lease".into())
+        .spawn(|| loop {
+            std::thread::sleep(EXPIRY_INTERVAL);
+            let expired: Vec<String> = match LEASES.lock() {
+                Ok(mut leases) => {
+                    let expired = leases
+                        .iter()
+                        .filter(|held| held.is_expired())
+                        .map(|held| held.client.clone())
+                        .collect();
+                    leases.retain(|held| !held.is_expired());
+                    expired
+                }
+                Err(_) => break,
+            };
+            if !expired.is_empty() {
+                log::info!("Lease: expired for {}", expired.join(", "));
+                let _package = packages::lease_package(list());
+            }
+        });
+    if let Err(error) = spawned {
+        log::error!("Lease: can't setup thread: {error}");
+    }
+}
+
+/// Whether `key` allows taking leases held by other clients, no one may without a configured key
+pub fn is_admin(key: Option<&str>) -> bool {
+    match ADMIN_KEY.read().ok().as_deref() {
+        Some(Some(admin_key)) => key == Some(admin_key.as_str()),
+        _ => false,
+    }
+}
+
+/// Current leases, without their tokens
+pub fn list() -> Vec<Lease> {
+    LEASES
+        .lock()
+        .map(|leases| {
+            leases
+                .iter()
+                .filter(|held| !held.is_expired())
+                .map(Held::lease)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Takes the outputs for `ttl`, or every output if `channels` is `None`.
+/// A lease is renewed or changed by passing its `token`. With `force`, the leases of other
+/// clients over the same outputs are revoked instead of rejecting the request.
+pub fn acquire(
+    client: &str,
+    channels: Option<Vec<PwmChannel>>,
+    ttl: Duration,
+    token: Option<&str>,
+    force: bool,
+) -> Result<LeaseGrant, LeaseError> {
+    grant(&mut *lock()?, client, channels, ttl, token, force)
+}
+
+fn grant(
+    leases: &mut Vec<Held>,
+    client: &str,
+    channels: Option<Vec<PwmChannel>>,
+    ttl: Duration,
+    token: Option<&str>,
+    force: bool,
+) -> Result<LeaseGrant, LeaseError> {
+    if client.trim().is_empty() {
+        return Err(LeaseError::Invalid(
+            "Client name can't be empty".to_string(),
+        ));
+    }
+    if channels
+        .as_ref()
+        .is_some_and(|channels| channels.is_empty())
+    {
+        return Err(LeaseError::Invalid(
+            "Leases need at least one channel".to_string(),
+        ));
+    }
+
+    let renewed = token.filter(|token| leases.iter().any(|held| held.token == *token));
+    if token.is_some() && renewed.is_none() {
+        return Err(LeaseError::Invalid(
+            "Lease token is unknown or expired".to_string(),
+        ));
+    }
+
+    let access = match &channels {
+        Some(channels) => Access::Channels(channels),
+        None => Access::Everything,
+    };
+    let conflicts = |held: &Held| {
+        Some(held.token.as_str()) != renewed && !held.is_expired() && held.covers(&access)
+    };
+    if force {
+        for held in leases.iter().filter(|held| conflicts(held)) {
+            log::warn!("Lease: revoked from {} by {client}", held.client);
+        }
+        leases.retain(|held| !conflicts(held));
+    } else if let Some(held) = leases.iter().find(|held| conflicts(held)) {
+        return Err(held_error(held));
+    }
+
+    let token = renewed.map(str::to_string).unwrap_or_else(new_token);
+    leases.retain(|held| held.token != token);
+    let held = Held {
+        token: token.clone(),
+        client: client.to_string(),
+        channels,
+        expires: Instant::now() + ttl,
+    };
+    let grant = LeaseGrant {
+        token,
+        lease: held.lease(),
+    };
+    leases.push(held);
+    log::info!(
+        "Lease: {} holds {:?}",
+        grant.lease.client,
+        grant.lease.channels
+    );
+    Ok(grant)
+}
+
+pub fn release(token: &str) -> Result<(), LeaseError> {
+    let mut leases = lock()?;
+    let Some(index) = leases.iter().position(|held| held.token == token) else {
+        return Err(LeaseError::Invalid(
+            "Lease token is unknown or expired".to_string(),
+        ));
+    };
+    let held = leases.remove(index);
+    log::info!("Lease: released by {}", held.client);
+    Ok(())
+}
+
+fn held_error(held: &Held) -> LeaseError {
+    let outputs = match &held.channels {
+        Some(channels) => format!("{channels:?}"),
+        None => "All outputs".to_string(),
+    };
+    LeaseError::Held(format!(
+        "{outputs} leased to {} for {:.0} s more",
+        held.client,
+        held.lease().expires_in
+    ))
+}
+
+/// Runs `operation` as the holder of `token`, its output writes are checked against the
+/// other leases. Threads started inside, like scripts and sequences, take the token along.
+pub fn with_owner<T>(token: Option<&str>, operation: impl FnOnce() -> T) -> T {
+    let previous = OWNER.with(|owner| owner.replace(token.map(str::to_string)));
+    let _guard = OwnerGuard(previous);
+    operation()
+}
+
+/// Token the current thread writes with, `None` for clients without a lease
+pub fn owner() -> Option<String> {
+    OWNER.with(|owner| owner.borrow().clone())
+}
+
+/// Rejects writes of the current thread to outputs leased to another client
+pub fn check_owner(access: Access) -> Result<(), LeaseError> {
+    check(owner().as_deref(), access)
+}
+
+/// Rejects writes to outputs leased to another client than the holder of `token`
+pub fn check(token: Option<&str>, access: Access) -> Result<(), LeaseError> {
+    check_in(&lock()?, token, &access)
+}
+
+fn check_in(leases: &[Held], token: Option<&str>, access: &Access) -> Result<(), LeaseError> {
+    match leases.iter().find(|held| {
+        Some(held.token.as_str()) != token && !held.is_expired() && held.covers(access)
+    }) {
+        Some(held) => Err(held_error(held)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn channels(channels: &[PwmChannel]) -> Option<Vec<PwmChannel>> {
+        Some(channels.to_vec())
+    }
+
+    #[test]
+    fn leases_are_acquired_renewed_and_expire() {
+        let mut leases = vec![];
+        let first = grant(
+            &mut leases,
+            "a",
+            channels(&[PwmChannel::Ch1]),
+            TTL,
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(first.token.len(), 32);
+        assert!(grant(
+            &mut leases,
+            "b",
+            channels(&[PwmChannel::Ch1]),
+            TTL,
+            None,
+            false
+        )
+        .is_err());
+        assert!(grant(&mut leases, " ", None, TTL, None, false).is_err());
+        assert!(grant(&mut leases, "b", channels(&[]), TTL, None, false).is_err());
+        assert!(grant(&mut leases, "b", None, TTL, Some("unknown"), false).is_err());
+
+        // Renewing keeps the token and may change the channels
+        let renewed = grant(
+            &mut leases,
+            "a",
+            channels(&[PwmChannel::Ch2]),
+            Duration::from_millis(10),
+            Some(&first.token),
+            false,
+        )
+        .unwrap();
+        assert_eq!(renewed.token, first.token);
+        assert_eq!(leases.len(), 1);
+        grant(
+            &mut leases,
+            "b",
+            channels(&[PwmChannel::Ch1]),
+            TTL,
+            None,
+            false,
+        )
+        .unwrap();
+
+        // Expired leases no longer hold their channels
+        std::thread::sleep(Duration::from_millis(20));
+        let access = Access::Channels(&[PwmChannel::Ch2]);
+        assert!(check_in(&leases, None, &access).is_ok());
+        grant(
+            &mut leases,
+            "c",
+            channels(&[PwmChannel::Ch2]),
+            TTL,
+            None,
+            false,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn forcing_requires_the_admin_key() {
+        assert!(!is_admin(None));
+        assert!(!is_admin(Some("key")));
+        *ADMIN_KEY.write().unwrap() = Some("key".to_string());
+        assert!(is_admin(Some("key")));
+        assert!(!is_admin(Some("other")));
+        assert!(!is_admin(None));
+        *ADMIN_KEY.write().unwrap() = None;
+
+        // Forced leases revoke the others over the same outputs only
+        let mut leases = vec![];
+        grant(
+            &mut leases,
+            "a",
+            channels(&[PwmChannel::Ch1]),
+            TTL,
+            None,
+            false,
+        )
+        .unwrap();
+        grant(
+            &mut leases,
+            "b",
+            channels(&[PwmChannel::Ch2]),
+            TTL,
+            None,
+            false,
+        )
+        .unwrap();
+        grant(
+            &mut leases,
+            "c",
+            channels(&[PwmChannel::Ch1]),
+            TTL,
+            None,
+            true,
+        )
+        .unwrap();
+        let clients: Vec<&str> = leases.iter().map(|held| held.client.as_str()).collect();
+        assert_eq!(clients, ["b", "c"]);
+    }
+
+    #[test]
+    fn leases_cover_their_channels_or_everything() {
+        let mut leases = vec![];
+        grant(
+            &mut leases,
+            "a",
+            channels(&[PwmChannel::Ch1]),
+            TTL,
+            None,
+            false,
+        )
+        .unwrap();
+        let held = &leases[0];
+        assert!(held.covers(&Access::Channels(&[PwmChannel::Ch1])));
+        assert!(held.covers(&Access::Channels(&[PwmChannel::Ch2, PwmChannel::Ch1])));
+        assert!(held.covers(&Access::Channels(&[PwmChannel::All])));
+        assert!(!held.covers(&Access::Channels(&[PwmChannel::Ch2])));
+        assert!(!held.covers(&Access::Lights));
+        assert!(held.covers(&Access::Everything));
+
+        let mut leases = vec![];
+        grant(&mut leases, "a", None, TTL, None, false).unwrap();
+        let held = &leases[0];
+        assert!(held.covers(&Access::Channels(&[PwmChannel::Ch2])));
+        assert!(held.covers(&Access::Lights));
+        assert!(held.covers(&Access::Everything));
+
+        let mut leases = vec![];
+        grant(
+            &mut leases,
+            "a",
+            channels(&[PwmChannel::All]),
+            TTL,
+            None,
+            false,
+        )
+        .unwrap();
+        assert!(leases[0].covers(&Access::Channels(&[PwmChannel::Ch16])));
+    }
+
+    #[test]
+    fn writes_are_checked_against_other_clients_leases() {
+        let mut leases = vec![];
+        let lease = grant(
+            &mut leases,
+            "a",
+            channels(&[PwmChannel::Ch1]),
+            TTL,
+            None,
+            false,
+        )
+        .unwrap();
+        let access = Access::Channels(&[PwmChannel::Ch1]);
+        assert!(check_in(&leases, Some(&lease.token), &access).is_ok());
+        assert!(check_in(&leases, None, &access).is_err());
+        assert!(matches!(
+            check_in(&leases, Some("foreign"), &access),
+            Err(LeaseError::Held(_))
+        ));
+        assert!(check_in(&leases, Some("foreign"), &Access::Lights).is_ok());
+        assert!(check_in(&leases, Some("foreign"), &Access::Everything).is_err());
+    }
+}
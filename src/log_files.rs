@@ -0,0 +1,632 @@
+use crate::data_logger::{self, LogCompression, LogFormat, Timestamp};
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct LogFileInfo {
+    pub name: String,
+    /// Size on disk, in bytes
+    pub size: u64,
+    pub format: LogFormat,
+    pub compression: LogCompression,
+    /// Time of the first record, RFC 3339 in UTC
+    pub start: Option<String>,
+    /// Time of the last record, RFC 3339 in UTC
+    pub end: Option<String>,
+    pub rows: usize,
+    /// The datalogger is writing to this file
+    pub current: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogFileError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Part of a log file, with its raw bytes
+enum Item {
+    Header(Vec<u8>),
+    /// Record and its time in microseconds since Unix epoch, if it could be read
+    Record(Option<i64>, Vec<u8>),
+}
+
+/// Rows and time span of a file
+#[derive(Clone, Copy, Default)]
+struct Summary {
+    rows: usize,
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+impl Summary {
+    fn add(&mut self, time: Option<i64>) {
+        self.rows += 1;
+        if let Some(time) = time {
+            self.start = Some(self.start.unwrap_or(time).min(time));
+            self.end = Some(self.end.unwrap_or(time).max(time));
+        }
+    }
+}
+
+lazy_static! {
+    /// Summaries of closed files, valid while their size and modification time are the same
+    static ref SUMMARIES: Mutex<HashMap<PathBuf, (u64, SystemTime, Summary)>> = Default::default();
+    /// Summaries of the files being written, kept up to date by their writers
+    static ref LIVE: Mutex<HashMap<PathBuf, Summary>> = Default::default();
+}
+
+/// Summary of a file being written, listed without scanning the file again. It's cached as
+/// a closed file's once dropped.
+pub struct LiveSummary {
+    path: PathBuf,
+    summary: Summary,
+}
+
+impl LiveSummary {
+    /// Starts from the records already in `path`, if any
+    pub fn open(path: &Path) -> Self {
+        let summary = match std::fs::metadata(path) {
+            Ok(metadata) if metadata.len() > 0 => metadata
+                .modified()
+                .map_err(LogFileError::from)
+                .and_then(|modified| summary(path, metadata.len(), modified))
+                .unwrap_or_else(|error| {
+                    log::warn!("Log files: Failed to read {}: {error}", path.display());
+                    Summary::default()
+                }),
+            _ => Summary::default(),
+        };
+        let live = LiveSummary {
+            path: path.to_path_buf(),
+            summary,
+        };
+        live.publish();
+        live
+    }
+
+    /// Counts a record written at `time`, in microseconds since Unix epoch
+    pub fn add(&mut self, time: Option<i64>) {
+        self.summary.add(time);
+        self.publish();
+    }
+
+    fn publish(&self) {
+        if let Ok(mut live) = LIVE.lock() {
+            live.insert(self.path.clone(), self.summary);
+        }
+    }
+}
+
+impl Drop for LiveSummary {
+    fn drop(&mut self) {
+        if let Ok(mut live) = LIVE.lock() {
+            live.remove(&self.path);
+        }
+        let Ok(metadata) = std::fs::metadata(&self.path) else {
+            return;
+        };
+        if let (Ok(modified), Ok(mut summaries)) = (metadata.modified(), SUMMARIES.lock()) {
+            summaries.insert(self.path.clone(), (metadata.len(), modified, self.summary));
+        }
+    }
+}
+
+/// Format and compression of a log file, from its name
+fn kind(name: &str) -> Option<(LogFormat, LogCompression)> {
+    let (name, compression) = [LogCompression::Gzip, LogCompression::Zstd]
+        .into_iter()
+        .find_map(|compression| {
+            name.strip_suffix(compression.extension())
+                .map(|name| (name, compression))
+        })
+        .unwrap_or((name, LogCompression::None));
+    let format = [LogFormat::Csv, LogFormat::JsonLines, LogFormat::MessagePack]
+        .into_iter()
+        .find(|format| {
+            name.strip_suffix(format.extension())
+                .is_some_and(|stem| stem.len() > 1 && stem.ends_with('.'))
+        })?;
+    Some((format, compression))
+}
+
+/// Path of the log file `name` in `directory`, which must not be left
+pub fn path(directory: &Path, name: &str) -> Result<PathBuf, LogFileError> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(LogFileError::Invalid(format!(
+            "{name:?} is not a valid file name"
+        )));
+    }
+    if kind(name).is_none() {
+        return Err(LogFileError::Invalid(format!("{name} is not a log file")));
+    }
+    let path = directory.join(name);
+    if !path.is_file() {
+        return Err(LogFileError::Invalid(format!("File {name} does not exist")));
+    }
+    Ok(path)
+}
+
+fn decoder(path: &Path, compression: LogCompression) -> std::io::Result<Box<dyn BufRead>> {
+    let file = std::fs::File::open(path)?;
+    let reader: Box<dyn Read> = match compression {
+        LogCompression::None => Box::new(file),
+        // Each block is a gzip member or zstd frame, they're all read in sequence
+        LogCompression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(file)),
+        LogCompression::Zstd => Box::new(zstd::stream::read::Decoder::new(file)?),
+    };
+    Ok(Box::new(BufReader::new(reader)))
+}
+
+/// Text of a CSV log file, decompressed, the binary and JSON formats are refused
+pub fn csv_reader(path: &Path) -> Result<Box<dyn BufRead>, LogFileError> {
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+    match kind(name) {
+        Some((LogFormat::Csv, compression)) => Ok(decoder(path, compression)?),
+        Some((format, _)) => Err(LogFileError::Invalid(format!(
+            "{name} is in the {format:?} format, only CSV files are supported"
+        ))),
+        None => Err(LogFileError::Invalid(format!("{name} is not a log file"))),
+    }
+}
+
+/// Wall-clock time column of a file, the most precise one in its header
+#[derive(Clone, Copy)]
+struct TimeColumn {
+    index: usize,
+    timestamp: Timestamp,
+    /// Binary epoch times are integer microseconds
+    binary: bool,
+}
+
+impl TimeColumn {
+    /// Files written before the header was fixed may start without one
+    fn legacy(format: LogFormat) -> Option<Self> {
+        Some(TimeColumn {
+            index: 0,
+            timestamp: match format {
+                LogFormat::MessagePack => Timestamp::Epoch,
+                _ => Timestamp::Local,
+            },
+            binary: format == LogFormat::MessagePack,
+        })
+    }
+
+    fn find<'a>(names: impl Iterator<Item = &'a str> + Clone) -> Option<Self> {
+        let position = |wanted: &[&str]| names.clone().position(|name| wanted.contains(&name));
+        [Timestamp::Epoch, Timestamp::Utc, Timestamp::Local]
+            .into_iter()
+            .find_map(|timestamp| {
+                if let Some(index) = position(&[timestamp.name()]) {
+                    return Some(TimeColumn {
+                        index,
+                        timestamp,
+                        binary: false,
+                    });
+                }
+                // MessagePack epoch times, named Time_us before the times could be selected
+                let binary_names = ["Time_Epoch_us", "Time_us"];
+                let index = position(&binary_names).filter(|_| timestamp == Timestamp::Epoch)?;
+                Some(TimeColumn {
+                    index,
+                    timestamp,
+                    binary: true,
+                })
+            })
+    }
+
+    fn csv(&self, line: &str) -> Option<i64> {
+        self.timestamp.parse(line.split(',').nth(self.index)?)
+    }
+
+    fn binary(&self, fields: &[rmpv::Value]) -> Option<i64> {
+        let field = fields.get(self.index)?;
+        if self.binary {
+            field.as_i64()
+        } else {
+            self.timestamp.parse(field.as_str()?)
+        }
+    }
+}
+
+/// Reads the header and records of a file in order, until `visit` breaks. A truncated last
+/// record, like one being written, ends the file.
+fn scan(path: &Path, mut visit: impl FnMut(Item) -> ControlFlow<()>) -> Result<(), LogFileError> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let (format, compression) =
+        kind(&name).ok_or_else(|| LogFileError::Invalid(format!("{name} is not a log file")))?;
+    let mut reader = decoder(path, compression)?;
+    let mut time = TimeColumn::legacy(format);
+
+    if format == LogFormat::MessagePack {
+        loop {
+            let mut raw = vec![];
+            let value = {
+                let mut tee = TeeReader {
+                    inner: &mut reader,
+                    copy: &mut raw,
+                };
+                match rmpv::decode::read_value(&mut tee) {
+                    Ok(value) => value,
+                    Err(_) => break,
+                }
+            };
+            // Headers only have names, records have at least a value besides their times
+            let fields = value.as_array().map(Vec::as_slice).unwrap_or_default();
+            let item = if !fields.is_empty() && fields.iter().all(rmpv::Value::is_str) {
+                time = TimeColumn::find(fields.iter().filter_map(rmpv::Value::as_str));
+                Item::Header(raw)
+            } else {
+                Item::Record(time.and_then(|time| time.binary(fields)), raw)
+            };
+            if visit(item).is_break() {
+                break;
+            }
+        }
+        return Ok(());
+    }
+
+    let mut line = String::new();
+    loop {
+        line.clear();
+        // Compressed files may end in the middle of a block, only complete lines are read
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) if !line.ends_with('\n') => break,
+            Ok(_) => {}
+            Err(error) if compression != LogCompression::None => {
+                log::debug!("Log files: {} ends early: {error}", path.display());
+                break;
+            }
+            Err(error) => return Err(error.into()),
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        let item = match format {
+            LogFormat::Csv if line.starts_with("Time") => {
+                time = TimeColumn::find(line.trim_end().split(','));
+                Item::Header(line.clone().into())
+            }
+            LogFormat::Csv => {
+                Item::Record(time.and_then(|time| time.csv(&line)), line.clone().into())
+            }
+            _ => {
+                let time = serde_json::from_str::<serde_json::Value>(&line)
+                    .ok()
+                    .and_then(|package| package["timestamp_us"].as_i64());
+                Item::Record(time, line.clone().into())
+            }
+        };
+        if visit(item).is_break() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Copies what's read from `inner`, to keep the raw bytes of decoded values
+struct TeeReader<'a, R> {
+    inner: R,
+    copy: &'a mut Vec<u8>,
+}
+
+impl<R: Read> Read for TeeReader<'_, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buffer)?;
+        self.copy.extend_from_slice(&buffer[..read]);
+        Ok(read)
+    }
+}
+
+/// Header at the start of a log file. It's empty when a record comes first, like in
+/// JSON Lines files, and `None` when the file has neither.
+pub fn header(path: &Path) -> Result<Option<Vec<u8>>, LogFileError> {
+    let mut header = None;
+    scan(path, |item| {
+        header = Some(match item {
+            Item::Header(raw) => raw,
+            Item::Record(..) => vec![],
+        });
+        ControlFlow::Break(())
+    })?;
+    Ok(header)
+}
+
+fn summary(path: &Path, size: u64, modified: SystemTime) -> Result<Summary, LogFileError> {
+    if let Ok(summaries) = SUMMARIES.lock() {
+        if let Some(&(cached_size, cached_modified, summary)) = summaries.get(path) {
+            if cached_size == size && cached_modified == modified {
+                return Ok(summary);
+            }
+        }
+    }
+
+    let mut summary = Summary::default();
+    scan(path, |item| {
+        if let Item::Record(time, _) = item {
+            summary.add(time);
+        }
+        ControlFlow::Continue(())
+    })?;
+
+    if let Ok(mut summaries) = SUMMARIES.lock() {
+        summaries.insert(path.to_path_buf(), (size, modified, summary));
+    }
+    Ok(summary)
+}
+
+fn rfc3339(time_us: i64) -> Option<String> {
+    DateTime::<Utc>::from_timestamp_micros(time_us)
+        .map(|time| time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true))
+}
+
+/// Log files in `directory`, from the oldest to the newest
+pub fn list(directory: &Path, current: Option<&Path>) -> Result<Vec<LogFileInfo>, LogFileError> {
+    let mut files = vec![];
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        let Some((format, compression)) = kind(&name) else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let modified = metadata.modified()?;
+        // Files being written aren't scanned again, their writers keep their summaries
+        let live = LIVE
+            .lock()
+            .ok()
+            .and_then(|live| live.get(&entry.path()).copied());
+        let scanned = match live {
+            Some(live) => Ok(live),
+            None => summary(&entry.path(), metadata.len(), modified),
+        };
+        let summary = match scanned {
+            Ok(summary) => summary,
+            Err(error) => {
+                log::warn!("Log files: Failed to read {name}: {error}");
+                Summary::default()
+            }
+        };
+        files.push((
+            modified,
+            LogFileInfo {
+                current: current == Some(entry.path().as_path()),
+                name,
+                size: metadata.len(),
+                format,
+                compression,
+                start: summary.start.and_then(rfc3339),
+                end: summary.end.and_then(rfc3339),
+                rows: summary.rows,
+            },
+        ));
+    }
+    files.sort_by(|a, b| (a.0, &a.1.name).cmp(&(b.0, &b.1.name)));
+    Ok(files.into_iter().map(|(_, file)| file).collect())
+}
+
+/// Records of a log file taken between two times
+pub struct Filter {
+    path: PathBuf,
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+impl Filter {
+    pub fn new(path: PathBuf, start: Option<DateTime<Utc>>, end: Option<DateTime<Utc>>) -> Self {
+        Filter {
+            path,
+            start: start.map(|time| time.timestamp_micros()),
+            end: end.map(|time| time.timestamp_micros()),
+        }
+    }
+
+    /// Writes the header and the records in range to `output` as they're read, uncompressed.
+    /// Records without a readable time are left out.
+    pub fn write(&self, output: &mut impl Write) -> Result<(), LogFileError> {
+        let mut result = Ok(());
+        scan(&self.path, |item| {
+            let raw = match item {
+                Item::Header(raw) => raw,
+                Item::Record(Some(time), raw)
+                    if self.start.is_none_or(|start| time >= start)
+                        && self.end.is_none_or(|end| time <= end) =>
+                {
+                    raw
+                }
+                Item::Record(..) => return ControlFlow::Continue(()),
+            };
+            result = output.write_all(&raw);
+            if result.is_err() {
+                return ControlFlow::Break(());
+            }
+            ControlFlow::Continue(())
+        })?;
+        result?;
+        Ok(output.flush()?)
+    }
+}
+
+/// Name of `name` once uncompressed, like `data.csv` for `data.csv.gz`
+pub fn uncompressed_name(name: &str) -> &str {
+    [LogCompression::Gzip, LogCompression::Zstd]
+        .into_iter()
+        .find_map(|compression| name.strip_suffix(compression.extension()))
+        .unwrap_or(name)
+}
+
+/// Deletes the log file `name`, unless the datalogger is writing to it
+pub fn delete(directory: &Path, name: &str, current: Option<&Path>) -> Result<(), LogFileError> {
+    let path = path(directory, name)?;
+    if current.is_some_and(|current| current == path || data_logger::events_path(current) == path) {
+        return Err(LogFileError::Invalid(format!(
+            "{name} is being written, pause the datalogger first"
+        )));
+    }
+    std::fs::remove_file(&path)?;
+    if let Ok(mut summaries) = SUMMARIES.lock() {
+        summaries.remove(&path);
+    }
+    log::info!("Log files: Deleted {}", path.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "Time_Monotonic,Time_Epoch,Pressure\n";
+
+    /// Records one second apart from 1700000000 s, the last one without a readable time
+    fn records(count: usize) -> String {
+        let mut text: String = (0..count)
+            .map(|n| format!("{n}.0,{}.5,101.3\n", 1_700_000_000 + n))
+            .collect();
+        text.push_str("9.0,not a time,101.3\n");
+        text
+    }
+
+    fn filtered(path: &Path, start: Option<i64>, end: Option<i64>) -> String {
+        let time = |seconds| DateTime::<Utc>::from_timestamp(seconds, 0);
+        let filter = Filter::new(path.to_path_buf(), start.and_then(time), end.and_then(time));
+        let mut output = vec![];
+        filter.write(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn lists_and_filters_csv_files() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        // The last line is still being written
+        let text = format!("{HEADER}{}1.0,17000", records(3));
+        std::fs::write(directory.join("data.csv"), &text).unwrap();
+        std::fs::write(directory.join("empty.csv"), "").unwrap();
+        std::fs::write(directory.join("notes.txt"), &text).unwrap();
+
+        let files = list(directory, Some(&directory.join("empty.csv"))).unwrap();
+        assert_eq!(files.len(), 2);
+        let data = files.iter().find(|file| file.name == "data.csv").unwrap();
+        assert_eq!(data.rows, 4);
+        assert_eq!(data.start.as_deref(), Some("2023-11-14T22:13:20.500000Z"));
+        assert_eq!(data.end.as_deref(), Some("2023-11-14T22:13:22.500000Z"));
+        assert!(!data.current);
+        let empty = files.iter().find(|file| file.name == "empty.csv").unwrap();
+        assert_eq!(
+            (empty.rows, empty.start.as_ref(), empty.current),
+            (0, None, true)
+        );
+
+        let path = directory.join("data.csv");
+        assert_eq!(
+            filtered(&path, Some(1_700_000_001), None),
+            format!("{HEADER}1.0,1700000001.5,101.3\n2.0,1700000002.5,101.3\n")
+        );
+        assert_eq!(
+            filtered(&path, None, Some(1_700_000_001)),
+            format!("{HEADER}0.0,1700000000.5,101.3\n")
+        );
+        assert_eq!(filtered(&path, Some(1_800_000_000), None), HEADER);
+        assert_eq!(filtered(&directory.join("empty.csv"), None, None), "");
+    }
+
+    #[test]
+    fn reads_compressed_blocks() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let path = directory.join("data.csv.gz");
+        let mut bytes = vec![];
+        // Each block is a gzip member of its own
+        for block in [HEADER.to_string(), records(2)] {
+            let mut encoder =
+                flate2::write::GzEncoder::new(&mut bytes, flate2::Compression::default());
+            encoder.write_all(block.as_bytes()).unwrap();
+            encoder.finish().unwrap();
+        }
+        std::fs::write(&path, bytes).unwrap();
+
+        let files = list(directory, None).unwrap();
+        assert_eq!(files[0].compression, LogCompression::Gzip);
+        assert_eq!(files[0].rows, 3);
+        assert_eq!(
+            filtered(&path, Some(1_700_000_001), None),
+            format!("{HEADER}1.0,1700000001.5,101.3\n")
+        );
+        let mut text = String::new();
+        csv_reader(&path)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, format!("{HEADER}{}", records(2)));
+    }
+
+    #[test]
+    fn live_summaries_replace_scans() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let path = directory.join("data.csv");
+        std::fs::write(&path, format!("{HEADER}{}", records(1))).unwrap();
+
+        let mut live = LiveSummary::open(&path);
+        assert_eq!(live.summary.rows, 2);
+        // Listed from the writer's counts, without reading the file
+        live.add(Some(1_700_000_100_000_000));
+        live.add(None);
+        let files = list(directory, None).unwrap();
+        assert_eq!(files[0].rows, 4);
+        assert_eq!(files[0].end.as_deref(), Some("2023-11-14T22:15:00.000000Z"));
+
+        // Kept once closed, while the file doesn't change
+        drop(live);
+        assert_eq!(list(directory, None).unwrap()[0].rows, 4);
+        std::fs::write(&path, HEADER).unwrap();
+        assert_eq!(list(directory, None).unwrap()[0].rows, 0);
+    }
+
+    #[test]
+    fn checks_names() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let data = directory.join("data.csv");
+        std::fs::write(&data, HEADER).unwrap();
+        std::fs::write(directory.join("data.events.jsonl"), "").unwrap();
+        std::fs::write(directory.join("data.jsonl"), "").unwrap();
+
+        for name in [
+            "",
+            "../data.csv",
+            ".csv",
+            "sub/data.csv",
+            "notes.txt",
+            "gone.csv",
+        ] {
+            assert!(path(directory, name).is_err(), "{name:?}");
+        }
+        assert_eq!(path(directory, "data.csv").unwrap(), data);
+        assert!(csv_reader(&directory.join("data.jsonl")).is_err());
+        assert_eq!(uncompressed_name("data.csv.zst"), "data.csv");
+
+        assert!(delete(directory, "data.csv", Some(&data)).is_err());
+        assert!(delete(directory, "data.events.jsonl", Some(&data)).is_err());
+        delete(directory, "data.jsonl", Some(&data)).unwrap();
+        delete(directory, "data.csv", None).unwrap();
+        assert!(!data.exists());
+    }
+}
@@ -1,33 +1,69 @@
+mod aliases;
 mod cli;
 mod data_logger;
+mod event_log;
+mod gamepad;
+mod gyro_bias;
 mod hardware_manager;
+mod heading;
+mod lease;
+mod log_files;
 mod logger;
+mod mixer;
+mod mounting;
+mod recorder;
+mod replay;
+mod scripting;
+mod sequencer;
 mod server;
+mod settings;
+mod vertical_speed;
+mod vibration;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let (datalogger_settings, monitor_settings, server_settings) = cli::parse_args();
+    let (datalogger_settings, monitor_settings, server_settings, storage_settings, replay_settings) =
+        cli::parse_args();
 
     logger::init();
-    log::info!("Starting navigator webservice with: {datalogger_settings:?} {monitor_settings:?} {server_settings:?}",);
+    log::info!("Starting navigator webservice with: {datalogger_settings:?} {monitor_settings:?} {server_settings:?} {storage_settings:?} {replay_settings:?}",);
 
-    hardware_manager::init();
+    settings::init(storage_settings.directory.into());
+    aliases::init();
+    mounting::init();
+    mixer::init();
+    gamepad::init();
+    sequencer::init();
+    lease::init(server_settings.admin_key.clone());
 
-    if monitor_settings.interval != 0 {
-        log::info!("starting monitor...");
-        hardware_manager::init_monitor(monitor_settings.interval);
+    if let Some(file) = replay_settings.file {
+        let status =
+            replay::load(file.as_ref()).and_then(|_| replay::play(Some(replay_settings.speed)));
+        if let Err(error) = status {
+            log::error!("Replay: {error}");
+        }
     }
 
-    if datalogger_settings.interval != 0 {
-        log::info!("starting datalogger...");
-        hardware_manager::init_datalogger(
-            datalogger_settings.interval,
-            format!(
-                "{}/{}",
-                datalogger_settings.directory, datalogger_settings.filename
-            )
-            .into(),
-        );
+    // The server keeps running with the available devices, the remaining ones are retried later
+    if let Err(error) = hardware_manager::init() {
+        log::error!("{error}");
+    }
+
+    hardware_manager::init_arming();
+
+    if let Err(error) = hardware_manager::init_recovery() {
+        log::error!("{error}");
+    }
+
+    // Both services are always started, so they can be enabled or reconfigured while running
+    log::info!("starting monitor...");
+    if let Err(error) = hardware_manager::init_monitor(monitor_settings) {
+        log::error!("{error}");
+    }
+
+    log::info!("starting datalogger...");
+    if let Err(error) = hardware_manager::init_datalogger(datalogger_settings) {
+        log::error!("{error}");
     }
 
     server::run(server_settings.port).await.unwrap();
@@ -0,0 +1,300 @@
+use crate::hardware_manager::PwmChannel;
+use crate::settings;
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+const SETTINGS_NAME: &str = "mixer";
+
+/// Degrees of freedom of a motion command, in the order of the thrusters' factors
+const AXES: usize = 6;
+
+/// Vehicle frames, for a frame with x forward, y right and z down.
+/// Positive commands move forward, right and down, and roll right, pitch up and yaw right.
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum Frame {
+    /// BlueROV2: four vectored horizontal thrusters, front right, front left, rear right and
+    /// rear left, then the right and left vertical thrusters
+    #[default]
+    Vectored,
+    /// BlueROV2 Heavy: the vectored horizontal thrusters, then four vertical thrusters at the
+    /// front right, front left, rear right and rear left
+    Vectored6Dof,
+    /// Uses the factors of each thruster
+    Custom,
+}
+
+impl Frame {
+    /// Surge, sway, heave, roll, pitch and yaw factors of each thruster, `None` for custom frames
+    fn factors(&self) -> Option<Vec<[f32; AXES]>> {
+        let horizontal = [
+            [1.0, -1.0, 0.0, 0.0, 0.0, -1.0],
+            [1.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+            [1.0, 1.0, 0.0, 0.0, 0.0, -1.0],
+            [1.0, -1.0, 0.0, 0.0, 0.0, 1.0],
+        ];
+        let vertical = match self {
+            Self::Vectored => vec![
+                [0.0, 0.0, 1.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, -1.0, 0.0, 0.0],
+            ],
+            Self::Vectored6Dof => vec![
+                [0.0, 0.0, 1.0, 1.0, -1.0, 0.0],
+                [0.0, 0.0, 1.0, -1.0, -1.0, 0.0],
+                [0.0, 0.0, 1.0, 1.0, 1.0, 0.0],
+                [0.0, 0.0, 1.0, -1.0, 1.0, 0.0],
+            ],
+            Self::Custom => return None,
+        };
+        Some(horizontal.into_iter().chain(vertical).collect())
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct Thruster {
+    pub channel: PwmChannel,
+    /// Surge, sway, heave, roll, pitch and yaw factors, replaced by the frame's unless `Custom`
+    #[serde(default)]
+    pub factors: Vec<f32>,
+    /// Inverts the thrust direction, for propellers or motors spinning the other way
+    #[serde(default)]
+    pub reversed: bool,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct MixerSettings {
+    pub frame: Frame,
+    pub thrusters: Vec<Thruster>,
+    /// Pulse width that stops the thrusters, in microseconds
+    pub neutral: f32,
+    /// Pulse width variation at full thrust, in microseconds
+    pub range: f32,
+}
+
+impl Default for MixerSettings {
+    fn default() -> Self {
+        Self::with_frame(Frame::default())
+    }
+}
+
+const CHANNELS: [PwmChannel; 16] = [
+    PwmChannel::Ch1,
+    PwmChannel::Ch2,
+    PwmChannel::Ch3,
+    PwmChannel::Ch4,
+    PwmChannel::Ch5,
+    PwmChannel::Ch6,
+    PwmChannel::Ch7,
+    PwmChannel::Ch8,
+    PwmChannel::Ch9,
+    PwmChannel::Ch10,
+    PwmChannel::Ch11,
+    PwmChannel::Ch12,
+    PwmChannel::Ch13,
+    PwmChannel::Ch14,
+    PwmChannel::Ch15,
+    PwmChannel::Ch16,
+];
+
+impl MixerSettings {
+    /// Settings of a frame, with its thrusters on the first channels
+    pub fn with_frame(frame: Frame) -> Self {
+        let thrusters = frame
+            .factors()
+            .unwrap_or_default()
+            .into_iter()
+            .zip(CHANNELS)
+            .map(|(factors, channel)| Thruster {
+                channel,
+                factors: factors.to_vec(),
+                reversed: false,
+            })
+            .collect();
+        Self {
+            frame,
+            thrusters,
+            neutral: 1500.0,
+            range: 400.0,
+        }
+    }
+
+    /// Fills the thrusters' factors from the frame, and checks that they are usable
+    pub fn resolve(mut self) -> Result<Self, String> {
+        if let Some(factors) = self.frame.factors() {
+            if factors.len() != self.thrusters.len() {
+                return Err(format!(
+                    "{:?} frame requires {} thrusters",
+                    self.frame,
+                    factors.len()
+                ));
+            }
+            for (thruster, factors) in self.thrusters.iter_mut().zip(factors) {
+                thruster.factors = factors.to_vec();
+            }
+        }
+
+        for (index, thruster) in self.thrusters.iter().enumerate() {
+            if matches!(thruster.channel, PwmChannel::All) {
+                return Err(format!("Thruster {} requires a single channel", index + 1));
+            }
+            if thruster.factors.len() != AXES {
+                return Err(format!("Thruster {} requires {AXES} factors", index + 1));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Pulse width of each thruster, in microseconds, for a surge, sway, heave, roll, pitch and
+    /// yaw command between -1 and 1.
+    /// Saturated outputs are scaled down together, keeping the direction of the motion.
+    fn mix(&self, command: [f32; AXES]) -> Vec<(PwmChannel, f32)> {
+        let outputs: Vec<f32> = self
+            .thrusters
+            .iter()
+            .map(|thruster| {
+                thruster
+                    .factors
+                    .iter()
+                    .zip(command)
+                    .map(|(factor, command)| factor * command)
+                    .sum()
+            })
+            .collect();
+
+        let peak = outputs
+            .iter()
+            .fold(1.0_f32, |peak, output| peak.max(output.abs()));
+
+        self.thrusters
+            .iter()
+            .zip(outputs)
+            .map(|(thruster, output)| {
+                let direction = if thruster.reversed { -1.0 } else { 1.0 };
+                let pulse = self.neutral + direction * output / peak * self.range;
+                (thruster.channel.clone(), pulse)
+            })
+            .collect()
+    }
+}
+
+lazy_static! {
+    static ref SETTINGS: RwLock<MixerSettings> = Default::default();
+}
+
+/// Loads the saved mixer settings
+pub fn init() {
+    let Some(settings) = settings::load::<MixerSettings>(SETTINGS_NAME) else {
+        return;
+    };
+    match settings.resolve() {
+        Ok(settings) => {
+            log::info!("Mixer: using {:?} frame", settings.frame);
+            if let Ok(mut current) = SETTINGS.write() {
+                *current = settings;
+            }
+        }
+        Err(error) => log::warn!("Mixer: ignoring the saved settings: {error}"),
+    }
+}
+
+/// Pulse width of each thruster, in microseconds, as mixed by the current settings
+pub fn mix(command: [f32; AXES]) -> Vec<(PwmChannel, f32)> {
+    settings().mix(command)
+}
+
+pub fn settings() -> MixerSettings {
+    SETTINGS
+        .read()
+        .map(|settings| settings.clone())
+        .unwrap_or_default()
+}
+
+/// Applies and saves the mixer settings, which should be resolved
+pub fn set_settings(settings: MixerSettings) -> std::io::Result<()> {
+    settings::save(SETTINGS_NAME, &settings)?;
+    if let Ok(mut current) = SETTINGS.write() {
+        *current = settings;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pulses(settings: &MixerSettings, command: [f32; AXES]) -> Vec<f32> {
+        settings
+            .mix(command)
+            .into_iter()
+            .map(|(_, pulse)| pulse)
+            .collect()
+    }
+
+    #[test]
+    fn mixes_the_vectored_frame() {
+        let settings = MixerSettings::default();
+        assert_eq!(pulses(&settings, [0.0; AXES]), vec![1500.0; 6]);
+        assert_eq!(
+            pulses(&settings, [1.0, 0.0, 0.0, 0.0, 0.0, 0.0]),
+            vec![1900.0, 1900.0, 1900.0, 1900.0, 1500.0, 1500.0]
+        );
+        assert_eq!(
+            pulses(&settings, [0.0, 0.0, -0.5, 0.0, 0.0, 0.0]),
+            vec![1500.0, 1500.0, 1500.0, 1500.0, 1300.0, 1300.0]
+        );
+        let channels: Vec<PwmChannel> = settings
+            .mix([0.0; AXES])
+            .into_iter()
+            .map(|(channel, _)| channel)
+            .collect();
+        assert_eq!(channels, CHANNELS[..6].to_vec());
+    }
+
+    #[test]
+    fn scales_saturated_outputs_together() {
+        let mut settings = MixerSettings::default();
+        // Surge and yaw add up to twice the range on two thrusters
+        assert_eq!(
+            pulses(&settings, [1.0, 0.0, 0.0, 0.0, 0.0, -1.0]),
+            vec![1900.0, 1500.0, 1900.0, 1500.0, 1500.0, 1500.0]
+        );
+
+        settings.thrusters[0].reversed = true;
+        assert_eq!(
+            pulses(&settings, [0.5, 0.0, 0.0, 0.0, 0.0, 0.0])[..2],
+            [1300.0, 1700.0]
+        );
+    }
+
+    #[test]
+    fn resolves_the_frame_factors() {
+        let mut settings = MixerSettings::with_frame(Frame::Vectored6Dof);
+        assert_eq!(settings.thrusters.len(), 8);
+        settings.thrusters[0].factors.clear();
+        let resolved = settings.clone().resolve().unwrap();
+        assert_eq!(resolved.thrusters[0].factors.len(), AXES);
+
+        settings.thrusters.pop();
+        let error = settings.resolve().unwrap_err();
+        assert!(error.contains("requires 8 thrusters"), "{error}");
+
+        let custom = MixerSettings {
+            thrusters: vec![Thruster {
+                channel: PwmChannel::Ch3,
+                factors: vec![1.0; 3],
+                reversed: false,
+            }],
+            ..MixerSettings::with_frame(Frame::Custom)
+        };
+        assert!(custom.clone().resolve().is_err());
+        let mut all = custom.clone();
+        all.thrusters[0].channel = PwmChannel::All;
+        all.thrusters[0].factors = vec![0.0; AXES];
+        assert!(all.resolve().is_err());
+
+        // Custom frames start without thrusters, and mix nothing
+        let empty = MixerSettings::with_frame(Frame::Custom).resolve().unwrap();
+        assert!(empty.mix([1.0; AXES]).is_empty());
+    }
+}
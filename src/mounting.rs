@@ -0,0 +1,245 @@
+use crate::settings;
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::sync::RwLock;
+
+const SETTINGS_NAME: &str = "mounting";
+
+/// Board rotations relative to the vehicle, following ArduPilot's `ROTATION_*` presets.
+/// Named rotations are applied as roll, then pitch, then yaw.
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Yaw45,
+    Yaw90,
+    Yaw135,
+    Yaw180,
+    Yaw225,
+    Yaw270,
+    Yaw315,
+    Roll180,
+    Roll180Yaw45,
+    Roll180Yaw90,
+    Roll180Yaw135,
+    Pitch180,
+    Roll180Yaw225,
+    Roll180Yaw270,
+    Roll180Yaw315,
+    Roll90,
+    Roll90Yaw45,
+    Roll90Yaw90,
+    Roll90Yaw135,
+    Roll270,
+    Roll270Yaw45,
+    Roll270Yaw90,
+    Roll270Yaw135,
+    Pitch90,
+    Pitch270,
+    Pitch180Yaw90,
+    Pitch180Yaw270,
+    Roll90Pitch90,
+    Roll180Pitch90,
+    Roll270Pitch90,
+    Roll90Pitch180,
+    Roll270Pitch180,
+    Roll90Pitch270,
+    Roll180Pitch270,
+    Roll270Pitch270,
+    Roll90Pitch180Yaw90,
+    Roll90Yaw270,
+    /// Uses the custom roll, pitch and yaw angles
+    Custom,
+}
+
+impl Rotation {
+    /// Roll, pitch and yaw of the preset in degrees, `None` for custom rotations
+    fn angles(&self) -> Option<[f32; 3]> {
+        let angles = match self {
+            Self::None => [0.0, 0.0, 0.0],
+            Self::Yaw45 => [0.0, 0.0, 45.0],
+            Self::Yaw90 => [0.0, 0.0, 90.0],
+            Self::Yaw135 => [0.0, 0.0, 135.0],
+            Self::Yaw180 => [0.0, 0.0, 180.0],
+            Self::Yaw225 => [0.0, 0.0, 225.0],
+            Self::Yaw270 => [0.0, 0.0, 270.0],
+            Self::Yaw315 => [0.0, 0.0, 315.0],
+            Self::Roll180 => [180.0, 0.0, 0.0],
+            Self::Roll180Yaw45 => [180.0, 0.0, 45.0],
+            Self::Roll180Yaw90 => [180.0, 0.0, 90.0],
+            Self::Roll180Yaw135 => [180.0, 0.0, 135.0],
+            Self::Pitch180 => [0.0, 180.0, 0.0],
+            Self::Roll180Yaw225 => [180.0, 0.0, 225.0],
+            Self::Roll180Yaw270 => [180.0, 0.0, 270.0],
+            Self::Roll180Yaw315 => [180.0, 0.0, 315.0],
+            Self::Roll90 => [90.0, 0.0, 0.0],
+            Self::Roll90Yaw45 => [90.0, 0.0, 45.0],
+            Self::Roll90Yaw90 => [90.0, 0.0, 90.0],
+            Self::Roll90Yaw135 => [90.0, 0.0, 135.0],
+            Self::Roll270 => [270.0, 0.0, 0.0],
+            Self::Roll270Yaw45 => [270.0, 0.0, 45.0],
+            Self::Roll270Yaw90 => [270.0, 0.0, 90.0],
+            Self::Roll270Yaw135 => [270.0, 0.0, 135.0],
+            Self::Pitch90 => [0.0, 90.0, 0.0],
+            Self::Pitch270 => [0.0, 270.0, 0.0],
+            Self::Pitch180Yaw90 => [0.0, 180.0, 90.0],
+            Self::Pitch180Yaw270 => [0.0, 180.0, 270.0],
+            Self::Roll90Pitch90 => [90.0, 90.0, 0.0],
+            Self::Roll180Pitch90 => [180.0, 90.0, 0.0],
+            Self::Roll270Pitch90 => [270.0, 90.0, 0.0],
+            Self::Roll90Pitch180 => [90.0, 180.0, 0.0],
+            Self::Roll270Pitch180 => [270.0, 180.0, 0.0],
+            Self::Roll90Pitch270 => [90.0, 270.0, 0.0],
+            Self::Roll180Pitch270 => [180.0, 270.0, 0.0],
+            Self::Roll270Pitch270 => [270.0, 270.0, 0.0],
+            Self::Roll90Pitch180Yaw90 => [90.0, 180.0, 90.0],
+            Self::Roll90Yaw270 => [90.0, 0.0, 270.0],
+            Self::Custom => return None,
+        };
+        Some(angles)
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct MountingSettings {
+    pub rotation: Rotation,
+    /// Custom roll in degrees, used with the `Custom` rotation
+    pub roll: f32,
+    /// Custom pitch in degrees, used with the `Custom` rotation
+    pub pitch: f32,
+    /// Custom yaw in degrees, used with the `Custom` rotation
+    pub yaw: f32,
+}
+
+impl MountingSettings {
+    /// Rotation matrix from the board to the vehicle frame
+    fn matrix(&self) -> [[f32; 3]; 3] {
+        let [roll, pitch, yaw] = self
+            .rotation
+            .angles()
+            .unwrap_or([self.roll, self.pitch, self.yaw])
+            .map(f32::to_radians);
+        let (sr, cr) = roll.sin_cos();
+        let (sp, cp) = pitch.sin_cos();
+        let (sy, cy) = yaw.sin_cos();
+
+        [
+            [cp * cy, sr * sp * cy - cr * sy, cr * sp * cy + sr * sy],
+            [cp * sy, sr * sp * sy + cr * cy, cr * sp * sy - sr * cy],
+            [-sp, sr * cp, cr * cp],
+        ]
+    }
+}
+
+struct Mounting {
+    settings: MountingSettings,
+    matrix: [[f32; 3]; 3],
+}
+
+impl From<MountingSettings> for Mounting {
+    fn from(settings: MountingSettings) -> Self {
+        Self {
+            matrix: settings.matrix(),
+            settings,
+        }
+    }
+}
+
+lazy_static! {
+    static ref MOUNTING: RwLock<Mounting> = RwLock::new(MountingSettings::default().into());
+}
+
+/// Loads the saved mounting settings
+pub fn init() {
+    if let Some(settings) = settings::load::<MountingSettings>(SETTINGS_NAME) {
+        log::info!("Mounting: using {settings:?}");
+        if let Ok(mut mounting) = MOUNTING.write() {
+            *mounting = settings.into();
+        }
+    }
+}
+
+/// Rotates a board vector into the vehicle frame
+pub fn rotate(vector: [f32; 3]) -> [f32; 3] {
+    let Ok(mounting) = MOUNTING.read() else {
+        return vector;
+    };
+    mounting
+        .matrix
+        .map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+pub fn settings() -> MountingSettings {
+    MOUNTING
+        .read()
+        .map(|mounting| mounting.settings.clone())
+        .unwrap_or_default()
+}
+
+/// Applies and saves the mounting settings
+pub fn set_settings(settings: MountingSettings) -> std::io::Result<()> {
+    settings::save(SETTINGS_NAME, &settings)?;
+    if let Ok(mut mounting) = MOUNTING.write() {
+        *mounting = settings.into();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(settings: MountingSettings, vector: [f32; 3]) -> [f32; 3] {
+        let mounting = Mounting::from(settings);
+        mounting
+            .matrix
+            .map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+    }
+
+    fn preset(rotation: Rotation) -> MountingSettings {
+        MountingSettings {
+            rotation,
+            ..Default::default()
+        }
+    }
+
+    fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+        for (actual_value, expected_value) in actual.iter().zip(expected) {
+            assert!(
+                (actual_value - expected_value).abs() < 1e-6,
+                "{actual:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn presets_match_ardupilot() {
+        let vector = [1.0, 2.0, 3.0];
+        assert_close(apply(preset(Rotation::None), vector), [1.0, 2.0, 3.0]);
+        assert_close(apply(preset(Rotation::Yaw90), vector), [-2.0, 1.0, 3.0]);
+        assert_close(apply(preset(Rotation::Roll180), vector), [1.0, -2.0, -3.0]);
+        assert_close(apply(preset(Rotation::Pitch90), vector), [3.0, 2.0, -1.0]);
+        assert_close(
+            apply(preset(Rotation::Roll90Yaw90), vector),
+            [3.0, 1.0, 2.0],
+        );
+    }
+
+    #[test]
+    fn custom_angles_only_apply_to_custom() {
+        let custom = MountingSettings {
+            rotation: Rotation::Custom,
+            roll: 0.0,
+            pitch: 0.0,
+            yaw: -90.0,
+        };
+        assert_close(apply(custom.clone(), [1.0, 2.0, 3.0]), [2.0, -1.0, 3.0]);
+
+        let preset = MountingSettings {
+            rotation: Rotation::Yaw180,
+            ..custom
+        };
+        assert_close(apply(preset, [1.0, 2.0, 3.0]), [-1.0, -2.0, 3.0]);
+    }
+}
@@ -0,0 +1,204 @@
+use crate::hardware_manager::{PwmChannel, UserLed};
+use crate::sequencer::{self, Sequence, SequenceError, SequenceState, Step, Target};
+use crate::settings;
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Macros are stored in this folder, inside the settings directory
+const DIRECTORY: &str = "macros";
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct MacroStatus {
+    pub name: String,
+    pub state: SequenceState,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RecordingStatus {
+    /// Macro being recorded, `None` while not recording
+    pub name: Option<String>,
+    /// Commands recorded so far
+    pub commands: usize,
+    /// Time since the recording started, in seconds
+    pub duration: f64,
+}
+
+struct Recording {
+    name: String,
+    start: Instant,
+    steps: Vec<Step>,
+}
+
+lazy_static! {
+    static ref RECORDING: Mutex<Option<Recording>> = Default::default();
+}
+
+fn check_name(name: &str) -> Result<(), SequenceError> {
+    settings::check_name("Macro", name).map_err(SequenceError::Invalid)
+}
+
+fn settings_name(name: &str) -> String {
+    format!("{DIRECTORY}/{name}")
+}
+
+/// Macros are replayed by the sequencer, apart from the sequences
+fn runner_key(name: &str) -> String {
+    format!("{DIRECTORY}/{name}")
+}
+
+fn status(recording: Option<&Recording>) -> RecordingStatus {
+    recording
+        .map(|recording| RecordingStatus {
+            name: Some(recording.name.clone()),
+            commands: recording.steps.len(),
+            duration: recording.start.elapsed().as_secs_f64(),
+        })
+        .unwrap_or_default()
+}
+
+fn record(target: Target, fill: impl FnOnce(&mut Step)) {
+    let Ok(mut recording) = RECORDING.lock() else {
+        return;
+    };
+    let Some(recording) = recording.as_mut() else {
+        return;
+    };
+
+    let mut step = Step {
+        time: recording.start.elapsed().as_secs_f64(),
+        target,
+        channel: None,
+        value: None,
+        ramp: 0.0,
+        from: None,
+        led: None,
+        frequency: None,
+        state: None,
+        color: None,
+    };
+    fill(&mut step);
+    recording.steps.push(step);
+}
+
+pub fn record_pwm(channel: PwmChannel, value: u16) {
+    record(Target::Pwm, |step| {
+        step.channel = Some(channel);
+        step.value = Some(value);
+    });
+}
+
+pub fn record_pwm_enable(state: bool) {
+    record(Target::PwmEnable, |step| step.state = Some(state));
+}
+
+pub fn record_pwm_frequency(frequency: f32) {
+    record(Target::PwmFrequency, |step| {
+        step.frequency = Some(frequency)
+    });
+}
+
+pub fn record_led(led: UserLed, state: bool) {
+    record(Target::UserLed, |step| {
+        step.led = Some(led);
+        step.state = Some(state);
+    });
+}
+
+pub fn record_neopixel(color: [u8; 3]) {
+    record(Target::NeoPixel, |step| step.color = Some(color.to_vec()));
+}
+
+/// Starts recording the output commands into a new macro
+pub fn start_recording(name: &str) -> Result<RecordingStatus, SequenceError> {
+    check_name(name)?;
+    let mut recording = RECORDING
+        .lock()
+        .map_err(|error| SequenceError::Invalid(error.to_string()))?;
+    if let Some(current) = recording.as_ref() {
+        return Err(SequenceError::Invalid(format!(
+            "Macro {} is already being recorded",
+            current.name
+        )));
+    }
+    *recording = Some(Recording {
+        name: name.to_string(),
+        start: Instant::now(),
+        steps: vec![],
+    });
+    Ok(status(recording.as_ref()))
+}
+
+/// Stops the recording and saves the macro
+pub fn stop_recording() -> Result<Sequence, SequenceError> {
+    let recording = RECORDING
+        .lock()
+        .map_err(|error| SequenceError::Invalid(error.to_string()))?
+        .take()
+        .ok_or_else(|| SequenceError::Invalid("No macro is being recorded".to_string()))?;
+
+    let sequence = Sequence {
+        steps: recording.steps,
+        repeat: false,
+    };
+    settings::save(&settings_name(&recording.name), &sequence)?;
+    log::info!(
+        "Recorder: saved macro {} with {} commands",
+        recording.name,
+        sequence.steps.len()
+    );
+    Ok(sequence)
+}
+
+pub fn recording() -> RecordingStatus {
+    RECORDING
+        .lock()
+        .map(|recording| status(recording.as_ref()))
+        .unwrap_or_default()
+}
+
+pub fn list() -> Result<Vec<MacroStatus>, SequenceError> {
+    let names = settings::names(DIRECTORY, "json")?;
+
+    Ok(names
+        .into_iter()
+        .map(|name| MacroStatus {
+            state: sequencer::runner_state(&runner_key(&name)),
+            name,
+        })
+        .collect())
+}
+
+pub fn get(name: &str) -> Result<Sequence, SequenceError> {
+    check_name(name)?;
+    settings::load::<Sequence>(&settings_name(name))
+        .ok_or_else(|| SequenceError::Invalid(format!("Macro {name} does not exist")))
+}
+
+pub fn delete(name: &str) -> Result<(), SequenceError> {
+    get(name)?;
+    // A macro being replayed keeps running until it's done
+    settings::remove(&settings_name(name))?;
+    Ok(())
+}
+
+/// Replays the macro, `speed` times faster than it was recorded
+pub fn play(name: &str, speed: f64) -> Result<MacroStatus, SequenceError> {
+    let sequence = get(name)?;
+    let key = runner_key(name);
+    sequencer::play(&key, sequence, speed)?;
+    Ok(MacroStatus {
+        name: name.to_string(),
+        state: sequencer::runner_state(&key),
+    })
+}
+
+pub fn stop(name: &str) -> Result<MacroStatus, SequenceError> {
+    check_name(name)?;
+    Ok(MacroStatus {
+        name: name.to_string(),
+        state: sequencer::halt(&runner_key(name))?,
+    })
+}
@@ -0,0 +1,466 @@
+use crate::data_logger::{self, Column, Timestamp};
+use crate::log_files::{self, LogFileError};
+use lazy_static::lazy_static;
+use navigator_rs::{ADCData, AxisData, SensorData};
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ReplayState {
+    /// No file is loaded, the monitor reads the navigator
+    #[default]
+    Idle,
+    Playing,
+    Paused,
+    /// The end of the file was reached, its last sample is held
+    Finished,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ReplayStatus {
+    pub file: Option<String>,
+    pub state: ReplayState,
+    /// Playback rate relative to the recording
+    pub speed: f64,
+    /// Time since the first sample, in seconds
+    pub position: f64,
+    /// Time between the first and last samples, in seconds
+    pub duration: f64,
+    pub samples: usize,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReplayError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+impl From<LogFileError> for ReplayError {
+    fn from(error: LogFileError) -> Self {
+        match error {
+            LogFileError::Invalid(message) => Self::Invalid(message),
+            LogFileError::Io(error) => Self::Io(error),
+        }
+    }
+}
+
+/// Recorded sample, replacing a monitor's reading
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    /// Time since the first sample, in seconds
+    time: f64,
+    pub state: SensorData,
+    pub vertical_speed: Option<f32>,
+}
+
+struct Replay {
+    file: PathBuf,
+    samples: Vec<Sample>,
+    state: ReplayState,
+    speed: f64,
+    /// Position when playback was last started, paused or moved
+    origin: f64,
+    since: Instant,
+}
+
+impl Replay {
+    fn duration(&self) -> f64 {
+        self.samples.last().map(|sample| sample.time).unwrap_or(0.0)
+    }
+
+    fn position(&self) -> f64 {
+        let position = match self.state {
+            ReplayState::Playing => self.origin + self.since.elapsed().as_secs_f64() * self.speed,
+            _ => self.origin,
+        };
+        position.min(self.duration())
+    }
+
+    /// Moves playback to `position`, keeping its state
+    fn anchor(&mut self, position: f64) {
+        self.origin = position;
+        self.since = Instant::now();
+    }
+
+    fn status(&self) -> ReplayStatus {
+        ReplayStatus {
+            file: Some(self.file.display().to_string()),
+            state: self.state,
+            speed: self.speed,
+            position: self.position(),
+            duration: self.duration(),
+            samples: self.samples.len(),
+        }
+    }
+}
+
+lazy_static! {
+    static ref REPLAY: Mutex<Option<Replay>> = Default::default();
+}
+
+fn lock() -> Result<MutexGuard<'static, Option<Replay>>, ReplayError> {
+    REPLAY
+        .lock()
+        .map_err(|error| ReplayError::Invalid(error.to_string()))
+}
+
+/// Names of the columns of a file, from its header
+struct Header {
+    names: Vec<String>,
+    /// Files written before the header was fixed have none, and may miss the vertical speed
+    legacy: bool,
+    /// Column the samples are timed by, wall-clock times are preferred as they carry on
+    /// across service restarts
+    time: Option<(usize, Timestamp)>,
+}
+
+impl Header {
+    fn new(names: Vec<String>, legacy: bool) -> Self {
+        let time = [
+            Timestamp::Epoch,
+            Timestamp::Utc,
+            Timestamp::Local,
+            Timestamp::Monotonic,
+        ]
+        .into_iter()
+        .find_map(|timestamp| {
+            let index = names.iter().position(|name| name == timestamp.name())?;
+            Some((index, timestamp))
+        });
+        Self {
+            names,
+            legacy,
+            time,
+        }
+    }
+
+    fn legacy() -> Self {
+        Self::new(
+            data_logger::header_names(&[Timestamp::Local], &Column::defaults()),
+            true,
+        )
+    }
+
+    fn parse(line: &str) -> Self {
+        let names = line
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect();
+        Self::new(names, false)
+    }
+}
+
+/// Sample read from a line, with its time in microseconds
+struct Row {
+    time: i64,
+    /// Local times only have a one second resolution
+    coarse: bool,
+    state: SensorData,
+    vertical_speed: Option<f32>,
+}
+
+fn parse_line(header: &Header, line: &str) -> Result<Row, String> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let columns = header.names.len();
+    if fields.len() != columns && !(header.legacy && fields.len() == columns - 1) {
+        return Err(format!("expected {columns} columns, got {}", fields.len()));
+    }
+    let Some((index, timestamp)) = header.time else {
+        return Err("file has no time column".to_string());
+    };
+    let time = timestamp
+        .parse(fields[index])
+        .ok_or_else(|| format!("invalid time {:?}", fields[index]))?;
+
+    // Columns that weren't logged are left at zero
+    let field = |name: &str| {
+        header
+            .names
+            .iter()
+            .position(|column| column == name)
+            .and_then(|index| fields.get(index))
+            .filter(|field| !field.is_empty())
+    };
+    let number = |name: &str| {
+        field(name)
+            .map(|field| field.parse::<f32>())
+            .transpose()
+            .map(Option::unwrap_or_default)
+            .map_err(|error| format!("invalid {name}: {error}"))
+    };
+    let axis = |prefix: &str| {
+        Ok::<_, String>(AxisData {
+            x: number(&format!("{prefix}_X"))?,
+            y: number(&format!("{prefix}_Y"))?,
+            z: number(&format!("{prefix}_Z"))?,
+        })
+    };
+
+    let state = SensorData {
+        adc: ADCData {
+            channel: [
+                number("ADC_Ch1")?,
+                number("ADC_Ch2")?,
+                number("ADC_Ch3")?,
+                number("ADC_Ch4")?,
+            ],
+        },
+        temperature: number("Temperature")?,
+        pressure: number("Pressure")?,
+        accelerometer: axis("Acc")?,
+        magnetometer: axis("Mag")?,
+        gyro: axis("Gyro")?,
+        leak: number("Leak")? != 0.0,
+    };
+
+    // The headings are computed again from the accelerometer and magnetometer
+    let vertical_speed = field("Vertical_Speed")
+        .map(|field| field.parse())
+        .transpose()
+        .map_err(|error| format!("invalid vertical speed: {error}"))?;
+    Ok(Row {
+        time,
+        coarse: timestamp == Timestamp::Local,
+        state,
+        vertical_speed,
+    })
+}
+
+/// Reads a datalogger file. Local times have a one second resolution,
+/// so the samples logged within the same second are spread evenly over it.
+fn parse(content: &str) -> Result<Vec<Sample>, ReplayError> {
+    let mut rows = vec![];
+    let mut header = Header::legacy();
+    for (number, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if line.starts_with("Time") {
+            header = Header::parse(line);
+            continue;
+        }
+        let row = parse_line(&header, line)
+            .map_err(|error| ReplayError::Invalid(format!("Line {}: {error}", number + 1)))?;
+        rows.push(row);
+    }
+    let Some(first) = rows.first().map(|row| row.time) else {
+        return Err(ReplayError::Invalid("File has no samples".to_string()));
+    };
+
+    let mut samples = Vec::with_capacity(rows.len());
+    for group in rows.chunk_by(|a, b| a.coarse && b.coarse && a.time == b.time) {
+        let start = (group[0].time - first) as f64 / 1e6;
+        for (index, row) in group.iter().enumerate() {
+            let spread = if row.coarse {
+                index as f64 / group.len() as f64
+            } else {
+                0.0
+            };
+            samples.push(Sample {
+                time: start + spread,
+                state: row.state,
+                vertical_speed: row.vertical_speed,
+            });
+        }
+    }
+    if samples.windows(2).any(|pair| pair[1].time < pair[0].time) {
+        return Err(ReplayError::Invalid(
+            "File times must not go backwards".to_string(),
+        ));
+    }
+    Ok(samples)
+}
+
+/// Loads a CSV datalogger file, compressed or not, paused at its first sample
+pub fn load(file: &Path) -> Result<ReplayStatus, ReplayError> {
+    let mut content = String::new();
+    log_files::csv_reader(file)?.read_to_string(&mut content)?;
+    let samples = parse(&content)?;
+    log::info!(
+        "Replay: loaded {} samples from {}",
+        samples.len(),
+        file.display()
+    );
+
+    let replay = Replay {
+        file: file.to_path_buf(),
+        samples,
+        state: ReplayState::Paused,
+        speed: 1.0,
+        origin: 0.0,
+        since: Instant::now(),
+    };
+    let status = replay.status();
+    *lock()? = Some(replay);
+    Ok(status)
+}
+
+/// Unloads the file, the monitor reads the navigator again
+pub fn stop() -> Result<ReplayStatus, ReplayError> {
+    if lock()?.take().is_some() {
+        log::info!("Replay: stopped, back to live readings");
+    }
+    Ok(ReplayStatus::default())
+}
+
+fn with_replay(
+    operation: impl FnOnce(&mut Replay) -> Result<(), ReplayError>,
+) -> Result<ReplayStatus, ReplayError> {
+    let mut replay = lock()?;
+    let replay = replay
+        .as_mut()
+        .ok_or_else(|| ReplayError::Invalid("No file is loaded".to_string()))?;
+    operation(replay)?;
+    Ok(replay.status())
+}
+
+/// Plays at `speed` times the recorded rate, or the previous one if `None`.
+/// Finished replays start over.
+pub fn play(speed: Option<f64>) -> Result<ReplayStatus, ReplayError> {
+    with_replay(|replay| {
+        let position = match replay.state {
+            ReplayState::Finished => 0.0,
+            _ => replay.position(),
+        };
+        replay.state = ReplayState::Playing;
+        replay.speed = speed.unwrap_or(replay.speed);
+        replay.anchor(position);
+        Ok(())
+    })
+}
+
+pub fn pause() -> Result<ReplayStatus, ReplayError> {
+    with_replay(|replay| {
+        let position = replay.position();
+        replay.state = ReplayState::Paused;
+        replay.anchor(position);
+        Ok(())
+    })
+}
+
+/// Moves to `position` seconds after the first sample
+pub fn seek(position: f64) -> Result<ReplayStatus, ReplayError> {
+    with_replay(|replay| {
+        if position > replay.duration() {
+            return Err(ReplayError::Invalid(format!(
+                "Position must be within the file's {} s",
+                replay.duration()
+            )));
+        }
+        if replay.state == ReplayState::Finished {
+            replay.state = ReplayState::Paused;
+        }
+        replay.anchor(position);
+        Ok(())
+    })
+}
+
+pub fn status() -> ReplayStatus {
+    REPLAY
+        .lock()
+        .ok()
+        .and_then(|replay| replay.as_ref().map(Replay::status))
+        .unwrap_or_default()
+}
+
+pub fn is_active() -> bool {
+    REPLAY.lock().is_ok_and(|replay| replay.is_some())
+}
+
+/// Sample at the playback position, `None` while no file is loaded
+pub fn sample() -> Option<Sample> {
+    let mut replay = REPLAY.lock().ok()?;
+    let replay = replay.as_mut()?;
+
+    let position = replay.position();
+    if replay.state == ReplayState::Playing && position >= replay.duration() {
+        log::info!("Replay: reached the end of {}", replay.file.display());
+        replay.state = ReplayState::Finished;
+        replay.anchor(position);
+    }
+    let index = replay
+        .samples
+        .partition_point(|sample| sample.time <= position)
+        .saturating_sub(1);
+    replay.samples.get(index).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::time::Duration;
+
+    const CSV: &str = "Time_Epoch,Pressure,Vertical_Speed\n\
+                       1700000000.0,100.0,0.5\n\
+                       1700000001.0,101.0,0.6\n\
+                       1700000002.0,102.0,0.7\n";
+
+    fn pressure() -> f32 {
+        sample().unwrap().state.pressure
+    }
+
+    #[test]
+    fn replays_plain_and_compressed_files() {
+        let temporary = tempfile::tempdir().unwrap();
+        let directory = temporary.path();
+        let plain = directory.join("data.csv");
+        std::fs::write(&plain, CSV).unwrap();
+        let compressed = directory.join("data.csv.gz");
+        let mut encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&compressed).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(CSV.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+
+        for file in [plain, compressed] {
+            let loaded = load(&file).unwrap();
+            assert_eq!(loaded.state, ReplayState::Paused);
+            assert_eq!((loaded.samples, loaded.duration), (3, 2.0));
+            assert_eq!(pressure(), 100.0);
+            assert_eq!(sample().unwrap().vertical_speed, Some(0.5));
+
+            // Samples are held until the next one's time
+            assert_eq!(seek(1.5).unwrap().position, 1.5);
+            assert_eq!(pressure(), 101.0);
+            assert!(seek(2.5).is_err());
+
+            // Paused replays stay where they were stopped
+            play(Some(1.0)).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            let paused = pause().unwrap();
+            assert_eq!(paused.state, ReplayState::Paused);
+            assert!(paused.position > 1.5 && paused.position < 2.0);
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(status().position, paused.position);
+
+            // The last sample is held once the end is reached
+            play(Some(1000.0)).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(pressure(), 102.0);
+            let finished = status();
+            assert_eq!(finished.state, ReplayState::Finished);
+            assert_eq!(finished.position, 2.0);
+
+            // Seeking a finished replay pauses it, playing it again starts over
+            assert_eq!(seek(0.5).unwrap().state, ReplayState::Paused);
+            assert_eq!(pressure(), 100.0);
+            play(Some(1000.0)).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+            sample();
+            assert!(play(Some(1.0)).unwrap().position < 0.1);
+        }
+
+        stop().unwrap();
+        assert!(!is_active());
+        assert!(sample().is_none());
+        assert!(seek(0.0).is_err());
+    }
+}
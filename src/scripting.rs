@@ -0,0 +1,399 @@
+use crate::hardware_manager::{self, PwmChannel, UserLed};
+use crate::lease;
+use crate::server::protocols::v1::packages;
+use crate::settings;
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use rhai::{Array, Dynamic, Engine, EvalAltResult, Position};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Scripts are stored in this folder, inside the settings directory
+const DIRECTORY: &str = "scripts";
+
+const EXTENSION: &str = "rhai";
+
+/// Lines kept from each script's output
+const LOG_LINES: usize = 500;
+
+/// Longest pause of `sleep` between checks for a stop request
+const SLEEP_STEP: Duration = Duration::from_millis(50);
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct ScriptingSettings {
+    /// Operations a script may run before being terminated, its CPU budget
+    pub max_operations: u64,
+    /// Time a script may run before being terminated, in seconds
+    pub max_duration: f64,
+}
+
+impl Default for ScriptingSettings {
+    fn default() -> Self {
+        Self {
+            max_operations: 100_000_000,
+            max_duration: 3600.0,
+        }
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Default)]
+pub enum ScriptState {
+    #[default]
+    Idle,
+    Running,
+    Finished,
+    Stopped,
+    Failed,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct ScriptStatus {
+    pub name: String,
+    pub state: ScriptState,
+    /// Reason of the last failure
+    pub error: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ScriptError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+#[derive(Default)]
+struct Script {
+    state: ScriptState,
+    error: Option<String>,
+    log: VecDeque<String>,
+    stop: Arc<AtomicBool>,
+}
+
+lazy_static! {
+    static ref SCRIPTS: Mutex<HashMap<String, Script>> = Default::default();
+    static ref SETTINGS: RwLock<ScriptingSettings> = Default::default();
+}
+
+fn path(name: &str) -> Result<PathBuf, ScriptError> {
+    settings::check_name("Script", name).map_err(ScriptError::Invalid)?;
+    Ok(settings::directory()
+        .join(DIRECTORY)
+        .join(format!("{name}.{EXTENSION}")))
+}
+
+fn status(name: &str, script: Option<&Script>) -> ScriptStatus {
+    ScriptStatus {
+        name: name.to_string(),
+        state: script.map(|script| script.state).unwrap_or_default(),
+        error: script.and_then(|script| script.error.clone()),
+    }
+}
+
+/// Appends a line to the script's log and streams it to the clients
+fn log(name: &str, line: String) {
+    if let Ok(mut scripts) = SCRIPTS.lock() {
+        let script = scripts.entry(name.to_string()).or_default();
+        if script.log.len() == LOG_LINES {
+            script.log.pop_front();
+        }
+        script.log.push_back(line.clone());
+    }
+    packages::script_log_package(name, line);
+}
+
+fn runtime_error(error: impl std::fmt::Display) -> Box<EvalAltResult> {
+    error.to_string().into()
+}
+
+fn to_array(values: Vec<f32>) -> Array {
+    values
+        .into_iter()
+        .map(|value| Dynamic::from_float(value as f64))
+        .collect()
+}
+
+/// Sandboxed engine with the navigator's bindings, stopped by `stop` or the settings' limits
+fn engine(name: &str, stop: Arc<AtomicBool>, settings: &ScriptingSettings) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(settings.max_operations)
+        .set_max_call_levels(64)
+        .set_max_expr_depths(64, 32)
+        .set_max_string_size(64 * 1024)
+        .set_max_array_size(64 * 1024)
+        .set_max_map_size(1024)
+        .disable_symbol("eval");
+
+    let deadline = Instant::now() + Duration::from_secs_f64(settings.max_duration);
+    let progress_stop = stop.clone();
+    engine.on_progress(move |_| {
+        if progress_stop.load(Ordering::Relaxed) {
+            return Some("Stopped".into());
+        }
+        if Instant::now() > deadline {
+            return Some("Time limit exceeded".into());
+        }
+        None
+    });
+
+    let print_name = name.to_string();
+    engine.on_print(move |text| log(&print_name, text.to_string()));
+    let debug_name = name.to_string();
+    engine.on_debug(move |text, _, position: Position| {
+        log(&debug_name, format!("{position:?}: {text}"))
+    });
+
+    engine.register_fn("sleep", move |milliseconds: i64| {
+        let end = Instant::now() + Duration::from_millis(milliseconds.max(0) as u64);
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                return Err(runtime_error("Stopped"));
+            }
+            let now = Instant::now();
+            if now > deadline {
+                return Err(runtime_error("Time limit exceeded"));
+            }
+            if now >= end {
+                return Ok(());
+            }
+            std::thread::sleep(SLEEP_STEP.min(end - now));
+        }
+    });
+
+    engine
+        .register_fn("read_temperature", || {
+            hardware_manager::read_temperature()
+                .map(|value| value as f64)
+                .map_err(runtime_error)
+        })
+        .register_fn("read_pressure", || {
+            hardware_manager::read_pressure()
+                .map(|value| value as f64)
+                .map_err(runtime_error)
+        })
+        .register_fn("read_vertical_speed", || {
+            hardware_manager::read_vertical_speed()
+                .map(|value| value as f64)
+                .map_err(runtime_error)
+        })
+        .register_fn("read_leak", || {
+            hardware_manager::read_leak().map_err(runtime_error)
+        })
+        .register_fn("read_alarms", || {
+            hardware_manager::active_alarms()
+                .into_iter()
+                .map(|alarm| Dynamic::from(format!("{alarm:?}")))
+                .collect::<Array>()
+        })
+        .register_fn("read_accel", || {
+            hardware_manager::read_accel()
+                .map(|data| to_array(data.into()))
+                .map_err(runtime_error)
+        })
+        .register_fn("read_gyro", || {
+            hardware_manager::read_gyro()
+                .map(|data| to_array(data.into()))
+                .map_err(runtime_error)
+        })
+        .register_fn("read_mag", || {
+            hardware_manager::read_mag()
+                .map(|data| to_array(data.into()))
+                .map_err(runtime_error)
+        })
+        .register_fn("read_heading", || {
+            hardware_manager::read_heading()
+                .map(|heading| to_array(heading.into()))
+                .map_err(runtime_error)
+        })
+        .register_fn("read_adc", || {
+            hardware_manager::read_adc_all()
+                .map(|data| to_array(data.into()))
+                .map_err(runtime_error)
+        });
+
+    engine
+        .register_fn("set_pwm", |channel: &str, value: i64| {
+            let channel = PwmChannel::from_str(channel).map_err(runtime_error)?;
+            let value = u16::try_from(value).map_err(runtime_error)?;
+            packages::pwm_channel_value(channel, value)
+                .map(|_| ())
+                .map_err(runtime_error)
+        })
+        .register_fn("set_pwm_enable", |state: bool| {
+            packages::set_pwm_enable(state)
+                .map(|_| ())
+                .map_err(runtime_error)
+        })
+        .register_fn("set_pwm_frequency", |frequency: f64| {
+            packages::set_pwm_freq_hz(frequency as f32)
+                .map(|_| ())
+                .map_err(runtime_error)
+        })
+        .register_fn("set_led", |led: &str, state: bool| {
+            let led = UserLed::from_str(led).map_err(runtime_error)?;
+            packages::set_led(led, state)
+                .map(|_| ())
+                .map_err(runtime_error)
+        })
+        .register_fn("set_neopixel", |red: i64, green: i64, blue: i64| {
+            let color = [red, green, blue].map(|value| value.clamp(0, 255) as u8);
+            packages::set_neopixel(vec![color])
+                .map(|_| ())
+                .map_err(runtime_error)
+        });
+
+    engine
+}
+
+/// Scripts saved on disk, with their state
+pub fn list() -> Result<Vec<ScriptStatus>, ScriptError> {
+    let names = settings::names(DIRECTORY, EXTENSION)?;
+
+    let scripts = SCRIPTS
+        .lock()
+        .map_err(|error| ScriptError::Invalid(error.to_string()))?;
+    Ok(names
+        .iter()
+        .map(|name| status(name, scripts.get(name)))
+        .collect())
+}
+
+pub fn source(name: &str) -> Result<String, ScriptError> {
+    match std::fs::read_to_string(path(name)?) {
+        Ok(source) => Ok(source),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => Err(ScriptError::Invalid(
+            format!("Script {name} does not exist"),
+        )),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Saves the script after checking its syntax, a running script keeps its previous version
+pub fn save(name: &str, source: &str) -> Result<ScriptStatus, ScriptError> {
+    let path = path(name)?;
+    Engine::new()
+        .compile(source)
+        .map_err(|error| ScriptError::Invalid(format!("Script {name}: {error}")))?;
+
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    std::fs::write(path, source)?;
+
+    let scripts = SCRIPTS
+        .lock()
+        .map_err(|error| ScriptError::Invalid(error.to_string()))?;
+    Ok(status(name, scripts.get(name)))
+}
+
+/// Runs the script on its own thread, its writes are checked against the leases as done
+/// by the client starting it
+pub fn start(name: &str) -> Result<ScriptStatus, ScriptError> {
+    let source = source(name)?;
+    let settings = settings();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // Held until the thread runs, so the script can't be started twice and only shows as
+    // running once it does
+    let mut scripts = SCRIPTS
+        .lock()
+        .map_err(|error| ScriptError::Invalid(error.to_string()))?;
+    if scripts
+        .get(name)
+        .is_some_and(|script| script.state == ScriptState::Running)
+    {
+        return Err(ScriptError::Invalid(format!(
+            "Script {name} is already running"
+        )));
+    }
+
+    let thread_name = name.to_string();
+    let thread_stop = stop.clone();
+    let owner = lease::owner();
+    std::thread::Builder::new()
+        .name(format!("script-{name}"))
+        .spawn(move || {
+            let name = thread_name;
+            let stop = thread_stop;
+            let engine = engine(&name, stop.clone(), &settings);
+            let result = lease::with_owner(owner.as_deref(), || engine.run(&source));
+
+            let (state, error) = match result {
+                Ok(()) => (ScriptState::Finished, None),
+                Err(_) if stop.load(Ordering::Relaxed) => (ScriptState::Stopped, None),
+                Err(error) => (ScriptState::Failed, Some(error.to_string())),
+            };
+            match &error {
+                Some(error) => log(&name, format!("Failed: {error}")),
+                None => log(&name, format!("{state:?}")),
+            }
+
+            if let Ok(mut scripts) = SCRIPTS.lock() {
+                let script = scripts.entry(name).or_default();
+                script.state = state;
+                script.error = error;
+            }
+        })?;
+
+    scripts.insert(
+        name.to_string(),
+        Script {
+            state: ScriptState::Running,
+            stop,
+            ..Default::default()
+        },
+    );
+    Ok(status(name, scripts.get(name)))
+}
+
+/// Requests the script to stop, it ends at its next operation or sleep step
+pub fn stop(name: &str) -> Result<ScriptStatus, ScriptError> {
+    path(name)?;
+    let scripts = SCRIPTS
+        .lock()
+        .map_err(|error| ScriptError::Invalid(error.to_string()))?;
+    let script = scripts.get(name);
+    match script {
+        Some(script) if script.state == ScriptState::Running => {
+            script.stop.store(true, Ordering::Relaxed);
+        }
+        _ => {
+            return Err(ScriptError::Invalid(format!(
+                "Script {name} is not running"
+            )))
+        }
+    }
+    Ok(status(name, script))
+}
+
+/// Output lines of the script's last run
+pub fn log_lines(name: &str) -> Result<Vec<String>, ScriptError> {
+    path(name)?;
+    let scripts = SCRIPTS
+        .lock()
+        .map_err(|error| ScriptError::Invalid(error.to_string()))?;
+    Ok(scripts
+        .get(name)
+        .map(|script| script.log.iter().cloned().collect())
+        .unwrap_or_default())
+}
+
+pub fn settings() -> ScriptingSettings {
+    SETTINGS
+        .read()
+        .map(|settings| settings.clone())
+        .unwrap_or_default()
+}
+
+/// Limits used by the scripts started afterwards
+pub fn set_settings(settings: ScriptingSettings) {
+    if let Ok(mut current) = SETTINGS.write() {
+        *current = settings;
+    }
+}
@@ -0,0 +1,668 @@
+use crate::hardware_manager::{PwmChannel, UserLed};
+use crate::lease;
+use crate::server::protocols::v1::packages;
+use crate::settings;
+use chrono::{Local, NaiveTime, Timelike};
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+
+/// Sequences are stored in this folder, inside the settings directory
+const DIRECTORY: &str = "sequences";
+
+const SCHEDULES_NAME: &str = "schedules";
+
+/// Interval between the outputs' updates during a ramp
+const RAMP_PERIOD: Duration = Duration::from_millis(20);
+
+/// Longest step start and ramp, in seconds
+const MAX_STEP_TIME: f64 = 24.0 * 60.0 * 60.0;
+
+/// Interval between the scheduler's checks
+const SCHEDULER_PERIOD: Duration = Duration::from_secs(1);
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Target {
+    Pwm,
+    PwmEnable,
+    PwmFrequency,
+    UserLed,
+    NeoPixel,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct Step {
+    /// Start of the step from the sequence's start, in seconds
+    pub time: f64,
+    pub target: Target,
+    /// Channel of `Pwm` steps
+    #[serde(default)]
+    pub channel: Option<PwmChannel>,
+    /// Value of `Pwm` steps
+    #[serde(default)]
+    pub value: Option<u16>,
+    /// Duration of a `Pwm` ramp to the value, in seconds, starting from the channel's
+    /// previous value in the sequence or `from`
+    #[serde(default)]
+    pub ramp: f64,
+    /// Starting value of a `Pwm` ramp
+    #[serde(default)]
+    pub from: Option<u16>,
+    /// LED of `UserLed` steps
+    #[serde(default)]
+    pub led: Option<UserLed>,
+    /// Frequency of `PwmFrequency` steps, in Hz
+    #[serde(default)]
+    pub frequency: Option<f32>,
+    /// State of `UserLed` and `PwmEnable` steps
+    #[serde(default)]
+    pub state: Option<bool>,
+    /// Red, green and blue of `NeoPixel` steps
+    #[serde(default)]
+    pub color: Option<Vec<u8>>,
+}
+
+impl Step {
+    fn check(&self) -> Result<(), String> {
+        let valid = match self.target {
+            Target::Pwm => self.channel.is_some() && self.value.is_some(),
+            Target::PwmEnable => self.state.is_some(),
+            Target::PwmFrequency => self.frequency.is_some(),
+            Target::UserLed => self.led.is_some() && self.state.is_some(),
+            Target::NeoPixel => self.color.as_ref().is_some_and(|color| color.len() == 3),
+        };
+        if !valid {
+            return Err(format!(
+                "{:?} step at {}s is missing its parameters",
+                self.target, self.time
+            ));
+        }
+        let range = 0.0..=MAX_STEP_TIME;
+        if !(range.contains(&self.time) && range.contains(&self.ramp)) {
+            return Err(format!(
+                "Step at {}s must start and ramp within {MAX_STEP_TIME}s",
+                self.time
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct Sequence {
+    pub steps: Vec<Step>,
+    /// Restarts the sequence once all its steps are done
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+impl Sequence {
+    pub fn check(&self) -> Result<(), String> {
+        self.steps.iter().try_for_each(Step::check)?;
+        // Repeating a sequence that takes no time would restart it continuously
+        let duration = self
+            .steps
+            .iter()
+            .map(|step| step.time + step.ramp)
+            .fold(0.0, f64::max);
+        if self.repeat && duration <= 0.0 {
+            return Err("Repeating sequences require steps after their start".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct Schedule {
+    pub sequence: String,
+    /// Starts the sequence daily at this local time, as `HH:MM` or `HH:MM:SS`
+    #[serde(default)]
+    pub at: Option<String>,
+    /// Starts the sequence periodically, in minutes
+    #[serde(default)]
+    pub every: Option<f64>,
+}
+
+impl Schedule {
+    fn time(&self) -> Result<Option<NaiveTime>, String> {
+        self.at
+            .as_ref()
+            .map(|at| {
+                NaiveTime::parse_from_str(at, "%H:%M:%S")
+                    .or_else(|_| NaiveTime::parse_from_str(at, "%H:%M"))
+                    .map_err(|error| format!("Schedule of {}: {at}: {error}", self.sequence))
+            })
+            .transpose()
+    }
+
+    fn check(&self) -> Result<(), String> {
+        self.time()?;
+        match (&self.at, self.every) {
+            (None, None) => Err(format!(
+                "Schedule of {} requires a time or a period",
+                self.sequence
+            )),
+            (_, Some(every)) if every <= 0.0 => Err(format!(
+                "Schedule of {} requires a positive period",
+                self.sequence
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the schedule's time was crossed going from `last_time` to `now`, or a period
+    /// ended going from `last_minutes` to `minutes` since the scheduler's start
+    fn is_due(
+        &self,
+        last_time: NaiveTime,
+        now: NaiveTime,
+        last_minutes: f64,
+        minutes: f64,
+    ) -> bool {
+        // Crossing midnight wraps the time, both sides are checked then
+        let daily = self.time().ok().flatten().is_some_and(|at| {
+            let at = at.num_seconds_from_midnight();
+            let (from, to) = (
+                last_time.num_seconds_from_midnight(),
+                now.num_seconds_from_midnight(),
+            );
+            if from <= to {
+                from < at && at <= to
+            } else {
+                from < at || at <= to
+            }
+        });
+        let periodic = self
+            .every
+            .is_some_and(|every| (minutes / every).floor() > (last_minutes / every).floor());
+        daily || periodic
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum SequenceState {
+    Idle,
+    Running,
+    Paused,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct SequenceStatus {
+    pub name: String,
+    pub state: SequenceState,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SequenceError {
+    #[error("{0}")]
+    Invalid(String),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+}
+
+enum Control {
+    Pause,
+    Resume,
+    Stop,
+}
+
+struct Runner {
+    handle: std::thread::JoinHandle<()>,
+    sender: Sender<Control>,
+    paused: bool,
+}
+
+lazy_static! {
+    static ref RUNNERS: Mutex<HashMap<String, Runner>> = Default::default();
+    static ref SCHEDULES: RwLock<Vec<Schedule>> = Default::default();
+}
+
+fn check_name(name: &str) -> Result<(), SequenceError> {
+    settings::check_name("Sequence", name).map_err(SequenceError::Invalid)
+}
+
+fn settings_name(name: &str) -> String {
+    format!("{DIRECTORY}/{name}")
+}
+
+fn lock_runners() -> Result<MutexGuard<'static, HashMap<String, Runner>>, SequenceError> {
+    RUNNERS
+        .lock()
+        .map_err(|error| SequenceError::Invalid(error.to_string()))
+}
+
+fn state(runner: Option<&Runner>) -> SequenceState {
+    match runner {
+        Some(runner) if runner.handle.is_finished() => SequenceState::Idle,
+        Some(runner) if runner.paused => SequenceState::Paused,
+        Some(_) => SequenceState::Running,
+        None => SequenceState::Idle,
+    }
+}
+
+struct Ramp {
+    channel: PwmChannel,
+    from: f64,
+    to: f64,
+    start: f64,
+    duration: f64,
+}
+
+impl Ramp {
+    fn value(&self, elapsed: f64) -> u16 {
+        let progress = ((elapsed - self.start) / self.duration).clamp(0.0, 1.0);
+        (self.from + (self.to - self.from) * progress).round() as u16
+    }
+}
+
+/// Sends a step's output through the packages, so it's broadcasted as any other command
+fn apply(step: &Step) -> Result<(), String> {
+    let result = match step.target {
+        Target::Pwm => match (step.channel.clone(), step.value) {
+            (Some(channel), Some(value)) => packages::pwm_channel_value(channel, value),
+            _ => return Ok(()),
+        },
+        Target::PwmEnable => packages::set_pwm_enable(step.state.unwrap_or_default()),
+        Target::PwmFrequency => match step.frequency {
+            Some(frequency) => packages::set_pwm_freq_hz(frequency),
+            None => return Ok(()),
+        },
+        Target::UserLed => match step.led.clone() {
+            Some(led) => packages::set_led(led, step.state.unwrap_or_default()),
+            None => return Ok(()),
+        },
+        Target::NeoPixel => match step.color.as_deref() {
+            Some([red, green, blue]) => packages::set_neopixel(vec![[*red, *green, *blue]]),
+            _ => return Ok(()),
+        },
+    };
+    result.map(|_| ()).map_err(|error| error.to_string())
+}
+
+/// Runs the sequence until it's done or stopped, returns false if stopped.
+/// The timeline runs `speed` times faster than real time.
+fn run_once(name: &str, steps: &[Step], speed: f64, receiver: &Receiver<Control>) -> bool {
+    let mut start = Instant::now();
+    let mut next = 0;
+    let mut ramps: Vec<Ramp> = vec![];
+    let mut values: HashMap<PwmChannel, u16> = HashMap::new();
+
+    loop {
+        let elapsed = start.elapsed().as_secs_f64() * speed;
+
+        while let Some(step) = steps.get(next).filter(|step| step.time <= elapsed) {
+            next += 1;
+            if let (Target::Pwm, Some(channel), Some(value)) =
+                (step.target, step.channel.clone(), step.value)
+            {
+                let from = step.from.or_else(|| values.get(&channel).copied());
+                values.insert(channel.clone(), value);
+                ramps.retain(|ramp| ramp.channel != channel);
+                if let (Some(from), true) = (from, step.ramp > 0.0) {
+                    ramps.push(Ramp {
+                        channel,
+                        from: from as f64,
+                        to: value as f64,
+                        start: step.time,
+                        duration: step.ramp,
+                    });
+                    continue;
+                }
+            }
+            if let Err(error) = apply(step) {
+                log::warn!("Sequence {name}: {error}");
+            }
+        }
+
+        for ramp in &ramps {
+            if let Err(error) =
+                packages::pwm_channel_value(ramp.channel.clone(), ramp.value(elapsed))
+            {
+                log::warn!("Sequence {name}: {error}");
+            }
+        }
+        ramps.retain(|ramp| elapsed < ramp.start + ramp.duration);
+
+        let until_next = steps
+            .get(next)
+            .map(|step| Duration::from_secs_f64((step.time - elapsed).max(0.0) / speed));
+        let wait = match (until_next, ramps.is_empty()) {
+            (None, true) => return true,
+            (Some(until_next), true) => until_next,
+            (until_next, false) => until_next.unwrap_or(RAMP_PERIOD).min(RAMP_PERIOD),
+        };
+
+        match receiver.recv_timeout(wait) {
+            Ok(Control::Pause) => {
+                let paused = Instant::now();
+                loop {
+                    match receiver.recv() {
+                        Ok(Control::Resume) => break,
+                        Ok(Control::Pause) => continue,
+                        Ok(Control::Stop) | Err(_) => return false,
+                    }
+                }
+                // The timeline is shifted by the time spent paused
+                start += paused.elapsed();
+            }
+            Ok(Control::Resume) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Control::Stop) | Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+pub fn list() -> Result<Vec<SequenceStatus>, SequenceError> {
+    let names = settings::names(DIRECTORY, "json")?;
+
+    let runners = lock_runners()?;
+    Ok(names
+        .into_iter()
+        .map(|name| SequenceStatus {
+            state: state(runners.get(&name)),
+            name,
+        })
+        .collect())
+}
+
+pub fn sequence(name: &str) -> Result<Sequence, SequenceError> {
+    check_name(name)?;
+    settings::load::<Sequence>(&settings_name(name))
+        .ok_or_else(|| SequenceError::Invalid(format!("Sequence {name} does not exist")))
+}
+
+/// Saves the sequence, a running sequence keeps its previous version
+pub fn save(name: &str, sequence: Sequence) -> Result<Sequence, SequenceError> {
+    check_name(name)?;
+    sequence.check().map_err(SequenceError::Invalid)?;
+    settings::save(&settings_name(name), &sequence)?;
+    Ok(sequence)
+}
+
+/// Runs the sequence on its own thread, identified by `key`, at `speed` times the real time.
+/// Its writes are checked against the leases as done by the client playing it, or by no
+/// client when started by the scheduler
+pub fn play(key: &str, mut sequence: Sequence, speed: f64) -> Result<(), SequenceError> {
+    sequence.check().map_err(SequenceError::Invalid)?;
+    sequence.steps.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let mut runners = lock_runners()?;
+    if state(runners.get(key)) != SequenceState::Idle {
+        return Err(SequenceError::Invalid(format!("{key} is already running")));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let thread_key = key.to_string();
+    let owner = lease::owner();
+    let handle = std::thread::Builder::new()
+        .name(format!("sequence-{key}"))
+        .spawn(move || {
+            log::info!("Sequence {thread_key}: started");
+            lease::with_owner(owner.as_deref(), || {
+                while run_once(&thread_key, &sequence.steps, speed, &receiver) && sequence.repeat {}
+            });
+            log::info!("Sequence {thread_key}: done");
+        })?;
+
+    runners.insert(
+        key.to_string(),
+        Runner {
+            handle,
+            sender,
+            paused: false,
+        },
+    );
+    Ok(())
+}
+
+pub fn start(name: &str) -> Result<SequenceStatus, SequenceError> {
+    play(name, sequence(name)?, 1.0)?;
+    Ok(SequenceStatus {
+        name: name.to_string(),
+        state: SequenceState::Running,
+    })
+}
+
+/// State of the runner identified by `key`
+pub fn runner_state(key: &str) -> SequenceState {
+    RUNNERS
+        .lock()
+        .map(|runners| state(runners.get(key)))
+        .unwrap_or(SequenceState::Idle)
+}
+
+fn control(key: &str, control: Control) -> Result<SequenceState, SequenceError> {
+    let mut runners = lock_runners()?;
+    let runner = runners
+        .get_mut(key)
+        .filter(|runner| !runner.handle.is_finished())
+        .ok_or_else(|| SequenceError::Invalid(format!("{key} is not running")))?;
+
+    match control {
+        Control::Pause => runner.paused = true,
+        Control::Resume => runner.paused = false,
+        Control::Stop => {}
+    }
+    let stopped = matches!(control, Control::Stop);
+    // A finished runner may have dropped its receiver, it's idle either way
+    let _ = runner.sender.send(control);
+
+    if stopped {
+        runners.remove(key);
+        return Ok(SequenceState::Idle);
+    }
+    Ok(state(Some(runner)))
+}
+
+/// Stops the runner identified by `key`
+pub fn halt(key: &str) -> Result<SequenceState, SequenceError> {
+    control(key, Control::Stop)
+}
+
+pub fn stop(name: &str) -> Result<SequenceStatus, SequenceError> {
+    check_name(name)?;
+    Ok(SequenceStatus {
+        name: name.to_string(),
+        state: halt(name)?,
+    })
+}
+
+pub fn pause(name: &str) -> Result<SequenceStatus, SequenceError> {
+    check_name(name)?;
+    Ok(SequenceStatus {
+        name: name.to_string(),
+        state: control(name, Control::Pause)?,
+    })
+}
+
+pub fn resume(name: &str) -> Result<SequenceStatus, SequenceError> {
+    check_name(name)?;
+    Ok(SequenceStatus {
+        name: name.to_string(),
+        state: control(name, Control::Resume)?,
+    })
+}
+
+/// Loads the saved schedules and starts the scheduler's thread
+pub fn init() {
+    if let Some(schedules) = settings::load::<Vec<Schedule>>(SCHEDULES_NAME) {
+        match schedules.iter().try_for_each(Schedule::check) {
+            Ok(()) => {
+                if let Ok(mut current) = SCHEDULES.write() {
+                    *current = schedules;
+                }
+            }
+            Err(error) => log::warn!("Sequencer: ignoring the saved schedules: {error}"),
+        }
+    }
+
+    let spawned = std::thread::Builder::new()
+        .name("scheduler".to_string())
+        .spawn(scheduler);
+    if let Err(error) = spawned {
+        log::error!("Sequencer: failed to start the scheduler: {error}");
+    }
+}
+
+/// Starts the scheduled sequences that are due, skipping the ones already running
+fn scheduler() {
+    let started = Instant::now();
+    let mut last_time = Local::now().time();
+    let mut last_minutes = 0.0;
+
+    loop {
+        std::thread::sleep(SCHEDULER_PERIOD);
+
+        let now = Local::now().time();
+        let minutes = started.elapsed().as_secs_f64() / 60.0;
+        let schedules = schedules();
+
+        for schedule in &schedules {
+            if schedule.is_due(last_time, now, last_minutes, minutes) {
+                match start(&schedule.sequence) {
+                    Ok(_) => log::info!("Sequencer: started {} as scheduled", schedule.sequence),
+                    Err(error) => log::warn!(
+                        "Sequencer: scheduled {} not started: {error}",
+                        schedule.sequence
+                    ),
+                }
+            }
+        }
+
+        last_time = now;
+        last_minutes = minutes;
+    }
+}
+
+pub fn schedules() -> Vec<Schedule> {
+    SCHEDULES
+        .read()
+        .map(|schedules| schedules.clone())
+        .unwrap_or_default()
+}
+
+/// Applies and saves the schedules
+pub fn set_schedules(schedules: Vec<Schedule>) -> Result<Vec<Schedule>, SequenceError> {
+    schedules
+        .iter()
+        .try_for_each(Schedule::check)
+        .map_err(SequenceError::Invalid)?;
+    settings::save(SCHEDULES_NAME, &schedules)?;
+    if let Ok(mut current) = SCHEDULES.write() {
+        *current = schedules.clone();
+    }
+    Ok(schedules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schedule(at: Option<&str>, every: Option<f64>) -> Schedule {
+        Schedule {
+            sequence: "test".to_string(),
+            at: at.map(str::to_string),
+            every,
+        }
+    }
+
+    fn time(text: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(text, "%H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn daily_schedules_fire_once_when_crossed() {
+        let daily = schedule(Some("12:30"), None);
+        let due = |from: &str, to: &str| daily.is_due(time(from), time(to), 0.0, 0.0);
+        assert!(due("12:29:59", "12:30:00"));
+        // Only the check reaching the time starts it
+        assert!(!due("12:30:00", "12:30:01"));
+        assert!(!due("12:28:00", "12:29:59"));
+        // A late check still catches it
+        assert!(due("12:29:00", "12:35:00"));
+
+        let midnight = schedule(Some("00:00:00"), None);
+        assert!(midnight.is_due(time("23:59:59"), time("00:00:00"), 0.0, 0.0));
+        assert!(!midnight.is_due(time("00:00:00"), time("00:00:01"), 0.0, 0.0));
+        let late = schedule(Some("23:59:59"), None);
+        assert!(late.is_due(time("23:59:58"), time("00:00:01"), 0.0, 0.0));
+        assert!(!late.is_due(time("23:59:59"), time("00:00:01"), 0.0, 0.0));
+    }
+
+    #[test]
+    fn periodic_schedules_fire_at_each_period() {
+        let periodic = schedule(None, Some(0.5));
+        let now = time("08:00:00");
+        assert!(!periodic.is_due(now, now, 0.0, 0.49));
+        assert!(periodic.is_due(now, now, 0.49, 0.5));
+        assert!(!periodic.is_due(now, now, 0.5, 0.99));
+        // Missed periods start it once
+        assert!(periodic.is_due(now, now, 0.99, 2.6));
+    }
+
+    #[test]
+    fn checks_schedules_and_steps() {
+        assert!(schedule(None, None).check().is_err());
+        assert!(schedule(None, Some(0.0)).check().is_err());
+        assert!(schedule(Some("25:00"), None).check().is_err());
+        assert!(schedule(Some("07:15:30"), Some(60.0)).check().is_ok());
+
+        let sequence: Sequence = serde_json::from_value(json!({ "steps": [
+            { "time": 0.0, "target": "Pwm", "channel": "Ch1", "value": 300 },
+            { "time": 1.0, "target": "UserLed", "led": "Led1" },
+        ]}))
+        .unwrap();
+        assert!(sequence.check().is_err());
+        let step = |time: f64, ramp: f64| -> Step {
+            serde_json::from_value(json!({
+                "time": time, "target": "Pwm", "channel": "Ch1", "value": 300, "ramp": ramp
+            }))
+            .unwrap()
+        };
+        assert!(step(-1.0, 0.0).check().is_err());
+        assert!(step(1e20, 0.0).check().is_err());
+        assert!(step(0.0, 1e20).check().is_err());
+        let mut not_a_number = step(0.0, 0.0);
+        not_a_number.time = f64::NAN;
+        assert!(not_a_number.check().is_err());
+        assert!(step(MAX_STEP_TIME, MAX_STEP_TIME).check().is_ok());
+
+        // Repeating sequences must take some time
+        let repeating = |steps: Vec<Step>| Sequence {
+            steps,
+            repeat: true,
+        };
+        assert!(repeating(vec![]).check().is_err());
+        assert!(repeating(vec![step(0.0, 0.0), step(0.0, 0.0)])
+            .check()
+            .is_err());
+        assert!(repeating(vec![step(0.0, 0.5)]).check().is_ok());
+        assert!(repeating(vec![step(0.0, 0.0), step(2.0, 0.0)])
+            .check()
+            .is_ok());
+        let once = Sequence {
+            steps: vec![],
+            repeat: false,
+        };
+        assert!(once.check().is_ok());
+    }
+
+    #[test]
+    fn ramps_between_values() {
+        let ramp = Ramp {
+            channel: PwmChannel::Ch1,
+            from: 1000.0,
+            to: 2000.0,
+            start: 2.0,
+            duration: 4.0,
+        };
+        assert_eq!(ramp.value(0.0), 1000);
+        assert_eq!(ramp.value(3.0), 1250);
+        assert_eq!(ramp.value(6.0), 2000);
+        assert_eq!(ramp.value(60.0), 2000);
+    }
+}
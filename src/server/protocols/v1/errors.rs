@@ -7,22 +7,37 @@ use validator::ValidationErrors;
 #[api_v2_errors(
     code = 400,
     description = "Bad Request: The client's request contains invalid or malformed data.",
+    code = 403,
+    description = "Forbidden: The request needs the admin key.",
+    code = 409,
+    description = "Conflict: The outputs are leased to another client.",
     code = 500,
-    description = "Internal Server Error: An unexpected server error has occurred."
+    description = "Internal Server Error: An unexpected server error has occurred.",
+    code = 503,
+    description = "Service Unavailable: The requested hardware is not available."
 )]
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Bad Request: {0}")]
     BadRequest(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("Conflict: {0}")]
+    Conflict(String),
     #[error("Internal Server Error: {0}")]
     Internal(String),
+    #[error("Hardware Unavailable: {0}")]
+    HardwareUnavailable(String),
 }
 
 impl ResponseError for Error {
     fn status_code(&self) -> StatusCode {
         match self {
             Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::Conflict(_) => StatusCode::CONFLICT,
             Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::HardwareUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 }
@@ -3,13 +3,39 @@ use serde::{Deserialize, Serialize};
 use strum::IntoEnumIterator;
 
 use crate::{
-    hardware_manager::{self},
-    server::protocols::v1::structures::{
-        AnsPackage, InputDeviceType, InputDevices, InputRequest, NeoPixel, NeoPixelRGB, Operation,
-        OutputDevices, OutputRequest, Pwm, UserLED, Value,
+    aliases::{self, AliasSettings},
+    data_logger::MAX_TRIGGER_TIME,
+    gamepad::{self, GamepadSettings},
+    gyro_bias::{self, GyroBiasState},
+    hardware_manager::{self, ArmingSettings, ArmingStatus},
+    heading::{self, HeadingSettings},
+    lease::{self, Access, Lease, LeaseError, LeaseGrant},
+    log_files::{self, LogFileError, LogFileInfo},
+    mixer::{self, MixerSettings},
+    mounting::{self, MountingSettings},
+    recorder::{self, MacroStatus, RecordingStatus},
+    replay::{self, ReplayError, ReplayStatus},
+    scripting::{self, ScriptError, ScriptStatus, ScriptingSettings},
+    sequencer::{self, Schedule, Sequence, SequenceError, SequenceStatus},
+    server::protocols::v1::{
+        errors::Error,
+        structures::{
+            AnsPackage, ApiDataloggerSettings, ApiDataloggerTrigger, ApiGamepadSettings,
+            ApiGamepadState, ApiGyroBiasSettings, ApiHeadingSettings, ApiLease, ApiLogFileQuery,
+            ApiMacroPlay, ApiMixerSettings, ApiMonitorSettings, ApiMotionCommand,
+            ApiMountingSettings, ApiReplayLoad, ApiReplayPlay, ApiReplaySeek, ApiScript,
+            ApiScriptingSettings, ApiVerticalSpeedSettings, ApiVibrationSettings, ArmingRequest,
+            DataloggerRequest, InputDeviceType, InputDevices, InputRequest, LeaseRequest, NeoPixel,
+            NeoPixelRGB, Operation, OutputDevices, OutputRequest, Pwm, ScriptRequest, UserLED,
+            Value, VibrationRequest,
+        },
     },
+    vertical_speed::{self, VerticalSpeedSettings},
+    vibration::{self, VibrationAnalysis, VibrationSettings},
 };
-use std::{error::Error, str::FromStr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use validator::Validate;
 
 #[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
 pub enum Sensors {
@@ -19,11 +45,13 @@ pub enum Sensors {
     Accelerometer,
     Gyroscope,
     Magnetometer,
+    Heading,
+    VerticalSpeed,
     Adc,
 }
 
 impl FromStr for Sensors {
-    type Err = Box<dyn Error>;
+    type Err = Box<dyn std::error::Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.to_lowercase();
@@ -35,6 +63,8 @@ impl FromStr for Sensors {
             "accelerometer" => Ok(Sensors::Accelerometer),
             "gyroscope" => Ok(Sensors::Gyroscope),
             "magnetometer" => Ok(Sensors::Magnetometer),
+            "heading" => Ok(Sensors::Heading),
+            "verticalspeed" | "vertical_speed" => Ok(Sensors::VerticalSpeed),
             "adc" => Ok(Sensors::Adc),
             _ => Err(format!("{} is not a valid Sensors variant", s).into()),
         }
@@ -42,7 +72,7 @@ impl FromStr for Sensors {
 }
 
 impl FromStr for hardware_manager::UserLed {
-    type Err = Box<dyn Error>;
+    type Err = Box<dyn std::error::Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.to_lowercase();
@@ -51,13 +81,14 @@ impl FromStr for hardware_manager::UserLed {
             "led1" => Ok(hardware_manager::UserLed::Led1),
             "led2" => Ok(hardware_manager::UserLed::Led2),
             "led3" => Ok(hardware_manager::UserLed::Led3),
-            _ => Err(format!("{} is not a valid UserLed variant", s).into()),
+            _ => aliases::user_led(&s)
+                .ok_or_else(|| format!("{} is not a valid UserLed variant or alias", s).into()),
         }
     }
 }
 
 impl FromStr for hardware_manager::PwmChannel {
-    type Err = Box<dyn Error>;
+    type Err = Box<dyn std::error::Error>;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let s = s.to_lowercase();
@@ -80,181 +111,1129 @@ impl FromStr for hardware_manager::PwmChannel {
             "ch15" => Ok(hardware_manager::PwmChannel::Ch15),
             "ch16" => Ok(hardware_manager::PwmChannel::Ch16),
             "all" => Ok(hardware_manager::PwmChannel::All),
-            _ => Err(format!("{} is not a valid PwmChannel variant", s).into()),
+            _ => aliases::pwm_channel(&s)
+                .ok_or_else(|| format!("{} is not a valid PwmChannel variant or alias", s).into()),
         }
     }
 }
 
-pub fn init() -> AnsPackage {
-    hardware_manager::init();
-    AnsPackage::new(Operation::Settings)
+/// Packages' timestamps follow RFC 3339, in UTC with microseconds
+fn timestamp(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
 }
 
-pub fn pwm_channel_value(channel: hardware_manager::PwmChannel, value: u16) -> AnsPackage {
-    hardware_manager::set_pwm_channel_value(channel.clone(), value);
+pub fn init() -> Result<AnsPackage, Error> {
+    hardware_manager::init()?;
+    Ok(AnsPackage::new(Operation::Settings))
+}
+
+pub fn monitor_settings() -> Result<hardware_manager::MonitorSettings, Error> {
+    hardware_manager::monitor_settings()
+}
+
+/// Applies the requested changes over the current monitor settings
+pub fn set_monitor_settings(
+    request: ApiMonitorSettings,
+) -> Result<hardware_manager::MonitorSettings, Error> {
+    request.validate()?;
+    let mut settings = hardware_manager::monitor_settings()?;
+    if let Some(enable) = request.enable {
+        settings.enable = enable;
+    }
+    if let Some(rate) = request.rate {
+        settings.rate = rate;
+    }
+    hardware_manager::set_monitor_settings(settings)
+}
+
+pub fn datalogger_settings() -> Result<hardware_manager::DataloggerSettings, Error> {
+    hardware_manager::datalogger_settings()
+}
+
+/// Applies the requested changes over the current datalogger settings
+pub fn set_datalogger_settings(
+    request: ApiDataloggerSettings,
+) -> Result<hardware_manager::DataloggerSettings, Error> {
+    request.validate()?;
+    let mut settings = hardware_manager::datalogger_settings()?;
+    if let Some(enable) = request.enable {
+        settings.enable = enable;
+    }
+    if let Some(rate) = request.rate {
+        settings.rate = rate;
+    }
+    if let Some(directory) = request.directory {
+        settings.directory = directory;
+    }
+    if let Some(filename) = request.filename {
+        settings.filename = filename;
+    }
+    if let Some(format) = request.format {
+        settings.format = format;
+    }
+    if let Some(compression) = request.compression {
+        // Levels of another algorithm don't carry over
+        if compression != settings.compression {
+            settings.compression_level = None;
+        }
+        settings.compression = compression;
+    }
+    if let Some(level) = request.compression_level {
+        let levels = settings.compression.levels();
+        if !levels.contains(&level) {
+            return Err(Error::BadRequest(format!(
+                "{:?} compression levels go from {} to {}",
+                settings.compression,
+                levels.start(),
+                levels.end()
+            )));
+        }
+        settings.compression_level = Some(level);
+    }
+    if let Some(events) = request.events {
+        settings.events = events;
+    }
+    if let Some(timestamps) = request.timestamps {
+        if timestamps.is_empty() {
+            return Err(Error::BadRequest(
+                "Datalogger needs at least one timestamp".to_string(),
+            ));
+        }
+        if let Some(timestamp) = timestamps
+            .iter()
+            .enumerate()
+            .find_map(|(index, timestamp)| {
+                timestamps[..index].contains(timestamp).then_some(timestamp)
+            })
+        {
+            return Err(Error::BadRequest(format!(
+                "Timestamp {timestamp:?} is selected more than once"
+            )));
+        }
+        settings.timestamps = timestamps;
+    }
+    if let Some(columns) = request.columns {
+        if columns.is_empty() {
+            return Err(Error::BadRequest(
+                "Datalogger needs at least one column".to_string(),
+            ));
+        }
+        if let Some(column) = columns
+            .iter()
+            .enumerate()
+            .find_map(|(index, column)| columns[..index].contains(column).then_some(column))
+        {
+            return Err(Error::BadRequest(format!(
+                "Column {column:?} is selected more than once"
+            )));
+        }
+        settings.columns = columns;
+    }
+    if let Some(rotation) = request.rotation {
+        if rotation.max_size == Some(0) || rotation.max_duration == Some(0) {
+            return Err(Error::BadRequest(
+                "Rotation limits must be greater than zero".to_string(),
+            ));
+        }
+        settings.rotation = rotation;
+    }
+    if let Some(retention) = request.retention {
+        if retention.max_files == Some(0) {
+            return Err(Error::BadRequest(
+                "Retention must keep at least one file".to_string(),
+            ));
+        }
+        settings.retention = retention;
+    }
+    if let Some(trigger) = request.trigger {
+        for seconds in [trigger.pre_trigger, trigger.post_trigger] {
+            if !(0.0..=MAX_TRIGGER_TIME).contains(&seconds) {
+                return Err(Error::BadRequest(format!(
+                    "Trigger times must be between 0 and {MAX_TRIGGER_TIME} seconds"
+                )));
+            }
+        }
+        if trigger
+            .conditions
+            .iter()
+            .any(|condition| !condition.above.is_finite() || !condition.neutral.is_finite())
+        {
+            return Err(Error::BadRequest(
+                "Trigger thresholds must be finite".to_string(),
+            ));
+        }
+        settings.trigger = trigger;
+    }
+    if let Some(min_free_space) = request.min_free_space {
+        settings.min_free_space = min_free_space;
+    }
+    hardware_manager::set_datalogger_settings(settings)
+}
+
+pub fn datalogger_status() -> Result<hardware_manager::DataloggerStatus, Error> {
+    Ok(hardware_manager::datalogger_status())
+}
+
+pub fn datalogger_package(status: hardware_manager::DataloggerStatus) -> AnsPackage {
+    AnsPackage::new(Operation::Datalogger(DataloggerRequest {
+        timestamp: timestamp(chrono::Utc::now()),
+        datalogger: status,
+    }))
+}
+
+pub fn set_datalogger_trigger(
+    request: ApiDataloggerTrigger,
+) -> Result<hardware_manager::DataloggerStatus, Error> {
+    if request.active && !hardware_manager::datalogger_settings()?.trigger.enable {
+        return Err(Error::Conflict(
+            "Datalogger is not in triggered mode".to_string(),
+        ));
+    }
+    Ok(hardware_manager::set_datalogger_trigger(request.active))
+}
+
+impl From<LogFileError> for Error {
+    fn from(error: LogFileError) -> Self {
+        match error {
+            LogFileError::Invalid(message) => Self::BadRequest(message),
+            LogFileError::Io(error) => Self::Internal(format!("Log files: {error}")),
+        }
+    }
+}
+
+fn current_datalogger_file() -> Option<PathBuf> {
+    hardware_manager::datalogger_status()
+        .file
+        .map(PathBuf::from)
+}
+
+pub fn datalogger_files() -> Result<Vec<LogFileInfo>, Error> {
+    let directory = PathBuf::from(datalogger_settings()?.directory);
+    Ok(log_files::list(
+        &directory,
+        current_datalogger_file().as_deref(),
+    )?)
+}
+
+pub fn datalogger_file(name: &str) -> Result<PathBuf, Error> {
+    let directory = PathBuf::from(datalogger_settings()?.directory);
+    Ok(log_files::path(&directory, name)?)
+}
+
+/// Name of the file being written by the datalogger
+pub fn current_datalogger_file_name() -> Result<String, Error> {
+    current_datalogger_file()
+        .and_then(|path| Some(path.file_name()?.to_string_lossy().to_string()))
+        .ok_or_else(|| Error::BadRequest("Datalogger is not writing to a file".to_string()))
+}
+
+fn parse_time(time: Option<String>) -> Result<Option<chrono::DateTime<chrono::Utc>>, Error> {
+    time.map(|time| {
+        chrono::DateTime::parse_from_rfc3339(&time)
+            .map(|time| time.with_timezone(&chrono::Utc))
+            .map_err(|error| Error::BadRequest(format!("Invalid time {time:?}: {error}")))
+    })
+    .transpose()
+}
+
+/// Records of a file within the requested time range, checked before they're read
+pub fn filtered_datalogger_file(
+    name: &str,
+    query: ApiLogFileQuery,
+) -> Result<log_files::Filter, Error> {
+    let path = datalogger_file(name)?;
+    Ok(log_files::Filter::new(
+        path,
+        parse_time(query.start)?,
+        parse_time(query.end)?,
+    ))
+}
+
+pub fn delete_datalogger_file(name: &str) -> Result<Vec<LogFileInfo>, Error> {
+    let directory = PathBuf::from(datalogger_settings()?.directory);
+    log_files::delete(&directory, name, current_datalogger_file().as_deref())?;
+    datalogger_files()
+}
+
+pub fn pwm_channel_value(
+    channel: hardware_manager::PwmChannel,
+    value: u16,
+) -> Result<AnsPackage, Error> {
+    let applied = hardware_manager::set_pwm_channel_value(channel.clone(), value)?;
+    recorder::record_pwm(channel.clone(), applied);
     let pwm = Pwm {
+        name: Some(aliases::pwm_names(std::slice::from_ref(&channel))),
         channel: Some(vec![channel]),
-        value: Some(vec![value]),
+        value: Some(vec![applied]),
         frequency: None,
         enable: None,
     };
     let package = OutputRequest {
-        timestamp: chrono::Utc::now().to_string(),
+        timestamp: timestamp(chrono::Utc::now()),
         output: vec![OutputDevices::Pwm(pwm)],
     };
-    AnsPackage::new(Operation::Output(package))
+    Ok(AnsPackage::new(Operation::Output(package)))
 }
 
-pub fn set_pwm_enable(state: bool) -> AnsPackage {
-    hardware_manager::set_pwm_enable(state);
+pub fn set_pwm_enable(state: bool) -> Result<AnsPackage, Error> {
+    hardware_manager::set_pwm_enable(state)?;
+    recorder::record_pwm_enable(state);
     let pwm = Pwm {
+        name: None,
         channel: None,
         value: None,
         frequency: None,
         enable: Some(state),
     };
     let package = OutputRequest {
-        timestamp: chrono::Utc::now().to_string(),
+        timestamp: timestamp(chrono::Utc::now()),
         output: vec![OutputDevices::Pwm(pwm)],
     };
-    AnsPackage::new(Operation::Output(package))
+    Ok(AnsPackage::new(Operation::Output(package)))
 }
 
-pub fn set_pwm_freq_hz(freq: f32) -> AnsPackage {
-    hardware_manager::set_pwm_freq_hz(freq);
+pub fn set_pwm_freq_hz(freq: f32) -> Result<AnsPackage, Error> {
+    hardware_manager::set_pwm_freq_hz(freq)?;
+    recorder::record_pwm_frequency(freq);
     let pwm = Pwm {
+        name: None,
         channel: None,
         value: None,
         frequency: Some(freq),
         enable: None,
     };
     let package = OutputRequest {
-        timestamp: chrono::Utc::now().to_string(),
+        timestamp: timestamp(chrono::Utc::now()),
+        output: vec![OutputDevices::Pwm(pwm)],
+    };
+    Ok(AnsPackage::new(Operation::Output(package)))
+}
+
+/// Mixes a motion command into the thrusters' channels
+pub fn mixer_command(command: ApiMotionCommand) -> Result<AnsPackage, Error> {
+    command.validate()?;
+    let outputs = mixer::mix([
+        command.surge,
+        command.sway,
+        command.heave,
+        command.roll,
+        command.pitch,
+        command.yaw,
+    ]);
+
+    let mut channels = vec![];
+    let mut values = vec![];
+    for (channel, pulse) in outputs {
+        let value = hardware_manager::pwm_pulse_value(pulse)?;
+        let applied = hardware_manager::set_pwm_channel_value(channel.clone(), value)?;
+        recorder::record_pwm(channel.clone(), applied);
+        channels.push(channel);
+        values.push(applied);
+    }
+
+    let pwm = Pwm {
+        name: Some(aliases::pwm_names(&channels)),
+        channel: Some(channels),
+        value: Some(values),
+        frequency: None,
+        enable: None,
+    };
+    let package = OutputRequest {
+        timestamp: timestamp(chrono::Utc::now()),
         output: vec![OutputDevices::Pwm(pwm)],
     };
-    AnsPackage::new(Operation::Output(package))
+    Ok(AnsPackage::new(Operation::Output(package)))
 }
 
-pub fn set_led(select: hardware_manager::UserLed, state: bool) -> AnsPackage {
-    hardware_manager::set_led(select.clone(), state);
+/// Maps the gamepad's state into outputs, `None` when none of them changed, every output is
+/// tried before returning the first error
+pub fn gamepad(state: ApiGamepadState) -> Result<Option<AnsPackage>, Error> {
+    let changes = gamepad::update(&state.axes, &state.buttons);
+    if changes.is_empty() {
+        return Ok(None);
+    }
+
+    let mut channels = vec![];
+    let mut values = vec![];
+    let mut user_led = UserLED {
+        name: vec![],
+        channel: vec![],
+        value: vec![],
+    };
+    let mut neopixel = None;
+    // Every change is tried, the failed ones are retried by the next update
+    let mut errors = vec![];
+
+    for change in changes {
+        let result = match &change.command {
+            gamepad::Command::Pwm(channel, pulse) => hardware_manager::pwm_pulse_value(*pulse)
+                .and_then(|value| {
+                    let applied = hardware_manager::set_pwm_channel_value(channel.clone(), value)?;
+                    recorder::record_pwm(channel.clone(), applied);
+                    channels.push(channel.clone());
+                    values.push(applied);
+                    Ok(())
+                }),
+            gamepad::Command::UserLed(select, state) => {
+                hardware_manager::set_led(select.clone(), *state).map(|_| {
+                    recorder::record_led(select.clone(), *state);
+                    user_led.channel.push(select.clone());
+                    user_led.value.push(*state);
+                })
+            }
+            gamepad::Command::NeoPixel(color) => {
+                hardware_manager::set_neopixel(vec![*color]).map(|_| {
+                    recorder::record_neopixel(*color);
+                    neopixel = Some(NeoPixel {
+                        name: aliases::neopixel_name(),
+                        value: vec![NeoPixelRGB::from(*color)],
+                    });
+                })
+            }
+        };
+        match result {
+            Ok(()) => gamepad::applied(&change),
+            Err(error) => {
+                log::warn!("Gamepad: {error}");
+                errors.push(error);
+            }
+        }
+    }
+
+    let mut output = vec![];
+    if !channels.is_empty() {
+        output.push(OutputDevices::Pwm(Pwm {
+            name: Some(aliases::pwm_names(&channels)),
+            channel: Some(channels),
+            value: Some(values),
+            frequency: None,
+            enable: None,
+        }));
+    }
+    if !user_led.channel.is_empty() {
+        user_led.name = aliases::user_led_names(&user_led.channel);
+        output.push(OutputDevices::UserLED(user_led));
+    }
+    if let Some(neopixel) = neopixel {
+        output.push(OutputDevices::NeoPixel(neopixel));
+    }
+
+    // The applied outputs are broadcast even if others failed
+    let package = (!output.is_empty()).then(|| {
+        AnsPackage::new(Operation::Output(OutputRequest {
+            timestamp: timestamp(chrono::Utc::now()),
+            output,
+        }))
+    });
+    match errors.into_iter().next() {
+        Some(error) => Err(error),
+        None => Ok(package),
+    }
+}
+
+pub fn set_led(select: hardware_manager::UserLed, state: bool) -> Result<AnsPackage, Error> {
+    hardware_manager::set_led(select.clone(), state)?;
+    recorder::record_led(select.clone(), state);
     let user_led = UserLED {
+        name: aliases::user_led_names(std::slice::from_ref(&select)),
         channel: vec![select],
         value: vec![state],
     };
-    AnsPackage::new(Operation::Output(OutputRequest {
-        timestamp: chrono::Utc::now().to_string(),
+    Ok(AnsPackage::new(Operation::Output(OutputRequest {
+        timestamp: timestamp(chrono::Utc::now()),
         output: vec![OutputDevices::UserLED(user_led)],
-    }))
+    })))
 }
 
-pub fn get_led_all() -> AnsPackage {
+pub fn get_led_all() -> Result<AnsPackage, Error> {
     let mut user_led = UserLED {
+        name: vec![],
         channel: vec![],
         value: vec![],
     };
 
     for select in hardware_manager::UserLed::iter() {
         user_led.channel.push(select.clone());
-        user_led.value.push(hardware_manager::get_led(select));
+        user_led.value.push(hardware_manager::get_led(select)?);
     }
+    user_led.name = aliases::user_led_names(&user_led.channel);
 
-    AnsPackage::new(Operation::Output(OutputRequest {
-        timestamp: chrono::Utc::now().to_string(),
+    Ok(AnsPackage::new(Operation::Output(OutputRequest {
+        timestamp: timestamp(chrono::Utc::now()),
         output: vec![OutputDevices::UserLED(user_led)],
-    }))
+    })))
 }
 
-pub fn set_neopixel(rgb_array: Vec<[u8; 3]>) -> AnsPackage {
-    hardware_manager::set_neopixel(rgb_array.clone());
-    let rgb = NeoPixelRGB::from(rgb_array[0]);
-    let neopixel = NeoPixel { value: vec![rgb] };
-    AnsPackage::new(Operation::Output(OutputRequest {
-        timestamp: (chrono::Utc::now().to_string()),
+pub fn set_neopixel(rgb_array: Vec<[u8; 3]>) -> Result<AnsPackage, Error> {
+    let Some(first) = rgb_array.first().copied() else {
+        return Err(Error::BadRequest(
+            "NeoPixel requires at least one color".to_string(),
+        ));
+    };
+    hardware_manager::set_neopixel(rgb_array)?;
+    recorder::record_neopixel(first);
+    let rgb = NeoPixelRGB::from(first);
+    let neopixel = NeoPixel {
+        name: aliases::neopixel_name(),
+        value: vec![rgb],
+    };
+    Ok(AnsPackage::new(Operation::Output(OutputRequest {
+        timestamp: timestamp(chrono::Utc::now()),
         output: vec![OutputDevices::NeoPixel(neopixel)],
-    }))
+    })))
+}
+
+fn read_sensor(selection: &Sensors, cache: bool) -> Result<Option<InputDevices>, Error> {
+    let input = if !cache {
+        match selection {
+            Sensors::Temperature => InputDevices::new(
+                InputDeviceType::Temperature,
+                Value::Single(hardware_manager::read_temperature()?),
+            ),
+            Sensors::Pressure => InputDevices::new(
+                InputDeviceType::Pressure,
+                Value::Single(hardware_manager::read_pressure()?),
+            ),
+            Sensors::Accelerometer => InputDevices::new(
+                InputDeviceType::Accelerometer,
+                Value::Array(hardware_manager::read_accel()?.into()),
+            ),
+            Sensors::Gyroscope => InputDevices::new(
+                InputDeviceType::Gyroscope,
+                Value::Array(hardware_manager::read_gyro()?.into()),
+            ),
+            Sensors::Magnetometer => InputDevices::new(
+                InputDeviceType::Magnetometer,
+                Value::Array(hardware_manager::read_mag()?.into()),
+            ),
+            // Heading values are ordered as true and magnetic
+            Sensors::Heading => InputDevices::new(
+                InputDeviceType::Heading,
+                Value::Array(hardware_manager::read_heading()?.into()),
+            ),
+            // Vertical speed is positive up
+            Sensors::VerticalSpeed => InputDevices::new(
+                InputDeviceType::VerticalSpeed,
+                Value::Single(hardware_manager::read_vertical_speed()?),
+            ),
+            Sensors::Adc => InputDevices::new(
+                InputDeviceType::Adc,
+                Value::Array(hardware_manager::read_adc_all()?.into()),
+            ),
+            Sensors::All => return Ok(None),
+        }
+    } else {
+        match selection {
+            Sensors::Temperature => InputDevices::new(
+                InputDeviceType::Temperature,
+                Value::Single(hardware_manager::cached::read_temperature()?),
+            ),
+            Sensors::Pressure => InputDevices::new(
+                InputDeviceType::Pressure,
+                Value::Single(hardware_manager::cached::read_pressure()?),
+            ),
+            Sensors::Accelerometer => InputDevices::new(
+                InputDeviceType::Accelerometer,
+                Value::Array(hardware_manager::cached::read_accel()?.into()),
+            ),
+            Sensors::Gyroscope => InputDevices::new(
+                InputDeviceType::Gyroscope,
+                Value::Array(hardware_manager::cached::read_gyro()?.into()),
+            ),
+            Sensors::Magnetometer => InputDevices::new(
+                InputDeviceType::Magnetometer,
+                Value::Array(hardware_manager::cached::read_mag()?.into()),
+            ),
+            Sensors::Heading => InputDevices::new(
+                InputDeviceType::Heading,
+                Value::Array(hardware_manager::cached::read_heading()?.into()),
+            ),
+            Sensors::VerticalSpeed => InputDevices::new(
+                InputDeviceType::VerticalSpeed,
+                Value::Single(hardware_manager::cached::read_vertical_speed()?),
+            ),
+            Sensors::Adc => InputDevices::new(
+                InputDeviceType::Adc,
+                Value::Array(hardware_manager::cached::read_adc_all()?.into()),
+            ),
+            Sensors::All => return Ok(None),
+        }
+    };
+    Ok(Some(input))
 }
 
-pub fn reading(selection: Sensors, cache: bool) -> AnsPackage {
-    let mut sensor_reading = InputRequest {
-        timestamp: chrono::Utc::now().to_string(),
+/// Input package without readings, stamped with the time of its sample
+fn input_request(sample_time: hardware_manager::SampleTime) -> InputRequest {
+    InputRequest {
+        timestamp: timestamp(sample_time.timestamp),
+        timestamp_us: sample_time.timestamp.timestamp_micros(),
+        age_us: sample_time.instant.elapsed().as_micros() as u64,
         ..Default::default()
+    }
+}
+
+pub fn reading(selection: Sensors, cache: bool) -> Result<AnsPackage, Error> {
+    // Direct readings are sampled now, cached ones carry the monitor's sample time
+    let sample_time = if cache {
+        hardware_manager::cached::read_sample_time()?
+    } else {
+        hardware_manager::SampleTime::now()
     };
 
-    let selection_array: Vec<Sensors> = match selection {
+    let mut sensor_reading = input_request(sample_time);
+
+    match selection {
+        // Unavailable devices are left out, so the remaining ones are still served
         Sensors::All => {
-            vec![
+            for selection in [
                 Sensors::Temperature,
                 Sensors::Pressure,
                 Sensors::Accelerometer,
                 Sensors::Gyroscope,
                 Sensors::Magnetometer,
+                Sensors::Heading,
+                Sensors::VerticalSpeed,
                 Sensors::Adc,
-            ]
+            ] {
+                match read_sensor(&selection, cache) {
+                    Ok(input) => sensor_reading.input.extend(input),
+                    Err(Error::HardwareUnavailable(_)) => {}
+                    Err(error) => return Err(error),
+                }
+            }
         }
-        _ => vec![selection],
-    };
+        _ => sensor_reading.input.extend(read_sensor(&selection, cache)?),
+    }
 
-    if !cache {
-        for selection in selection_array {
-            match selection {
-                Sensors::Temperature => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Temperature,
-                    Value::Single(hardware_manager::read_temperature()),
-                )),
-                Sensors::Pressure => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Pressure,
-                    Value::Single(hardware_manager::read_pressure()),
-                )),
-                Sensors::Accelerometer => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Accelerometer,
-                    Value::Array(hardware_manager::read_accel().into()),
-                )),
-                Sensors::Gyroscope => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Gyroscope,
-                    Value::Array(hardware_manager::read_gyro().into()),
-                )),
-                Sensors::Magnetometer => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Magnetometer,
-                    Value::Array(hardware_manager::read_mag().into()),
-                )),
-                Sensors::Adc => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Adc,
-                    Value::Array(hardware_manager::read_adc_all().into()),
-                )),
-                Sensors::All => {}
-            }
+    Ok(AnsPackage::new(Operation::Input(sensor_reading)))
+}
+
+/// Latest vibration analysis, a new one is available at each completed window
+pub fn vibration() -> Result<AnsPackage, Error> {
+    if !vibration::settings().enable {
+        return Err(Error::BadRequest(
+            "Vibration analysis is disabled".to_string(),
+        ));
+    }
+    match vibration::latest() {
+        Some(analysis) => Ok(vibration_package(analysis)),
+        None => Err(Error::BadRequest(
+            "Vibration analysis has no completed window yet".to_string(),
+        )),
+    }
+}
+
+pub fn vibration_package(analysis: VibrationAnalysis) -> AnsPackage {
+    AnsPackage::new(Operation::Vibration(VibrationRequest {
+        timestamp: timestamp(chrono::Utc::now()),
+        vibration: Box::new(analysis),
+    }))
+}
+
+pub fn vibration_settings() -> Result<VibrationSettings, Error> {
+    Ok(vibration::settings())
+}
+
+/// Applies the requested changes over the current vibration settings
+pub fn set_vibration_settings(request: ApiVibrationSettings) -> Result<VibrationSettings, Error> {
+    request.validate()?;
+    let mut settings = vibration::settings();
+    if let Some(enable) = request.enable {
+        settings.enable = enable;
+    }
+    if let Some(sample_rate) = request.sample_rate {
+        settings.sample_rate = sample_rate;
+    }
+    if let Some(window_size) = request.window_size {
+        if !window_size.is_power_of_two() || window_size < vibration::MIN_WINDOW_SIZE {
+            return Err(Error::BadRequest(format!(
+                "Window size must be a power of two of at least {}, got {window_size}",
+                vibration::MIN_WINDOW_SIZE
+            )));
         }
-    } else {
-        for selection in selection_array {
-            match selection {
-                Sensors::Temperature => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Temperature,
-                    Value::Single(hardware_manager::cached::read_temperature()),
-                )),
-                Sensors::Pressure => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Pressure,
-                    Value::Single(hardware_manager::cached::read_pressure()),
-                )),
-                Sensors::Accelerometer => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Accelerometer,
-                    Value::Array(hardware_manager::cached::read_accel().into()),
-                )),
-                Sensors::Gyroscope => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Gyroscope,
-                    Value::Array(hardware_manager::cached::read_gyro().into()),
-                )),
-                Sensors::Magnetometer => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Magnetometer,
-                    Value::Array(hardware_manager::cached::read_mag().into()),
-                )),
-                Sensors::Adc => sensor_reading.input.push(InputDevices::new(
-                    InputDeviceType::Adc,
-                    Value::Array(hardware_manager::cached::read_adc_all().into()),
-                )),
-                Sensors::All => {}
-            }
+        settings.window_size = window_size;
+    }
+    if let Some(window) = request.window {
+        settings.window = window;
+    }
+    if let Some(stream) = request.stream {
+        settings.stream = stream;
+    }
+    vibration::set_settings(settings.clone());
+    Ok(settings)
+}
+
+pub fn gyro_bias() -> Result<GyroBiasState, Error> {
+    Ok(gyro_bias::state())
+}
+
+/// Applies the requested changes over the current gyro bias estimation settings
+pub fn set_gyro_bias_settings(request: ApiGyroBiasSettings) -> Result<GyroBiasState, Error> {
+    request.validate()?;
+    let mut settings = gyro_bias::state().settings;
+    if let Some(enable) = request.enable {
+        settings.enable = enable;
+    }
+    if let Some(window) = request.window {
+        settings.window = window;
+    }
+    if let Some(accel_variance_threshold) = request.accel_variance_threshold {
+        settings.accel_variance_threshold = accel_variance_threshold;
+    }
+    if let Some(gyro_variance_threshold) = request.gyro_variance_threshold {
+        settings.gyro_variance_threshold = gyro_variance_threshold;
+    }
+    if let Some(time_constant) = request.time_constant {
+        settings.time_constant = time_constant;
+    }
+    gyro_bias::set_settings(settings);
+    Ok(gyro_bias::state())
+}
+
+/// Captures the current gyroscope mean as its bias, the vehicle should be still
+pub fn capture_gyro_bias() -> Result<GyroBiasState, Error> {
+    match gyro_bias::capture() {
+        Some(_) => Ok(gyro_bias::state()),
+        None => Err(Error::BadRequest(
+            "No gyroscope samples to capture, is the monitor running?".to_string(),
+        )),
+    }
+}
+
+pub fn heading_settings() -> Result<HeadingSettings, Error> {
+    Ok(heading::settings())
+}
+
+/// Applies the requested changes over the current heading settings
+pub fn set_heading_settings(request: ApiHeadingSettings) -> Result<HeadingSettings, Error> {
+    request.validate()?;
+    let mut settings = heading::settings();
+    if let Some(declination) = request.declination {
+        settings.declination = declination;
+    }
+    if let Some(yaw_offset) = request.yaw_offset {
+        settings.yaw_offset = yaw_offset;
+    }
+    heading::set_settings(settings.clone());
+    Ok(settings)
+}
+
+pub fn mounting_settings() -> Result<MountingSettings, Error> {
+    Ok(mounting::settings())
+}
+
+/// Applies the requested changes over the current mounting settings, and saves them
+pub fn set_mounting_settings(request: ApiMountingSettings) -> Result<MountingSettings, Error> {
+    request.validate()?;
+    let mut settings = mounting::settings();
+    if let Some(rotation) = request.rotation {
+        settings.rotation = rotation;
+    }
+    if let Some(roll) = request.roll {
+        settings.roll = roll;
+    }
+    if let Some(pitch) = request.pitch {
+        settings.pitch = pitch;
+    }
+    if let Some(yaw) = request.yaw {
+        settings.yaw = yaw;
+    }
+    mounting::set_settings(settings.clone())
+        .map_err(|error| Error::Internal(format!("Failed to save mounting settings: {error}")))?;
+    Ok(settings)
+}
+
+pub fn vertical_speed_settings() -> Result<VerticalSpeedSettings, Error> {
+    Ok(vertical_speed::settings())
+}
+
+/// Applies the requested changes over the current vertical speed settings
+pub fn set_vertical_speed_settings(
+    request: ApiVerticalSpeedSettings,
+) -> Result<VerticalSpeedSettings, Error> {
+    request.validate()?;
+    let mut settings = vertical_speed::settings();
+    if let Some(medium) = request.medium {
+        settings.medium = medium;
+    }
+    if let Some(fluid_density) = request.fluid_density {
+        settings.fluid_density = fluid_density;
+    }
+    if let Some(time_constant) = request.time_constant {
+        settings.time_constant = time_constant;
+    }
+    vertical_speed::set_settings(settings.clone());
+    Ok(settings)
+}
+
+pub fn mixer_settings() -> Result<MixerSettings, Error> {
+    Ok(mixer::settings())
+}
+
+/// Applies the requested changes over the current mixer settings
+pub fn set_mixer_settings(request: ApiMixerSettings) -> Result<MixerSettings, Error> {
+    request.validate()?;
+    let mut settings = mixer::settings();
+    if let Some(frame) = request.frame {
+        if frame != settings.frame && request.thrusters.is_none() {
+            settings.thrusters = MixerSettings::with_frame(frame).thrusters;
         }
+        settings.frame = frame;
+    }
+    if let Some(thrusters) = request.thrusters {
+        settings.thrusters = thrusters;
+    }
+    if let Some(neutral) = request.neutral {
+        settings.neutral = neutral;
+    }
+    if let Some(range) = request.range {
+        settings.range = range;
     }
+    let settings = settings.resolve().map_err(Error::BadRequest)?;
+    mixer::set_settings(settings.clone())
+        .map_err(|error| Error::Internal(format!("Failed to save mixer settings: {error}")))?;
+    Ok(settings)
+}
+
+pub fn gamepad_settings() -> Result<GamepadSettings, Error> {
+    Ok(gamepad::settings())
+}
 
-    AnsPackage::new(Operation::Input(sensor_reading))
+/// Applies the requested changes over the current gamepad profiles
+pub fn set_gamepad_settings(request: ApiGamepadSettings) -> Result<GamepadSettings, Error> {
+    let mut settings = gamepad::settings();
+    if let Some(profiles) = request.profiles {
+        settings.profiles = profiles;
+    }
+    if let Some(active) = request.active {
+        settings.active = active;
+    }
+    settings.check().map_err(Error::BadRequest)?;
+    gamepad::set_settings(settings.clone())
+        .map_err(|error| Error::Internal(format!("Failed to save gamepad settings: {error}")))?;
+    Ok(settings)
+}
+
+impl From<ScriptError> for Error {
+    fn from(error: ScriptError) -> Self {
+        match error {
+            ScriptError::Invalid(message) => Self::BadRequest(message),
+            ScriptError::Io(error) => Self::Internal(format!("Scripts: {error}")),
+        }
+    }
+}
+
+pub fn scripts() -> Result<Vec<ScriptStatus>, Error> {
+    Ok(scripting::list()?)
+}
+
+pub fn script(name: &str) -> Result<ApiScript, Error> {
+    let source = scripting::source(name)?;
+    Ok(ApiScript { source })
+}
+
+pub fn save_script(name: &str, script: ApiScript) -> Result<ScriptStatus, Error> {
+    Ok(scripting::save(name, &script.source)?)
+}
+
+pub fn start_script(name: &str) -> Result<ScriptStatus, Error> {
+    Ok(scripting::start(name)?)
+}
+
+pub fn stop_script(name: &str) -> Result<ScriptStatus, Error> {
+    Ok(scripting::stop(name)?)
+}
+
+pub fn script_log(name: &str) -> Result<Vec<String>, Error> {
+    Ok(scripting::log_lines(name)?)
+}
+
+pub fn script_log_package(name: &str, line: String) -> AnsPackage {
+    AnsPackage::new(Operation::Script(ScriptRequest {
+        timestamp: timestamp(chrono::Utc::now()),
+        script: name.to_string(),
+        log: line,
+    }))
+}
+
+pub fn scripting_settings() -> Result<ScriptingSettings, Error> {
+    Ok(scripting::settings())
+}
+
+/// Applies the requested changes over the current scripting limits
+pub fn set_scripting_settings(request: ApiScriptingSettings) -> Result<ScriptingSettings, Error> {
+    request.validate()?;
+    let mut settings = scripting::settings();
+    if let Some(max_operations) = request.max_operations {
+        settings.max_operations = max_operations;
+    }
+    if let Some(max_duration) = request.max_duration {
+        settings.max_duration = max_duration;
+    }
+    scripting::set_settings(settings.clone());
+    Ok(settings)
+}
+
+impl From<SequenceError> for Error {
+    fn from(error: SequenceError) -> Self {
+        match error {
+            SequenceError::Invalid(message) => Self::BadRequest(message),
+            SequenceError::Io(error) => Self::Internal(format!("Sequences: {error}")),
+        }
+    }
+}
+
+pub fn sequences() -> Result<Vec<SequenceStatus>, Error> {
+    Ok(sequencer::list()?)
+}
+
+pub fn sequence(name: &str) -> Result<Sequence, Error> {
+    Ok(sequencer::sequence(name)?)
+}
+
+pub fn save_sequence(name: &str, sequence: Sequence) -> Result<Sequence, Error> {
+    Ok(sequencer::save(name, sequence)?)
+}
+
+pub fn start_sequence(name: &str) -> Result<SequenceStatus, Error> {
+    Ok(sequencer::start(name)?)
+}
+
+pub fn stop_sequence(name: &str) -> Result<SequenceStatus, Error> {
+    Ok(sequencer::stop(name)?)
+}
+
+pub fn pause_sequence(name: &str) -> Result<SequenceStatus, Error> {
+    Ok(sequencer::pause(name)?)
+}
+
+pub fn resume_sequence(name: &str) -> Result<SequenceStatus, Error> {
+    Ok(sequencer::resume(name)?)
+}
+
+pub fn schedules() -> Result<Vec<Schedule>, Error> {
+    Ok(sequencer::schedules())
+}
+
+pub fn set_schedules(schedules: Vec<Schedule>) -> Result<Vec<Schedule>, Error> {
+    Ok(sequencer::set_schedules(schedules)?)
+}
+
+pub fn recording() -> Result<RecordingStatus, Error> {
+    Ok(recorder::recording())
+}
+
+pub fn start_recording(name: &str) -> Result<RecordingStatus, Error> {
+    Ok(recorder::start_recording(name)?)
+}
+
+pub fn stop_recording() -> Result<Sequence, Error> {
+    Ok(recorder::stop_recording()?)
+}
+
+pub fn macros() -> Result<Vec<MacroStatus>, Error> {
+    Ok(recorder::list()?)
+}
+
+pub fn macro_commands(name: &str) -> Result<Sequence, Error> {
+    Ok(recorder::get(name)?)
+}
+
+pub fn delete_macro(name: &str) -> Result<Vec<MacroStatus>, Error> {
+    recorder::delete(name)?;
+    macros()
+}
+
+pub fn play_macro(name: &str, request: ApiMacroPlay) -> Result<MacroStatus, Error> {
+    request.validate()?;
+    Ok(recorder::play(name, request.speed.unwrap_or(1.0))?)
+}
+
+pub fn stop_macro(name: &str) -> Result<MacroStatus, Error> {
+    Ok(recorder::stop(name)?)
+}
+
+impl From<ReplayError> for Error {
+    fn from(error: ReplayError) -> Self {
+        match error {
+            ReplayError::Invalid(message) => Self::BadRequest(message),
+            ReplayError::Io(error) => Self::BadRequest(format!("Replay: {error}")),
+        }
+    }
+}
+
+pub fn replay() -> Result<ReplayStatus, Error> {
+    Ok(replay::status())
+}
+
+pub fn load_replay(request: ApiReplayLoad) -> Result<ReplayStatus, Error> {
+    Ok(replay::load(&datalogger_file(&request.file)?)?)
+}
+
+pub fn play_replay(request: ApiReplayPlay) -> Result<ReplayStatus, Error> {
+    request.validate()?;
+    Ok(replay::play(request.speed)?)
+}
+
+pub fn pause_replay() -> Result<ReplayStatus, Error> {
+    Ok(replay::pause()?)
+}
+
+pub fn seek_replay(request: ApiReplaySeek) -> Result<ReplayStatus, Error> {
+    request.validate()?;
+    Ok(replay::seek(request.position)?)
+}
+
+pub fn stop_replay() -> Result<ReplayStatus, Error> {
+    Ok(replay::stop()?)
+}
+
+pub fn aliases() -> Result<AliasSettings, Error> {
+    Ok(aliases::settings())
+}
+
+pub fn set_aliases(settings: AliasSettings) -> Result<AliasSettings, Error> {
+    settings.check().map_err(Error::BadRequest)?;
+    aliases::set_settings(settings.clone())
+        .map_err(|error| Error::Internal(format!("Failed to save the aliases: {error}")))?;
+    Ok(settings)
+}
+
+impl From<LeaseError> for Error {
+    fn from(error: LeaseError) -> Self {
+        match error {
+            LeaseError::Invalid(message) => Self::BadRequest(message),
+            LeaseError::Held(message) => Self::Conflict(message),
+        }
+    }
+}
+
+pub fn lease_package(leases: Vec<Lease>) -> AnsPackage {
+    AnsPackage::new(Operation::Lease(LeaseRequest {
+        timestamp: timestamp(chrono::Utc::now()),
+        lease: leases,
+    }))
+}
+
+pub fn leases() -> Result<Vec<Lease>, Error> {
+    Ok(lease::list())
+}
+
+/// Takes or renews, with the `token` of a held lease, control of the outputs
+pub fn acquire_lease(request: ApiLease, token: Option<&str>) -> Result<LeaseGrant, Error> {
+    request.validate()?;
+    let force = request.force.unwrap_or_default();
+    if force && !lease::is_admin(request.admin_key.as_deref()) {
+        return Err(Error::Forbidden(
+            "Forcing a lease requires the admin key".to_string(),
+        ));
+    }
+    let grant = lease::acquire(
+        &request.client,
+        request.channels,
+        std::time::Duration::from_secs_f64(request.ttl),
+        token,
+        force,
+    )?;
+    let _package = lease_package(lease::list());
+    Ok(grant)
+}
+
+pub fn release_lease(token: Option<&str>) -> Result<Vec<Lease>, Error> {
+    let token =
+        token.ok_or_else(|| Error::BadRequest("Releasing requires the lease token".to_string()))?;
+    lease::release(token)?;
+    let leases = lease::list();
+    let _package = lease_package(leases.clone());
+    Ok(leases)
+}
+
+/// Runs `operation` for the holder of `token`, if `access` isn't leased to another client.
+/// Its writes, and the ones of the scripts and sequences it starts, are checked again as
+/// they're done.
+pub fn leased<T>(
+    token: Option<&str>,
+    access: Access,
+    operation: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    lease::check(token, access)?;
+    lease::with_owner(token, operation)
+}
+
+/// Channels written by the mixer
+pub fn mixer_channels() -> Vec<hardware_manager::PwmChannel> {
+    mixer::settings()
+        .thrusters
+        .into_iter()
+        .map(|thruster| thruster.channel)
+        .collect()
+}
+
+fn arming_package(armed: bool) -> AnsPackage {
+    AnsPackage::new(Operation::Arming(ArmingRequest {
+        timestamp: timestamp(chrono::Utc::now()),
+        armed,
+    }))
+}
+
+pub fn arming() -> Result<ArmingStatus, Error> {
+    hardware_manager::arming_status()
+}
+
+pub fn arm() -> Result<ArmingStatus, Error> {
+    hardware_manager::arm()?;
+    let _package = arming_package(true);
+    hardware_manager::arming_status()
+}
+
+pub fn disarm() -> Result<ArmingStatus, Error> {
+    // Disarmed even if the PWM can't be disabled, so clients are told either way
+    let result = hardware_manager::disarm();
+    let _package = arming_package(false);
+    result?;
+    hardware_manager::arming_status()
+}
+
+pub fn arming_settings() -> Result<ArmingSettings, Error> {
+    hardware_manager::arming_settings()
+}
+
+pub fn set_arming_settings(settings: ArmingSettings) -> Result<ArmingSettings, Error> {
+    let values = std::iter::once(settings.safe_value)
+        .chain(settings.safe_values.iter().map(|safe| safe.value));
+    if values.into_iter().any(|value| value > 4096) {
+        return Err(Error::BadRequest(
+            "Safe values must be at most 4096".to_string(),
+        ));
+    }
+    hardware_manager::set_arming_settings(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn input_packages_carry_their_sample_time() {
+        let time = chrono::DateTime::from_timestamp_micros(1_700_000_000_123_456).unwrap();
+        let sample_time = hardware_manager::SampleTime {
+            timestamp: time,
+            instant: Instant::now() - Duration::from_millis(250),
+        };
+        let package = input_request(sample_time);
+        assert_eq!(package.timestamp, "2023-11-14T22:13:20.123456Z");
+        assert_eq!(package.timestamp_us, 1_700_000_000_123_456);
+        // Aged from the sample, not from the package's creation
+        assert!(
+            (250_000..1_250_000).contains(&package.age_us),
+            "{}",
+            package.age_us
+        );
+        assert!(package.input.is_empty());
+
+        let fresh = input_request(hardware_manager::SampleTime::now());
+        assert!(fresh.age_us < 1_000_000);
+        assert!(fresh.timestamp.ends_with('Z'));
+    }
 }
@@ -1,22 +1,46 @@
+use crate::log_files::{self, LogFileInfo};
 use crate::{
-    hardware_manager::{self},
+    aliases::AliasSettings,
+    gamepad::GamepadSettings,
+    gyro_bias::GyroBiasState,
+    hardware_manager::{
+        self, ArmingSettings, ArmingStatus, DataloggerSettings, DataloggerStatus, DeviceStatus,
+        MonitorSettings,
+    },
+    heading::HeadingSettings,
+    lease::{Access, Lease, LeaseGrant},
+    mixer::MixerSettings,
+    mounting::MountingSettings,
+    recorder::{MacroStatus, RecordingStatus},
+    replay::ReplayStatus,
+    scripting::{ScriptStatus, ScriptingSettings},
+    sequencer::{Schedule, Sequence, SequenceStatus},
     server::protocols::v1::{
         errors::Error,
         packages,
         structures::{
-            AnsPackage, ApiNeopixel, ApiPwmChannelValue, ApiPwmEnable, ApiPwmFrequency, ApiUserLed,
-            ServerMetadata,
+            AnsPackage, ApiDataloggerSettings, ApiDataloggerTrigger, ApiGamepadSettings,
+            ApiGamepadState, ApiGyroBiasSettings, ApiHeadingSettings, ApiLease, ApiLogFileQuery,
+            ApiMacroPlay, ApiMixerSettings, ApiMonitorSettings, ApiMotionCommand,
+            ApiMountingSettings, ApiNeopixel, ApiPwmChannelValue, ApiPwmEnable, ApiPwmFrequency,
+            ApiPwmValue, ApiRecording, ApiReplayLoad, ApiReplayPlay, ApiReplaySeek, ApiScript,
+            ApiScriptingSettings, ApiUserLed, ApiUserLedValue, ApiVerticalSpeedSettings,
+            ApiVibrationSettings, ServerMetadata,
         },
     },
+    vertical_speed::VerticalSpeedSettings,
+    vibration::VibrationSettings,
 };
-use actix_web::Responder;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, Responder};
 use mime_guess::from_path;
 use paperclip::actix::{
-    api_v2_operation, get, post,
+    api_v2_operation, delete, get, post,
     web::{self, HttpResponse, Json},
 };
 
 use std::vec;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use validator::Validate;
 
 #[derive(rust_embed::RustEmbed)]
@@ -41,14 +65,677 @@ async fn index() -> impl Responder {
 #[api_v2_operation]
 #[get("v1/settings/init")]
 async fn init() -> Result<Json<AnsPackage>, Error> {
-    let package = packages::init();
+    let package = packages::init()?;
+    Ok(Json(package))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/hardware")]
+async fn get_hardware_status() -> Result<Json<Vec<DeviceStatus>>, Error> {
+    let status = hardware_manager::devices_status()?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/monitor")]
+async fn get_monitor_settings() -> Result<Json<MonitorSettings>, Error> {
+    let settings = packages::monitor_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/monitor")]
+async fn post_monitor_settings(
+    json: web::Json<ApiMonitorSettings>,
+) -> Result<Json<MonitorSettings>, Error> {
+    let settings = packages::set_monitor_settings(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/datalogger")]
+async fn get_datalogger_settings() -> Result<Json<DataloggerSettings>, Error> {
+    let settings = packages::datalogger_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/datalogger")]
+async fn post_datalogger_settings(
+    json: web::Json<ApiDataloggerSettings>,
+) -> Result<Json<DataloggerSettings>, Error> {
+    let settings = packages::set_datalogger_settings(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+/// File being written and free disk space, logging pauses while it's below the minimum
+#[api_v2_operation]
+#[get("v1/datalogger")]
+async fn get_datalogger() -> Result<Json<DataloggerStatus>, Error> {
+    let status = packages::datalogger_status()?;
+    Ok(Json(status))
+}
+
+/// Holds or releases the manual trigger, the datalogger writes while it's held and the
+/// post-trigger time after
+#[api_v2_operation]
+#[post("v1/datalogger/trigger")]
+async fn post_datalogger_trigger(
+    json: web::Json<ApiDataloggerTrigger>,
+) -> Result<Json<DataloggerStatus>, Error> {
+    let status = packages::set_datalogger_trigger(json.into_inner())?;
+    Ok(Json(status))
+}
+
+/// Log files in the datalogger's directory, with their time span and row count
+#[api_v2_operation]
+#[get("v1/datalogger/files")]
+async fn get_datalogger_files() -> Result<Json<Vec<LogFileInfo>>, Error> {
+    let files = blocking(packages::datalogger_files).await?;
+    Ok(Json(files))
+}
+
+/// Runs file system work, like scanning log files, off the server's threads
+async fn blocking<T: Send + 'static>(
+    operation: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    web::block(operation)
+        .await
+        .map_err(|error| Error::Internal(format!("Blocking task failed: {error}")))?
+}
+
+/// Downloads a log file, with support for single byte ranges. With `start` or `end`,
+/// only the records in between are streamed, uncompressed and without ranges.
+#[api_v2_operation]
+#[get("v1/datalogger/files/{name}")]
+async fn get_datalogger_file(
+    request: HttpRequest,
+    name: web::Path<String>,
+    query: web::Query<ApiLogFileQuery>,
+) -> Result<HttpResponse, Error> {
+    download_log_file(&request, &name.into_inner(), query.into_inner()).await
+}
+
+/// Downloads the file being written, only its complete blocks while compressed
+#[api_v2_operation]
+#[get("v1/datalogger/current")]
+async fn get_datalogger_current(
+    request: HttpRequest,
+    query: web::Query<ApiLogFileQuery>,
+) -> Result<HttpResponse, Error> {
+    let name = packages::current_datalogger_file_name()?;
+    download_log_file(&request, &name, query.into_inner()).await
+}
+
+#[api_v2_operation]
+#[delete("v1/datalogger/files/{name}")]
+async fn delete_datalogger_file(name: web::Path<String>) -> Result<Json<Vec<LogFileInfo>>, Error> {
+    let name = name.into_inner();
+    let files = blocking(move || packages::delete_datalogger_file(&name)).await?;
+    Ok(Json(files))
+}
+
+/// Chunk size of file downloads, in bytes
+const DOWNLOAD_CHUNK: usize = 64 * 1024;
+
+/// Single range of a `Range: bytes=...` header as first and last byte, `None` to send the
+/// whole content, `Some(Err)` if it's out of `size`
+fn byte_range(request: &HttpRequest, size: u64) -> Option<Result<(u64, u64), ()>> {
+    let header = request.headers().get(header::RANGE)?.to_str().ok()?;
+    let (first, last) = header.strip_prefix("bytes=")?.split_once('-')?;
+    // Multiple ranges aren't supported, the whole content is sent instead
+    if last.contains(',') {
+        return None;
+    }
+    let range = match (first.trim(), last.trim()) {
+        ("", suffix) => {
+            let length: u64 = suffix.parse().ok()?;
+            (size.saturating_sub(length), size.checked_sub(1)?)
+        }
+        (first, "") => (first.parse().ok()?, size.saturating_sub(1)),
+        (first, last) => (
+            first.parse().ok()?,
+            last.parse::<u64>().ok()?.min(size.saturating_sub(1)),
+        ),
+    };
+    if range.0 > range.1 || range.0 >= size {
+        return Some(Err(()));
+    }
+    Some(Ok(range))
+}
+
+/// Sends what's written to it as chunks of a streamed response, fails once the client is gone
+struct ChunkSender(tokio::sync::mpsc::Sender<std::io::Result<web::Bytes>>);
+
+impl std::io::Write for ChunkSender {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .blocking_send(Ok(web::Bytes::copy_from_slice(buffer)))
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::BrokenPipe))?;
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Streams the records as they're read, their size isn't known beforehand
+fn download_filtered_log_file(filter: log_files::Filter, name: &str) -> HttpResponse {
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+    actix_web::rt::task::spawn_blocking(move || {
+        let mut output =
+            std::io::BufWriter::with_capacity(DOWNLOAD_CHUNK, ChunkSender(sender.clone()));
+        match filter.write(&mut output) {
+            Ok(()) => {}
+            // The client went away
+            Err(log_files::LogFileError::Io(error))
+                if error.kind() == std::io::ErrorKind::BrokenPipe => {}
+            Err(error) => {
+                log::warn!("Log files: Filtered download stopped: {error}");
+                let _ = sender.blocking_send(Err(std::io::Error::other(error.to_string())));
+            }
+        }
+    });
+    let chunks = futures::stream::poll_fn(move |context| receiver.poll_recv(context));
+    HttpResponse::Ok()
+        .content_type(from_path(name).first_or_octet_stream().as_ref())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{name}\""),
+        ))
+        .streaming(chunks)
+}
+
+async fn download_log_file(
+    request: &HttpRequest,
+    name: &str,
+    query: ApiLogFileQuery,
+) -> Result<HttpResponse, Error> {
+    if query.start.is_some() || query.end.is_some() {
+        let filter = packages::filtered_datalogger_file(name, query)?;
+        return Ok(download_filtered_log_file(
+            filter,
+            log_files::uncompressed_name(name),
+        ));
+    }
+
+    let path = packages::datalogger_file(name)?;
+    let size = std::fs::metadata(&path)
+        .map_err(|error| Error::Internal(format!("Log files: {error}")))?
+        .len();
+
+    let (status, (first, last)) = match byte_range(request, size) {
+        None => (StatusCode::OK, (0, size.saturating_sub(1))),
+        Some(Ok(range)) => (StatusCode::PARTIAL_CONTENT, range),
+        Some(Err(())) => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{size}")))
+                .finish())
+        }
+    };
+    let length = if size == 0 { 0 } else { last - first + 1 };
+
+    let mut response = HttpResponse::build(status);
+    response
+        .content_type(from_path(name).first_or_octet_stream().as_ref())
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{name}\""),
+        ))
+        .no_chunking(length);
+    if status == StatusCode::PARTIAL_CONTENT {
+        response.insert_header((
+            header::CONTENT_RANGE,
+            format!("bytes {first}-{last}/{size}"),
+        ));
+    }
+
+    let mut file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|error| Error::Internal(format!("Log files: {error}")))?;
+    file.seek(std::io::SeekFrom::Start(first))
+        .await
+        .map_err(|error| Error::Internal(format!("Log files: {error}")))?;
+    // The file may still grow while being written, only the announced length is sent
+    let chunks = futures::stream::unfold((file, length), |(mut file, remaining)| async move {
+        if remaining == 0 {
+            return None;
+        }
+        let mut chunk = vec![0; DOWNLOAD_CHUNK.min(remaining as usize)];
+        match file.read(&mut chunk).await {
+            Ok(0) => None,
+            Ok(read) => {
+                chunk.truncate(read);
+                Some((Ok(web::Bytes::from(chunk)), (file, remaining - read as u64)))
+            }
+            Err(error) => Some((Err(error), (file, 0))),
+        }
+    });
+    Ok(response.streaming(chunks))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/vibration")]
+async fn get_vibration_settings() -> Result<Json<VibrationSettings>, Error> {
+    let settings = packages::vibration_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/vibration")]
+async fn post_vibration_settings(
+    json: web::Json<ApiVibrationSettings>,
+) -> Result<Json<VibrationSettings>, Error> {
+    let settings = packages::set_vibration_settings(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/gyro_bias")]
+async fn get_gyro_bias() -> Result<Json<GyroBiasState>, Error> {
+    let state = packages::gyro_bias()?;
+    Ok(Json(state))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/gyro_bias")]
+async fn post_gyro_bias_settings(
+    json: web::Json<ApiGyroBiasSettings>,
+) -> Result<Json<GyroBiasState>, Error> {
+    let state = packages::set_gyro_bias_settings(json.into_inner())?;
+    Ok(Json(state))
+}
+
+/// Captures the current gyroscope mean as its bias, the vehicle should be still
+#[api_v2_operation]
+#[post("v1/settings/gyro_bias/capture")]
+async fn post_gyro_bias_capture() -> Result<Json<GyroBiasState>, Error> {
+    let state = packages::capture_gyro_bias()?;
+    Ok(Json(state))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/heading")]
+async fn get_heading_settings() -> Result<Json<HeadingSettings>, Error> {
+    let settings = packages::heading_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/heading")]
+async fn post_heading_settings(
+    json: web::Json<ApiHeadingSettings>,
+) -> Result<Json<HeadingSettings>, Error> {
+    let settings = packages::set_heading_settings(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/mounting")]
+async fn get_mounting_settings() -> Result<Json<MountingSettings>, Error> {
+    let settings = packages::mounting_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/mounting")]
+async fn post_mounting_settings(
+    json: web::Json<ApiMountingSettings>,
+) -> Result<Json<MountingSettings>, Error> {
+    let settings = packages::set_mounting_settings(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/vertical_speed")]
+async fn get_vertical_speed_settings() -> Result<Json<VerticalSpeedSettings>, Error> {
+    let settings = packages::vertical_speed_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/vertical_speed")]
+async fn post_vertical_speed_settings(
+    json: web::Json<ApiVerticalSpeedSettings>,
+) -> Result<Json<VerticalSpeedSettings>, Error> {
+    let settings = packages::set_vertical_speed_settings(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/aliases")]
+async fn get_aliases() -> Result<Json<AliasSettings>, Error> {
+    let settings = packages::aliases()?;
+    Ok(Json(settings))
+}
+
+/// Replaces the names of the outputs, which are accepted in place of `Ch1`, `Led1`...
+#[api_v2_operation]
+#[post("v1/settings/aliases")]
+async fn post_aliases(json: web::Json<AliasSettings>) -> Result<Json<AliasSettings>, Error> {
+    let settings = packages::set_aliases(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/mixer")]
+async fn get_mixer_settings() -> Result<Json<MixerSettings>, Error> {
+    let settings = packages::mixer_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/mixer")]
+async fn post_mixer_settings(
+    json: web::Json<ApiMixerSettings>,
+) -> Result<Json<MixerSettings>, Error> {
+    let settings = packages::set_mixer_settings(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/gamepad")]
+async fn get_gamepad_settings() -> Result<Json<GamepadSettings>, Error> {
+    let settings = packages::gamepad_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/gamepad")]
+async fn post_gamepad_settings(
+    json: web::Json<ApiGamepadSettings>,
+) -> Result<Json<GamepadSettings>, Error> {
+    let settings = packages::set_gamepad_settings(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/scripting")]
+async fn get_scripting_settings() -> Result<Json<ScriptingSettings>, Error> {
+    let settings = packages::scripting_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/scripting")]
+async fn post_scripting_settings(
+    json: web::Json<ApiScriptingSettings>,
+) -> Result<Json<ScriptingSettings>, Error> {
+    let settings = packages::set_scripting_settings(json.into_inner())?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[get("v1/scripts")]
+async fn get_scripts() -> Result<Json<Vec<ScriptStatus>>, Error> {
+    let scripts = packages::scripts()?;
+    Ok(Json(scripts))
+}
+
+#[api_v2_operation]
+#[get("v1/scripts/{name}")]
+async fn get_script(name: web::Path<String>) -> Result<Json<ApiScript>, Error> {
+    let script = packages::script(&name.into_inner())?;
+    Ok(Json(script))
+}
+
+/// Uploads a script, replacing the previous one with the same name
+#[api_v2_operation]
+#[post("v1/scripts/{name}")]
+async fn post_script(
+    name: web::Path<String>,
+    json: web::Json<ApiScript>,
+) -> Result<Json<ScriptStatus>, Error> {
+    let status = packages::save_script(&name.into_inner(), json.into_inner())?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/scripts/{name}/start")]
+async fn post_script_start(
+    request: HttpRequest,
+    name: web::Path<String>,
+) -> Result<Json<ScriptStatus>, Error> {
+    let status = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::start_script(&name.into_inner())
+    })?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/scripts/{name}/stop")]
+async fn post_script_stop(
+    request: HttpRequest,
+    name: web::Path<String>,
+) -> Result<Json<ScriptStatus>, Error> {
+    let status = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::stop_script(&name.into_inner())
+    })?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[get("v1/scripts/{name}/log")]
+async fn get_script_log(name: web::Path<String>) -> Result<Json<Vec<String>>, Error> {
+    let log = packages::script_log(&name.into_inner())?;
+    Ok(Json(log))
+}
+
+#[api_v2_operation]
+#[get("v1/sequences")]
+async fn get_sequences() -> Result<Json<Vec<SequenceStatus>>, Error> {
+    let sequences = packages::sequences()?;
+    Ok(Json(sequences))
+}
+
+#[api_v2_operation]
+#[get("v1/sequences/{name}")]
+async fn get_sequence(name: web::Path<String>) -> Result<Json<Sequence>, Error> {
+    let sequence = packages::sequence(&name.into_inner())?;
+    Ok(Json(sequence))
+}
+
+/// Saves a sequence, replacing the previous one with the same name
+#[api_v2_operation]
+#[post("v1/sequences/{name}")]
+async fn post_sequence(
+    name: web::Path<String>,
+    json: web::Json<Sequence>,
+) -> Result<Json<Sequence>, Error> {
+    let sequence = packages::save_sequence(&name.into_inner(), json.into_inner())?;
+    Ok(Json(sequence))
+}
+
+#[api_v2_operation]
+#[post("v1/sequences/{name}/start")]
+async fn post_sequence_start(
+    request: HttpRequest,
+    name: web::Path<String>,
+) -> Result<Json<SequenceStatus>, Error> {
+    let status = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::start_sequence(&name.into_inner())
+    })?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/sequences/{name}/stop")]
+async fn post_sequence_stop(
+    request: HttpRequest,
+    name: web::Path<String>,
+) -> Result<Json<SequenceStatus>, Error> {
+    let status = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::stop_sequence(&name.into_inner())
+    })?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/sequences/{name}/pause")]
+async fn post_sequence_pause(
+    request: HttpRequest,
+    name: web::Path<String>,
+) -> Result<Json<SequenceStatus>, Error> {
+    let status = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::pause_sequence(&name.into_inner())
+    })?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/sequences/{name}/resume")]
+async fn post_sequence_resume(
+    request: HttpRequest,
+    name: web::Path<String>,
+) -> Result<Json<SequenceStatus>, Error> {
+    let status = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::resume_sequence(&name.into_inner())
+    })?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/schedules")]
+async fn get_schedules() -> Result<Json<Vec<Schedule>>, Error> {
+    let schedules = packages::schedules()?;
+    Ok(Json(schedules))
+}
+
+/// Replaces all the schedules
+#[api_v2_operation]
+#[post("v1/settings/schedules")]
+async fn post_schedules(json: web::Json<Vec<Schedule>>) -> Result<Json<Vec<Schedule>>, Error> {
+    let schedules = packages::set_schedules(json.into_inner())?;
+    Ok(Json(schedules))
+}
+
+#[api_v2_operation]
+#[get("v1/recording")]
+async fn get_recording() -> Result<Json<RecordingStatus>, Error> {
+    let status = packages::recording()?;
+    Ok(Json(status))
+}
+
+/// Records every output command into a new macro, until stopped
+#[api_v2_operation]
+#[post("v1/recording/start")]
+async fn post_recording_start(
+    json: web::Json<ApiRecording>,
+) -> Result<Json<RecordingStatus>, Error> {
+    let status = packages::start_recording(&json.into_inner().name)?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/recording/stop")]
+async fn post_recording_stop() -> Result<Json<Sequence>, Error> {
+    let recorded = packages::stop_recording()?;
+    Ok(Json(recorded))
+}
+
+#[api_v2_operation]
+#[get("v1/macros")]
+async fn get_macros() -> Result<Json<Vec<MacroStatus>>, Error> {
+    let macros = packages::macros()?;
+    Ok(Json(macros))
+}
+
+#[api_v2_operation]
+#[get("v1/macros/{name}")]
+async fn get_macro(name: web::Path<String>) -> Result<Json<Sequence>, Error> {
+    let recorded = packages::macro_commands(&name.into_inner())?;
+    Ok(Json(recorded))
+}
+
+#[api_v2_operation]
+#[delete("v1/macros/{name}")]
+async fn delete_macro(name: web::Path<String>) -> Result<Json<Vec<MacroStatus>>, Error> {
+    let macros = packages::delete_macro(&name.into_inner())?;
+    Ok(Json(macros))
+}
+
+#[api_v2_operation]
+#[post("v1/macros/{name}/play")]
+async fn post_macro_play(
+    request: HttpRequest,
+    name: web::Path<String>,
+    json: web::Json<ApiMacroPlay>,
+) -> Result<Json<MacroStatus>, Error> {
+    let status = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::play_macro(&name.into_inner(), json.into_inner())
+    })?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/macros/{name}/stop")]
+async fn post_macro_stop(
+    request: HttpRequest,
+    name: web::Path<String>,
+) -> Result<Json<MacroStatus>, Error> {
+    let status = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::stop_macro(&name.into_inner())
+    })?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[get("v1/replay")]
+async fn get_replay() -> Result<Json<ReplayStatus>, Error> {
+    let status = packages::replay()?;
+    Ok(Json(status))
+}
+
+/// Loads a datalogger file, paused, to be replayed in place of the navigator's readings
+#[api_v2_operation]
+#[post("v1/replay/load")]
+async fn post_replay_load(json: web::Json<ApiReplayLoad>) -> Result<Json<ReplayStatus>, Error> {
+    let status = packages::load_replay(json.into_inner())?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/replay/play")]
+async fn post_replay_play(json: web::Json<ApiReplayPlay>) -> Result<Json<ReplayStatus>, Error> {
+    let status = packages::play_replay(json.into_inner())?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/replay/pause")]
+async fn post_replay_pause() -> Result<Json<ReplayStatus>, Error> {
+    let status = packages::pause_replay()?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[post("v1/replay/seek")]
+async fn post_replay_seek(json: web::Json<ApiReplaySeek>) -> Result<Json<ReplayStatus>, Error> {
+    let status = packages::seek_replay(json.into_inner())?;
+    Ok(Json(status))
+}
+
+/// Unloads the file, back to the navigator's readings
+#[api_v2_operation]
+#[post("v1/replay/stop")]
+async fn post_replay_stop() -> Result<Json<ReplayStatus>, Error> {
+    let status = packages::stop_replay()?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[get("v1/input/vibration")]
+async fn get_vibration() -> Result<Json<AnsPackage>, Error> {
+    let package = packages::vibration()?;
     Ok(Json(package))
 }
 
 #[api_v2_operation]
 #[get("v1/input/{sensor}")]
 async fn get_sensor(sensor: web::Path<packages::Sensors>) -> Result<Json<AnsPackage>, Error> {
-    let package = packages::reading(sensor.into_inner(), false);
+    let package = packages::reading(sensor.into_inner(), false)?;
     Ok(Json(package))
 }
 
@@ -57,68 +744,230 @@ async fn get_sensor(sensor: web::Path<packages::Sensors>) -> Result<Json<AnsPack
 async fn get_sensor_cached(
     sensor: web::Path<packages::Sensors>,
 ) -> Result<Json<AnsPackage>, Error> {
-    let package = packages::reading(sensor.into_inner(), true);
+    let package = packages::reading(sensor.into_inner(), true)?;
     Ok(Json(package))
 }
 
+#[api_v2_operation]
+#[get("v1/arming")]
+async fn get_arming() -> Result<Json<ArmingStatus>, Error> {
+    let status = packages::arming()?;
+    Ok(Json(status))
+}
+
+/// Lets the channels follow the writes, refused if any pre-arm check fails
+#[api_v2_operation]
+#[post("v1/arming/arm")]
+async fn post_arm(request: HttpRequest) -> Result<Json<ArmingStatus>, Error> {
+    let status = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::arm()
+    })?;
+    Ok(Json(status))
+}
+
+/// Disables the PWM and holds the channels at their safe values, open to every client
+#[api_v2_operation]
+#[post("v1/arming/disarm")]
+async fn post_disarm() -> Result<Json<ArmingStatus>, Error> {
+    let status = packages::disarm()?;
+    Ok(Json(status))
+}
+
+#[api_v2_operation]
+#[get("v1/settings/arming")]
+async fn get_arming_settings() -> Result<Json<ArmingSettings>, Error> {
+    let settings = packages::arming_settings()?;
+    Ok(Json(settings))
+}
+
+#[api_v2_operation]
+#[post("v1/settings/arming")]
+async fn post_arming_settings(
+    request: HttpRequest,
+    json: web::Json<ArmingSettings>,
+) -> Result<Json<ArmingSettings>, Error> {
+    let settings = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::set_arming_settings(json.into_inner())
+    })?;
+    Ok(Json(settings))
+}
+
+/// Token of the client's lease, sent on the `X-Lease-Token` header
+fn lease_token(request: &HttpRequest) -> Option<&str> {
+    request
+        .headers()
+        .get("X-Lease-Token")
+        .and_then(|token| token.to_str().ok())
+}
+
+#[api_v2_operation]
+#[get("v1/lease")]
+async fn get_leases() -> Result<Json<Vec<Lease>>, Error> {
+    let leases = packages::leases()?;
+    Ok(Json(leases))
+}
+
+/// Takes control of the outputs, or renews the lease of the `X-Lease-Token` header
+#[api_v2_operation]
+#[post("v1/lease/acquire")]
+async fn post_lease_acquire(
+    request: HttpRequest,
+    json: web::Json<ApiLease>,
+) -> Result<Json<LeaseGrant>, Error> {
+    let grant = packages::acquire_lease(json.into_inner(), lease_token(&request))?;
+    Ok(Json(grant))
+}
+
+#[api_v2_operation]
+#[post("v1/lease/release")]
+async fn post_lease_release(request: HttpRequest) -> Result<Json<Vec<Lease>>, Error> {
+    let leases = packages::release_lease(lease_token(&request))?;
+    Ok(Json(leases))
+}
+
 #[api_v2_operation]
 #[get("v1/output/user_led")]
 async fn get_led_all() -> Result<Json<AnsPackage>, Error> {
-    let package = packages::get_led_all();
+    let package = packages::get_led_all()?;
     Ok(Json(package))
 }
 
 #[api_v2_operation]
 #[post("v1/output/user_led")]
-async fn post_led(json: web::Json<ApiUserLed>) -> Result<Json<AnsPackage>, Error> {
+async fn post_led(
+    request: HttpRequest,
+    json: web::Json<ApiUserLed>,
+) -> Result<Json<AnsPackage>, Error> {
     let userled = json.into_inner();
-    let package = packages::set_led(userled.userled, userled.value);
+    let package = packages::leased(lease_token(&request), Access::Lights, || {
+        packages::set_led(userled.userled, userled.value)
+    })?;
+    Ok(Json(package))
+}
+
+/// Sets a LED by its name or alias
+#[api_v2_operation]
+#[post("v1/output/user_led/{led}")]
+async fn post_led_named(
+    request: HttpRequest,
+    led: web::Path<hardware_manager::UserLed>,
+    json: web::Json<ApiUserLedValue>,
+) -> Result<Json<AnsPackage>, Error> {
+    let package = packages::leased(lease_token(&request), Access::Lights, || {
+        packages::set_led(led.into_inner(), json.into_inner().value)
+    })?;
     Ok(Json(package))
 }
 
 #[api_v2_operation]
 #[post("v1/output/neopixel")]
-async fn post_neopixel(json: web::Json<ApiNeopixel>) -> Result<Json<AnsPackage>, Error> {
+async fn post_neopixel(
+    request: HttpRequest,
+    json: web::Json<ApiNeopixel>,
+) -> Result<Json<AnsPackage>, Error> {
     let neopixel = json.into_inner();
-    let package = packages::set_neopixel(vec![[neopixel.red, neopixel.green, neopixel.blue]]);
+    let package = packages::leased(lease_token(&request), Access::Lights, || {
+        packages::set_neopixel(vec![[neopixel.red, neopixel.green, neopixel.blue]])
+    })?;
     Ok(Json(package))
 }
 
 #[api_v2_operation]
 #[post("v1/output/pwm/channel/value")]
-async fn post_pwm(json: web::Json<ApiPwmChannelValue>) -> Result<Json<AnsPackage>, Error> {
+async fn post_pwm(
+    request: HttpRequest,
+    json: web::Json<ApiPwmChannelValue>,
+) -> Result<Json<AnsPackage>, Error> {
     let pwm = json.into_inner();
+    let channels = [pwm.channel.clone()];
     match pwm.validate() {
         Ok(_) => {
-            let package = packages::pwm_channel_value(pwm.channel, pwm.value);
+            let package =
+                packages::leased(lease_token(&request), Access::Channels(&channels), || {
+                    packages::pwm_channel_value(pwm.channel, pwm.value)
+                })?;
             Ok(Json(package))
         }
         Err(e) => Err(Error::from(e)),
     }
 }
 
+/// Sets a channel by its name or alias
+#[api_v2_operation]
+#[post("v1/output/pwm/{channel}/value")]
+async fn post_pwm_named(
+    request: HttpRequest,
+    channel: web::Path<hardware_manager::PwmChannel>,
+    json: web::Json<ApiPwmValue>,
+) -> Result<Json<AnsPackage>, Error> {
+    let channel = channel.into_inner();
+    let pwm = json.into_inner();
+    pwm.validate()?;
+    let channels = [channel.clone()];
+    let package = packages::leased(lease_token(&request), Access::Channels(&channels), || {
+        packages::pwm_channel_value(channel, pwm.value)
+    })?;
+    Ok(Json(package))
+}
+
 #[api_v2_operation]
 #[post("v1/output/pwm/enable")]
-async fn post_pwm_enable(json: web::Json<ApiPwmEnable>) -> Result<Json<AnsPackage>, Error> {
+async fn post_pwm_enable(
+    request: HttpRequest,
+    json: web::Json<ApiPwmEnable>,
+) -> Result<Json<AnsPackage>, Error> {
     let bool = json.into_inner().enable;
-    let package = packages::set_pwm_enable(bool);
+    let package = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::set_pwm_enable(bool)
+    })?;
     Ok(Json(package))
 }
 
 #[api_v2_operation]
 #[post("v1/output/pwm/frequency")]
-async fn post_pwm_frequency(json: web::Json<ApiPwmFrequency>) -> Result<Json<AnsPackage>, Error> {
+async fn post_pwm_frequency(
+    request: HttpRequest,
+    json: web::Json<ApiPwmFrequency>,
+) -> Result<Json<AnsPackage>, Error> {
     let pwm = json.into_inner();
     match pwm.validate() {
         Ok(_) => {
-            let package = packages::set_pwm_freq_hz(pwm.frequency);
-            hardware_manager::set_pwm_enable(true);
+            let package = packages::leased(lease_token(&request), Access::Everything, || {
+                packages::set_pwm_freq_hz(pwm.frequency)
+            })?;
             Ok(Json(package))
         }
         Err(e) => Err(Error::from(e)),
     }
 }
 
+#[api_v2_operation]
+#[post("v1/output/mixer")]
+async fn post_mixer(
+    request: HttpRequest,
+    json: web::Json<ApiMotionCommand>,
+) -> Result<Json<AnsPackage>, Error> {
+    let channels = packages::mixer_channels();
+    let package = packages::leased(lease_token(&request), Access::Channels(&channels), || {
+        packages::mixer_command(json.into_inner())
+    })?;
+    Ok(Json(package))
+}
+
+/// Answers with the changed outputs, or nothing if the gamepad's state didn't change any
+#[api_v2_operation]
+#[post("v1/output/gamepad")]
+async fn post_gamepad(
+    request: HttpRequest,
+    json: web::Json<ApiGamepadState>,
+) -> Result<Json<Option<AnsPackage>>, Error> {
+    // Mappings may target any output
+    let package = packages::leased(lease_token(&request), Access::Everything, || {
+        packages::gamepad(json.into_inner())
+    })?;
+    Ok(Json(package))
+}
+
 /// The "register_service" route is used by BlueOS extensions manager
 #[api_v2_operation]
 #[get("register_service")]
@@ -129,13 +978,87 @@ async fn get_server_metadata() -> Result<Json<ServerMetadata>, Error> {
 
 pub fn register_services(cfg: &mut web::ServiceConfig) {
     cfg.service(index)
+        .service(init)
+        .service(get_hardware_status)
+        .service(get_monitor_settings)
+        .service(post_monitor_settings)
+        .service(get_datalogger_settings)
+        .service(post_datalogger_settings)
+        .service(get_datalogger)
+        .service(post_datalogger_trigger)
+        .service(get_datalogger_files)
+        .service(get_datalogger_file)
+        .service(get_datalogger_current)
+        .service(delete_datalogger_file)
+        .service(get_vibration_settings)
+        .service(post_vibration_settings)
+        .service(get_gyro_bias)
+        .service(post_gyro_bias_settings)
+        .service(post_gyro_bias_capture)
+        .service(get_heading_settings)
+        .service(post_heading_settings)
+        .service(get_mounting_settings)
+        .service(post_mounting_settings)
+        .service(get_vertical_speed_settings)
+        .service(post_vertical_speed_settings)
+        .service(get_aliases)
+        .service(post_aliases)
+        .service(get_mixer_settings)
+        .service(post_mixer_settings)
+        .service(get_gamepad_settings)
+        .service(post_gamepad_settings)
+        .service(get_scripting_settings)
+        .service(post_scripting_settings)
+        .service(get_scripts)
+        .service(get_script)
+        .service(post_script)
+        .service(post_script_start)
+        .service(post_script_stop)
+        .service(get_script_log)
+        .service(get_sequences)
+        .service(get_sequence)
+        .service(post_sequence)
+        .service(post_sequence_start)
+        .service(post_sequence_stop)
+        .service(post_sequence_pause)
+        .service(post_sequence_resume)
+        .service(get_schedules)
+        .service(post_schedules)
+        .service(get_recording)
+        .service(post_recording_start)
+        .service(post_recording_stop)
+        .service(get_macros)
+        .service(get_macro)
+        .service(delete_macro)
+        .service(post_macro_play)
+        .service(post_macro_stop)
+        .service(get_replay)
+        .service(post_replay_load)
+        .service(post_replay_play)
+        .service(post_replay_pause)
+        .service(post_replay_seek)
+        .service(post_replay_stop)
+        // Registered before the generic sensor routes, which would match it
+        .service(get_vibration)
         .service(get_sensor)
         .service(get_sensor_cached)
         .service(get_led_all)
+        .service(get_leases)
+        .service(get_arming)
+        .service(post_arm)
+        .service(post_disarm)
+        .service(get_arming_settings)
+        .service(post_arming_settings)
+        .service(post_lease_acquire)
+        .service(post_lease_release)
         .service(get_server_metadata)
         .service(post_pwm_enable)
         .service(post_pwm_frequency)
         .service(post_pwm)
+        .service(post_pwm_named)
+        .service(post_mixer)
+        .service(post_gamepad)
         .service(post_neopixel)
-        .service(post_led);
+        .service(post_led)
+        .service(post_led_named);
 }
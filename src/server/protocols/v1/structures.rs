@@ -1,5 +1,15 @@
+use crate::data_logger::{
+    Column, LogCompression, LogFormat, RetentionSettings, RotationSettings, Timestamp,
+    TriggerSettings,
+};
+use crate::gamepad::Profile;
 use crate::hardware_manager;
+use crate::lease::Lease;
+use crate::mixer::{Frame, Thruster};
+use crate::mounting::Rotation;
 use crate::server::protocols::v1::websocket;
+use crate::vertical_speed::Medium;
+use crate::vibration::{VibrationAnalysis, WindowFunction};
 use paperclip::actix::Apiv2Schema;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
@@ -14,10 +24,7 @@ pub struct AnsPackage {
 
 impl AnsPackage {
     pub fn new(operation: Operation) -> AnsPackage {
-        let package = AnsPackage {
-            model: "Navigator_v4".to_string(),
-            operation,
-        };
+        let package = Self::unsent(operation);
 
         // All the AnsPackage's requests can be broadcasted to websocket clients
         // This helps all clients to be in sync.
@@ -25,6 +32,14 @@ impl AnsPackage {
 
         package
     }
+
+    /// Package that isn't broadcast, like the ones written by the datalogger
+    pub fn unsent(operation: Operation) -> AnsPackage {
+        AnsPackage {
+            model: "Navigator_v4".to_string(),
+            operation,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Apiv2Schema)]
@@ -32,6 +47,11 @@ impl AnsPackage {
 pub enum Operation {
     Input(InputRequest),
     Output(OutputRequest),
+    Vibration(VibrationRequest),
+    Script(ScriptRequest),
+    Lease(LeaseRequest),
+    Arming(ArmingRequest),
+    Datalogger(DataloggerRequest),
     Settings,
 }
 
@@ -51,6 +71,9 @@ pub enum OutputDevices {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Pwm {
+    /// Alias of each channel, or its own name if it has none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub channel: Option<Vec<hardware_manager::PwmChannel>>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -80,26 +103,245 @@ pub struct ApiPwmChannelValue {
     pub value: u16,
 }
 
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiPwmValue {
+    #[validate(range(min = 0, max = 4096))]
+    pub value: u16,
+}
+
 #[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
 pub struct ApiPwmFrequency {
     #[validate(range(min = 24, max = 1526))]
     pub frequency: f32,
 }
 
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiMonitorSettings {
+    pub enable: Option<bool>,
+    #[validate(range(min = 0.1, max = 200.0))]
+    pub rate: Option<f64>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiDataloggerSettings {
+    pub enable: Option<bool>,
+    #[validate(range(min = 0.1, max = 200.0))]
+    pub rate: Option<f64>,
+    pub directory: Option<String>,
+    pub filename: Option<String>,
+    pub format: Option<LogFormat>,
+    pub compression: Option<LogCompression>,
+    pub compression_level: Option<i32>,
+    pub events: Option<bool>,
+    pub timestamps: Option<Vec<Timestamp>>,
+    pub columns: Option<Vec<Column>>,
+    /// Replaces the rotation settings
+    pub rotation: Option<RotationSettings>,
+    /// Replaces the retention settings
+    pub retention: Option<RetentionSettings>,
+    pub min_free_space: Option<u64>,
+    /// Replaces the trigger settings
+    pub trigger: Option<TriggerSettings>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
+pub struct ApiDataloggerTrigger {
+    /// Triggers until released
+    pub active: bool,
+}
+
+/// Time range of the records to download, RFC 3339
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
+pub struct ApiLogFileQuery {
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiVibrationSettings {
+    pub enable: Option<bool>,
+    #[validate(range(min = 1.0, max = 200.0))]
+    pub sample_rate: Option<f64>,
+    #[validate(range(min = 16, max = 4096))]
+    pub window_size: Option<usize>,
+    pub window: Option<WindowFunction>,
+    pub stream: Option<bool>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiGyroBiasSettings {
+    pub enable: Option<bool>,
+    #[validate(range(min = 0.1, max = 60.0))]
+    pub window: Option<f64>,
+    #[validate(range(min = 0.0))]
+    pub accel_variance_threshold: Option<f32>,
+    #[validate(range(min = 0.0))]
+    pub gyro_variance_threshold: Option<f32>,
+    #[validate(range(min = 0.1, max = 3600.0))]
+    pub time_constant: Option<f64>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiHeadingSettings {
+    #[validate(range(min = -180.0, max = 180.0))]
+    pub declination: Option<f32>,
+    #[validate(range(min = -180.0, max = 180.0))]
+    pub yaw_offset: Option<f32>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiMountingSettings {
+    pub rotation: Option<Rotation>,
+    #[validate(range(min = -360.0, max = 360.0))]
+    pub roll: Option<f32>,
+    #[validate(range(min = -360.0, max = 360.0))]
+    pub pitch: Option<f32>,
+    #[validate(range(min = -360.0, max = 360.0))]
+    pub yaw: Option<f32>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiVerticalSpeedSettings {
+    pub medium: Option<Medium>,
+    #[validate(range(min = 900.0, max = 1100.0))]
+    pub fluid_density: Option<f32>,
+    #[validate(range(min = 0.05, max = 60.0))]
+    pub time_constant: Option<f32>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiMixerSettings {
+    /// Changing the frame without thrusters places them on the first channels
+    pub frame: Option<Frame>,
+    pub thrusters: Option<Vec<Thruster>>,
+    #[validate(range(min = 500.0, max = 2500.0))]
+    pub neutral: Option<f32>,
+    #[validate(range(min = 0.0, max = 1000.0))]
+    pub range: Option<f32>,
+}
+
+/// Motion command for the mixer, each degree of freedom between -1 and 1
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiMotionCommand {
+    #[serde(default)]
+    #[validate(range(min = -1.0, max = 1.0))]
+    pub surge: f32,
+    #[serde(default)]
+    #[validate(range(min = -1.0, max = 1.0))]
+    pub sway: f32,
+    #[serde(default)]
+    #[validate(range(min = -1.0, max = 1.0))]
+    pub heave: f32,
+    #[serde(default)]
+    #[validate(range(min = -1.0, max = 1.0))]
+    pub roll: f32,
+    #[serde(default)]
+    #[validate(range(min = -1.0, max = 1.0))]
+    pub pitch: f32,
+    #[serde(default)]
+    #[validate(range(min = -1.0, max = 1.0))]
+    pub yaw: f32,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
+pub struct ApiGamepadSettings {
+    /// Profile to use, empty to stop mapping the gamepad
+    pub active: Option<String>,
+    pub profiles: Option<Vec<Profile>>,
+}
+
+/// Raw gamepad state, as reported by the browser's Gamepad API
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
+pub struct ApiGamepadState {
+    #[serde(default)]
+    pub axes: Vec<f32>,
+    #[serde(default)]
+    pub buttons: Vec<bool>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
+pub struct ApiScript {
+    pub source: String,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiScriptingSettings {
+    #[validate(range(min = 1000))]
+    pub max_operations: Option<u64>,
+    #[validate(range(min = 1.0, max = 604800.0))]
+    pub max_duration: Option<f64>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiLease {
+    /// Name shown to the other clients
+    pub client: String,
+    /// Channels to lease, every output if not set
+    pub channels: Option<Vec<hardware_manager::PwmChannel>>,
+    /// Time until the lease expires, in seconds
+    #[validate(range(min = 1.0, max = 3600.0))]
+    pub ttl: f64,
+    /// Revokes the other clients' leases over the same outputs
+    pub force: Option<bool>,
+    /// Required to force, if the server has one
+    pub admin_key: Option<String>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
+pub struct ApiRecording {
+    pub name: String,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiMacroPlay {
+    /// Replay speed relative to the recording, 1 by default
+    #[validate(range(min = 0.01, max = 100.0))]
+    pub speed: Option<f64>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
+pub struct ApiReplayLoad {
+    /// Name of a CSV file in the datalogger's directory, it may be compressed
+    pub file: String,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiReplayPlay {
+    /// Playback rate relative to the recording, the previous one by default
+    #[validate(range(min = 0.01, max = 100.0))]
+    pub speed: Option<f64>,
+}
+
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize, Validate)]
+pub struct ApiReplaySeek {
+    /// Time since the first sample, in seconds
+    #[validate(range(min = 0.0))]
+    pub position: f64,
+}
+
 #[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
 pub struct ApiUserLed {
     pub userled: hardware_manager::UserLed,
     pub value: bool,
 }
 
+#[derive(Apiv2Schema, Debug, Deserialize, Serialize)]
+pub struct ApiUserLedValue {
+    pub value: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UserLED {
+    /// Alias of each LED, or its own name if it has none
+    pub name: Vec<String>,
     pub channel: Vec<hardware_manager::UserLed>,
     pub value: Vec<bool>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NeoPixel {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
     pub value: Vec<NeoPixelRGB>,
 }
 
@@ -122,10 +364,49 @@ impl NeoPixelRGB {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InputRequest {
+    /// Sample time, RFC 3339 in UTC
     pub timestamp: String,
+    /// Sample time, microseconds since Unix epoch
+    pub timestamp_us: i64,
+    /// Time elapsed since the sample was taken until the package creation, microseconds
+    pub age_us: u64,
     pub input: Vec<InputDevices>,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct VibrationRequest {
+    pub timestamp: String,
+    pub vibration: Box<VibrationAnalysis>,
+}
+
+/// Output line of a running script
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScriptRequest {
+    pub timestamp: String,
+    pub script: String,
+    pub log: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArmingRequest {
+    pub timestamp: String,
+    pub armed: bool,
+}
+
+/// Datalogger status, sent when it disables itself
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DataloggerRequest {
+    pub timestamp: String,
+    pub datalogger: hardware_manager::DataloggerStatus,
+}
+
+/// Leases held after one was taken, released or expired
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaseRequest {
+    pub timestamp: String,
+    pub lease: Vec<Lease>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum InputDeviceType {
     Temperature,
@@ -134,7 +415,13 @@ pub enum InputDeviceType {
     Accelerometer,
     Gyroscope,
     Magnetometer,
+    Heading,
+    VerticalSpeed,
     Adc,
+    Leak,
+    PwmEnable,
+    Pwm,
+    UserLeds,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct InputDevices {
@@ -153,7 +440,14 @@ impl InputDevices {
             InputDeviceType::Accelerometer => "m/s2".to_string(),
             InputDeviceType::Gyroscope => "rad/s".to_string(),
             InputDeviceType::Magnetometer => "uT".to_string(),
+            InputDeviceType::Heading => "deg".to_string(),
+            InputDeviceType::VerticalSpeed => "m/s".to_string(),
             InputDeviceType::Adc => "V".to_string(),
+            // States are 1 or 0, PWM values are in 4096ths of the period
+            InputDeviceType::Leak
+            | InputDeviceType::PwmEnable
+            | InputDeviceType::Pwm
+            | InputDeviceType::UserLeds => "".to_string(),
         };
 
         Self {
@@ -175,6 +469,8 @@ impl Default for InputRequest {
     fn default() -> Self {
         Self {
             timestamp: "".to_string(),
+            timestamp_us: 0,
+            age_us: 0,
             input: vec![],
         }
     }
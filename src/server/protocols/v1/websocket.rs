@@ -1,7 +1,17 @@
+use crate::aliases::AliasSettings;
+use crate::event_log::{self, Event};
+use crate::hardware_manager::{ArmingSettings, PwmChannel, UserLed};
+use crate::lease::Access;
+use crate::sequencer::{Schedule, Sequence};
 use crate::server::protocols::v1::{
+    errors::Error,
     packages,
     structures::{
-        AnsPackage, ApiNeopixel, ApiPwmChannelValue, ApiPwmEnable, ApiPwmFrequency, ApiUserLed,
+        ApiDataloggerSettings, ApiDataloggerTrigger, ApiGamepadSettings, ApiGamepadState,
+        ApiGyroBiasSettings, ApiHeadingSettings, ApiLease, ApiMixerSettings, ApiMonitorSettings,
+        ApiMotionCommand, ApiMountingSettings, ApiNeopixel, ApiPwmChannelValue, ApiPwmEnable,
+        ApiPwmFrequency, ApiPwmValue, ApiScript, ApiScriptingSettings, ApiUserLed, ApiUserLedValue,
+        ApiVerticalSpeedSettings, ApiVibrationSettings,
     },
 };
 use actix::{Actor, Addr, AsyncContext, Handler, Message, StreamHandler};
@@ -20,6 +30,7 @@ use std::{
     str::FromStr,
     sync::{Arc, Mutex},
 };
+use validator::Validate;
 
 pub struct StringMessage(String);
 
@@ -27,11 +38,6 @@ impl Message for StringMessage {
     type Result = ();
 }
 
-#[derive(Serialize, Debug)]
-pub struct WebsocketError {
-    pub error: String,
-}
-
 #[derive(Debug)]
 pub struct WebsocketActorContent {
     pub actor: Addr<WebsocketActor>,
@@ -51,7 +57,7 @@ impl WebsocketManager {
 
         let string = serde_json::to_string_pretty(value).unwrap();
         for client in &self.clients {
-            let is_match = client.re.as_ref().map_or(false, |regx| regx.is_match(name));
+            let is_match = client.re.as_ref().is_some_and(|regx| regx.is_match(name));
             if is_match {
                 client.actor.do_send(StringMessage(string.clone()));
             }
@@ -75,13 +81,45 @@ pub fn send_to_websockets(message: Value) {
 pub struct WebsocketActor {
     server: Arc<Mutex<WebsocketManager>>,
     pub filter: String,
+    /// Token of the lease taken through this connection
+    lease: Option<String>,
+    /// Address of the client, for the event log
+    peer: String,
 }
 
 impl WebsocketActor {
-    pub fn new(message_filter: String) -> Self {
+    pub fn new(message_filter: String, peer: String) -> Self {
         Self {
             server: MANAGER.clone(),
             filter: message_filter,
+            lease: None,
+            peer,
+        }
+    }
+
+    /// Leases taken through a connection are kept by it, and released when it closes
+    fn request_lease(&mut self, request: &str) -> String {
+        let v: Vec<&str> = request.trim_start_matches('/').splitn(3, '/').collect();
+        match (v.get(1).copied(), v.get(2)) {
+            (None, None) => reply_content(packages::leases()),
+            (Some("acquire"), Some(content)) => match serde_json::from_str::<ApiLease>(content) {
+                Ok(data) => {
+                    let grant = packages::acquire_lease(data, self.lease.as_deref());
+                    if let Ok(grant) = &grant {
+                        self.lease = Some(grant.token.clone());
+                    }
+                    reply_content(grant)
+                }
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            },
+            (Some("release"), None) => {
+                reply_content(packages::release_lease(self.lease.take().as_deref()))
+            }
+            _ => json!("Error: Invalid command selected").to_string(),
         }
     }
 }
@@ -109,6 +147,9 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
                 actor: ctx.address(),
                 re: Regex::new(&self.filter).ok(),
             });
+        event_log::record(Event::ClientConnected {
+            client: self.peer.clone(),
+        });
     }
 
     fn finished(&mut self, ctx: &mut Self::Context) {
@@ -118,18 +159,25 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WebsocketActor {
             .unwrap()
             .clients
             .retain(|x| x.actor != ctx.address());
+
+        if let Some(token) = self.lease.take() {
+            let _leases = packages::release_lease(Some(&token));
+        }
+        event_log::record(Event::ClientDisconnected {
+            client: self.peer.clone(),
+        });
     }
 
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
             Ok(ws::Message::Text(text)) => {
-                let v: Vec<&str> = text.split("&&").collect();
-
-                for request in v {
+                for request in split_requests(&text) {
                     let request = request.trim();
-                    if request.starts_with('/') {
-                        ctx.text(request_endpoint(request));
+                    if request.starts_with("/lease") {
+                        ctx.text(self.request_lease(request));
+                    } else if request.starts_with('/') {
+                        ctx.text(request_endpoint(request, self.lease.as_deref()));
                     } else {
                         let error_msg = format!(
                             "{} {}, missing / ?",
@@ -160,7 +208,11 @@ pub async fn websocket(
 
     log::debug!("New websocket with filter {:#?}", &filter);
 
-    ws::start(WebsocketActor::new(filter), &req, stream)
+    let peer = req
+        .peer_addr()
+        .map(|address| address.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    ws::start(WebsocketActor::new(filter, peer), &req, stream)
 }
 
 #[derive(Deserialize, Apiv2Schema)]
@@ -169,30 +221,292 @@ pub struct WebsocketQuery {
     filter: Option<String>,
 }
 
-fn request_endpoint(request: &str) -> String {
+/// Acknowledges a command, or reports why it failed
+fn reply<T>(result: Result<T, Error>) -> String {
+    match result {
+        Ok(_package) => json!("Ok: Command received").to_string(),
+        Err(error) => json!(format!("Error: {error}")).to_string(),
+    }
+}
+
+/// Answers with the requested content, or reports why it failed
+fn reply_content<T: Serialize>(result: Result<T, Error>) -> String {
+    match result {
+        Ok(content) => json!(content).to_string(),
+        Err(error) => json!(format!("Error: {error}")).to_string(),
+    }
+}
+
+/// Settings may carry paths, so the JSON content is everything after the setting's name
+fn request_settings(request: &str, lease: Option<&str>) -> String {
+    let v: Vec<&str> = request.trim_start_matches('/').splitn(3, '/').collect();
+    match (v.get(1).copied(), v.get(2)) {
+        (Some("monitor"), None) => reply_content(packages::monitor_settings()),
+        (Some("monitor"), Some(content)) => {
+            match serde_json::from_str::<ApiMonitorSettings>(content) {
+                Ok(data) => reply_content(packages::set_monitor_settings(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        (Some("datalogger"), None) => reply_content(packages::datalogger_settings()),
+        (Some("datalogger"), Some(content)) => {
+            match serde_json::from_str::<ApiDataloggerSettings>(content) {
+                Ok(data) => reply_content(packages::set_datalogger_settings(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        (Some("vibration"), None) => reply_content(packages::vibration_settings()),
+        (Some("vibration"), Some(content)) => {
+            match serde_json::from_str::<ApiVibrationSettings>(content) {
+                Ok(data) => reply_content(packages::set_vibration_settings(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        (Some("gyro_bias"), None) => reply_content(packages::gyro_bias()),
+        (Some("gyro_bias"), Some(&"capture")) => reply_content(packages::capture_gyro_bias()),
+        (Some("gyro_bias"), Some(content)) => {
+            match serde_json::from_str::<ApiGyroBiasSettings>(content) {
+                Ok(data) => reply_content(packages::set_gyro_bias_settings(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        (Some("heading"), None) => reply_content(packages::heading_settings()),
+        (Some("heading"), Some(content)) => {
+            match serde_json::from_str::<ApiHeadingSettings>(content) {
+                Ok(data) => reply_content(packages::set_heading_settings(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        (Some("mounting"), None) => reply_content(packages::mounting_settings()),
+        (Some("mounting"), Some(content)) => {
+            match serde_json::from_str::<ApiMountingSettings>(content) {
+                Ok(data) => reply_content(packages::set_mounting_settings(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        (Some("schedules"), None) => reply_content(packages::schedules()),
+        (Some("schedules"), Some(content)) => {
+            match serde_json::from_str::<Vec<Schedule>>(content) {
+                Ok(data) => reply_content(packages::set_schedules(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        (Some("scripting"), None) => reply_content(packages::scripting_settings()),
+        (Some("scripting"), Some(content)) => {
+            match serde_json::from_str::<ApiScriptingSettings>(content) {
+                Ok(data) => reply_content(packages::set_scripting_settings(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        (Some("gamepad"), None) => reply_content(packages::gamepad_settings()),
+        (Some("gamepad"), Some(content)) => {
+            match serde_json::from_str::<ApiGamepadSettings>(content) {
+                Ok(data) => reply_content(packages::set_gamepad_settings(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        (Some("arming"), None) => reply_content(packages::arming_settings()),
+        (Some("arming"), Some(content)) => match serde_json::from_str::<ArmingSettings>(content) {
+            Ok(data) => reply_content(packages::leased(lease, Access::Everything, || {
+                packages::set_arming_settings(data)
+            })),
+            Err(err) => json!(format!(
+                "Error: JSON was not well-formatted. Details: {}",
+                err
+            ))
+            .to_string(),
+        },
+        (Some("aliases"), None) => reply_content(packages::aliases()),
+        (Some("aliases"), Some(content)) => match serde_json::from_str::<AliasSettings>(content) {
+            Ok(data) => reply_content(packages::set_aliases(data)),
+            Err(err) => json!(format!(
+                "Error: JSON was not well-formatted. Details: {}",
+                err
+            ))
+            .to_string(),
+        },
+        (Some("mixer"), None) => reply_content(packages::mixer_settings()),
+        (Some("mixer"), Some(content)) => match serde_json::from_str::<ApiMixerSettings>(content) {
+            Ok(data) => reply_content(packages::set_mixer_settings(data)),
+            Err(err) => json!(format!(
+                "Error: JSON was not well-formatted. Details: {}",
+                err
+            ))
+            .to_string(),
+        },
+        (Some("vertical_speed"), None) => reply_content(packages::vertical_speed_settings()),
+        (Some("vertical_speed"), Some(content)) => {
+            match serde_json::from_str::<ApiVerticalSpeedSettings>(content) {
+                Ok(data) => reply_content(packages::set_vertical_speed_settings(data)),
+                Err(err) => json!(format!(
+                    "Error: JSON was not well-formatted. Details: {}",
+                    err
+                ))
+                .to_string(),
+            }
+        }
+        _ => json!("Error: Invalid command selected").to_string(),
+    }
+}
+
+/// Requests of a message, split on `&&` except for script uploads, whose source may contain it
+fn split_requests(text: &str) -> Vec<&str> {
+    let text = text.trim();
+    let script_upload = text
+        .strip_prefix("/scripts/")
+        .and_then(|request| request.split_once('/'))
+        .is_some_and(|(_, content)| content.starts_with('{'));
+    if script_upload {
+        vec![text]
+    } else {
+        text.split("&&").collect()
+    }
+}
+
+/// Scripts carry free text, so the JSON content is everything after the script's name
+fn request_scripts(request: &str, lease: Option<&str>) -> String {
+    let v: Vec<&str> = request.trim_start_matches('/').splitn(3, '/').collect();
+    match (v.get(1).copied(), v.get(2).copied()) {
+        (None, None) => reply_content(packages::scripts()),
+        (Some(name), None) => reply_content(packages::script(name)),
+        (Some(name), Some("start")) => {
+            reply_content(packages::leased(lease, Access::Everything, || {
+                packages::start_script(name)
+            }))
+        }
+        (Some(name), Some("stop")) => {
+            reply_content(packages::leased(lease, Access::Everything, || {
+                packages::stop_script(name)
+            }))
+        }
+        (Some(name), Some("log")) => reply_content(packages::script_log(name)),
+        (Some(name), Some(content)) => match serde_json::from_str::<ApiScript>(content) {
+            Ok(data) => reply_content(packages::save_script(name, data)),
+            Err(err) => json!(format!(
+                "Error: JSON was not well-formatted. Details: {}",
+                err
+            ))
+            .to_string(),
+        },
+        _ => json!("Error: Invalid command selected").to_string(),
+    }
+}
+
+fn request_sequences(request: &str, lease: Option<&str>) -> String {
+    let v: Vec<&str> = request.trim_start_matches('/').splitn(3, '/').collect();
+    match (v.get(1).copied(), v.get(2).copied()) {
+        (None, None) => reply_content(packages::sequences()),
+        (Some(name), None) => reply_content(packages::sequence(name)),
+        (Some(name), Some("start")) => {
+            reply_content(packages::leased(lease, Access::Everything, || {
+                packages::start_sequence(name)
+            }))
+        }
+        (Some(name), Some("stop")) => {
+            reply_content(packages::leased(lease, Access::Everything, || {
+                packages::stop_sequence(name)
+            }))
+        }
+        (Some(name), Some("pause")) => {
+            reply_content(packages::leased(lease, Access::Everything, || {
+                packages::pause_sequence(name)
+            }))
+        }
+        (Some(name), Some("resume")) => {
+            reply_content(packages::leased(lease, Access::Everything, || {
+                packages::resume_sequence(name)
+            }))
+        }
+        (Some(name), Some(content)) => match serde_json::from_str::<Sequence>(content) {
+            Ok(data) => reply_content(packages::save_sequence(name, data)),
+            Err(err) => json!(format!(
+                "Error: JSON was not well-formatted. Details: {}",
+                err
+            ))
+            .to_string(),
+        },
+        _ => json!("Error: Invalid command selected").to_string(),
+    }
+}
+
+/// Output writes are checked against the leases, `lease` is the token of the connection's one
+fn request_endpoint(request: &str, lease: Option<&str>) -> String {
     let v: Vec<&str> = request.trim_start_matches('/').splitn(5, '/').collect();
     match v[0] {
-        "input" => {
-            let _package = packages::reading(packages::Sensors::from_str(v[1]).unwrap(), false);
-            json!("Ok: Command received").to_string()
-        }
+        "input" if v.get(1) == Some(&"vibration") => reply(packages::vibration()),
+        "input" => match v.get(1).map(|sensor| packages::Sensors::from_str(sensor)) {
+            Some(Ok(sensor)) => reply(packages::reading(sensor, false)),
+            _ => json!("Error: Invalid sensor selected").to_string(),
+        },
         "output" => match v[1] {
             "userled" => {
                 if v.len() == 2 {
-                    let _package = packages::get_led_all();
-                    json!("Ok: Command received").to_string()
+                    reply(packages::get_led_all())
                 } else if v.len() == 3 {
                     match serde_json::from_str::<ApiUserLed>(v[2]) {
-                        Ok(data) => {
-                            let _package = packages::set_led(data.userled, data.value);
-                            json!("Ok: Command received").to_string()
-                        }
+                        Ok(data) => reply(packages::leased(lease, Access::Lights, || {
+                            packages::set_led(data.userled, data.value)
+                        })),
                         Err(err) => json!(format!(
                             "Error: JSON was not well-formatted. Details: {}",
                             err
                         ))
                         .to_string(),
                     }
+                } else if v.len() == 4 {
+                    // The LED is named in the path, by its name or alias
+                    match (
+                        UserLed::from_str(v[2]),
+                        serde_json::from_str::<ApiUserLedValue>(v[3]),
+                    ) {
+                        (Ok(led), Ok(data)) => {
+                            reply(packages::leased(lease, Access::Lights, || {
+                                packages::set_led(led, data.value)
+                            }))
+                        }
+                        (Err(err), _) => json!(format!("Error: {}", err)).to_string(),
+                        (_, Err(err)) => json!(format!(
+                            "Error: JSON was not well-formatted. Details: {}",
+                            err
+                        ))
+                        .to_string(),
+                    }
                 } else {
                     json!("Error: Invalid command selected").to_string()
                 }
@@ -200,10 +514,43 @@ fn request_endpoint(request: &str) -> String {
             "neopixel" => {
                 if v.len() == 3 {
                     match serde_json::from_str::<ApiNeopixel>(v[2]) {
+                        Ok(data) => reply(packages::leased(lease, Access::Lights, || {
+                            packages::set_neopixel(vec![[data.red, data.green, data.blue]])
+                        })),
+                        Err(err) => json!(format!(
+                            "Error: JSON was not well-formatted. Details: {}",
+                            err
+                        ))
+                        .to_string(),
+                    }
+                } else {
+                    json!("Error: Invalid command selected").to_string()
+                }
+            }
+            "gamepad" => {
+                if v.len() == 3 {
+                    match serde_json::from_str::<ApiGamepadState>(v[2]) {
+                        Ok(data) => reply(packages::leased(lease, Access::Everything, || {
+                            packages::gamepad(data)
+                        })),
+                        Err(err) => json!(format!(
+                            "Error: JSON was not well-formatted. Details: {}",
+                            err
+                        ))
+                        .to_string(),
+                    }
+                } else {
+                    json!("Error: Invalid command selected").to_string()
+                }
+            }
+            "mixer" => {
+                if v.len() == 3 {
+                    match serde_json::from_str::<ApiMotionCommand>(v[2]) {
                         Ok(data) => {
-                            let _package =
-                                packages::set_neopixel(vec![[data.red, data.green, data.blue]]);
-                            json!("Ok: Command received").to_string()
+                            let channels = packages::mixer_channels();
+                            reply(packages::leased(lease, Access::Channels(&channels), || {
+                                packages::mixer_command(data)
+                            }))
                         }
                         Err(err) => json!(format!(
                             "Error: JSON was not well-formatted. Details: {}",
@@ -217,13 +564,11 @@ fn request_endpoint(request: &str) -> String {
             }
             "pwm" => match v[2] {
                 "enable" => {
-                    let _package: AnsPackage;
                     if v.len() == 4 {
                         match serde_json::from_str::<ApiPwmEnable>(v[3]) {
-                            Ok(data) => {
-                                let _package = packages::set_pwm_enable(data.enable);
-                                json!("Ok: Command received").to_string()
-                            }
+                            Ok(data) => reply(packages::leased(lease, Access::Everything, || {
+                                packages::set_pwm_enable(data.enable)
+                            })),
                             Err(err) => json!(format!(
                                 "Error: JSON was not well-formatted. Details: {}",
                                 err
@@ -236,13 +581,11 @@ fn request_endpoint(request: &str) -> String {
                 }
 
                 "frequency" => {
-                    let _package: AnsPackage;
                     if v.len() == 4 {
                         match serde_json::from_str::<ApiPwmFrequency>(v[3]) {
-                            Ok(data) => {
-                                let _package = packages::set_pwm_freq_hz(data.frequency);
-                                json!("Ok: Command received").to_string()
-                            }
+                            Ok(data) => reply(packages::leased(lease, Access::Everything, || {
+                                packages::set_pwm_freq_hz(data.frequency)
+                            })),
                             Err(err) => json!(format!(
                                 "Error: JSON was not well-formatted. Details: {}",
                                 err
@@ -253,14 +596,14 @@ fn request_endpoint(request: &str) -> String {
                         json!("Error: Invalid command selected").to_string()
                     }
                 }
-                _ => {
-                    let _package: AnsPackage;
+                "channel" => {
                     if v.len() == 5 {
                         match serde_json::from_str::<ApiPwmChannelValue>(v[4]) {
                             Ok(data) => {
-                                let _package =
-                                    packages::pwm_channel_value(data.channel, data.value);
-                                json!("Ok: Command received").to_string()
+                                let channels = [data.channel.clone()];
+                                reply(packages::leased(lease, Access::Channels(&channels), || {
+                                    packages::pwm_channel_value(data.channel, data.value)
+                                }))
                             }
                             Err(err) => json!(format!(
                                 "Error: JSON was not well-formatted. Details: {}",
@@ -272,10 +615,87 @@ fn request_endpoint(request: &str) -> String {
                         json!("Error: Invalid command selected").to_string()
                     }
                 }
+                // The channel is named in the path, by its name or alias
+                channel => {
+                    if v.len() == 5 && v[3] == "value" {
+                        match (
+                            PwmChannel::from_str(channel),
+                            serde_json::from_str::<ApiPwmValue>(v[4]),
+                        ) {
+                            (Ok(channel), Ok(data)) => {
+                                let channels = [channel.clone()];
+                                reply(data.validate().map_err(Error::from).and_then(|_| {
+                                    packages::leased(lease, Access::Channels(&channels), || {
+                                        packages::pwm_channel_value(channel, data.value)
+                                    })
+                                }))
+                            }
+                            (Err(err), _) => json!(format!("Error: {}", err)).to_string(),
+                            (_, Err(err)) => json!(format!(
+                                "Error: JSON was not well-formatted. Details: {}",
+                                err
+                            ))
+                            .to_string(),
+                        }
+                    } else {
+                        json!("Error: Invalid command selected").to_string()
+                    }
+                }
             },
             "get_connected" => json!(MANAGER.lock().unwrap().get_client_count()).to_string(),
             _ => json!("Error: Invalid command selected").to_string(),
         },
+        "arming" => match v.get(1).copied() {
+            None => reply_content(packages::arming()),
+            Some("arm") => reply_content(packages::leased(lease, Access::Everything, || {
+                packages::arm()
+            })),
+            Some("disarm") => reply_content(packages::disarm()),
+            _ => json!("Error: Invalid command selected").to_string(),
+        },
+        "datalogger" => match (v.get(1).copied(), v.get(2)) {
+            (None, None) => reply_content(packages::datalogger_status()),
+            (Some("trigger"), Some(content)) => {
+                match serde_json::from_str::<ApiDataloggerTrigger>(content) {
+                    Ok(data) => reply_content(packages::set_datalogger_trigger(data)),
+                    Err(err) => json!(format!(
+                        "Error: JSON was not well-formatted. Details: {}",
+                        err
+                    ))
+                    .to_string(),
+                }
+            }
+            _ => json!("Error: Invalid command selected").to_string(),
+        },
+        "settings" => request_settings(request, lease),
+        "scripts" => request_scripts(request, lease),
+        "sequences" => request_sequences(request, lease),
         _ => format!("{} {}", json!("Error: Invalid command:"), request),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_requests_keeps_script_uploads_whole() {
+        let requests = split_requests("/input/adc && /settings/monitor &&/arming");
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].trim(), "/settings/monitor");
+
+        let source = "let level = 3;\nif level > 1 && level < 5 { print(\"ok && done\"); }";
+        let message = format!("/scripts/and_test/{}", json!({ "source": source }));
+        let requests = split_requests(&message);
+        assert_eq!(requests, vec![message.as_str()]);
+
+        // The source reaches the script's request whole
+        let parts: Vec<&str> = requests[0].trim_start_matches('/').splitn(3, '/').collect();
+        let script: ApiScript = serde_json::from_str(parts[2]).expect("Content is a script");
+        assert_eq!(script.source, source);
+
+        // Other script requests are still split
+        let requests = split_requests("/scripts/and_test/start && /scripts/and_test/log");
+        assert_eq!(requests.len(), 2);
+    }
+}
@@ -0,0 +1,102 @@
+use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+lazy_static! {
+    static ref DIRECTORY: RwLock<PathBuf> = RwLock::new(PathBuf::from("./"));
+}
+
+/// Sets the directory where the settings files are stored
+pub fn init(directory: PathBuf) {
+    if let Ok(mut current) = DIRECTORY.write() {
+        *current = directory;
+    }
+}
+
+/// Directory where the settings files are stored
+pub fn directory() -> PathBuf {
+    DIRECTORY
+        .read()
+        .map(|directory| directory.clone())
+        .unwrap_or_default()
+}
+
+fn path(name: &str) -> PathBuf {
+    directory().join(format!("{name}.json"))
+}
+
+/// Loads the settings saved as `name`, `None` if there are none or they can't be parsed
+pub fn load<T: DeserializeOwned>(name: &str) -> Option<T> {
+    let path = path(name);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return None,
+        Err(error) => {
+            log::warn!("Settings: failed to read {path:?}: {error}");
+            return None;
+        }
+    };
+
+    match serde_json::from_str(&content) {
+        Ok(value) => Some(value),
+        Err(error) => {
+            log::warn!("Settings: failed to parse {path:?}: {error}");
+            None
+        }
+    }
+}
+
+/// Checks that `name` is usable as a file name, `kind` tells what's named in the error
+pub fn check_name(kind: &str, name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(format!(
+            "{kind} names may only contain letters, digits, '_' and '-', got {name:?}"
+        ));
+    }
+    Ok(())
+}
+
+/// Sorted names of the files with `extension` in the `directory` within the settings one, none
+/// if it doesn't exist yet
+pub fn names(directory: &str, extension: &str) -> std::io::Result<Vec<String>> {
+    let mut names = vec![];
+    match std::fs::read_dir(self::directory().join(directory)) {
+        Ok(entries) => {
+            for entry in entries {
+                let path = entry?.path();
+                if path.extension().is_some_and(|current| current == extension) {
+                    if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                        names.push(name.to_string());
+                    }
+                }
+            }
+        }
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+        Err(error) => return Err(error),
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Saves the settings as `name`, replacing the previous ones
+pub fn save<T: Serialize>(name: &str, value: &T) -> std::io::Result<()> {
+    let path = path(name);
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+
+    // Written aside and renamed, so a crash never leaves a truncated file behind
+    let temporary = path.with_extension("json.tmp");
+    std::fs::write(&temporary, serde_json::to_string_pretty(value)?)?;
+    std::fs::rename(temporary, path)
+}
+
+/// Removes the settings saved as `name`
+pub fn remove(name: &str) -> std::io::Result<()> {
+    std::fs::remove_file(path(name))
+}
@@ -0,0 +1,226 @@
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Instant;
+
+const GRAVITY: f32 = 9.80665;
+
+/// Longest interval between samples before the estimation restarts, in seconds
+const MAX_INTERVAL: f32 = 1.0;
+
+/// Reference pressure used by the barometric altitude, in kPa
+const SEA_LEVEL_PRESSURE: f32 = 101.325;
+
+/// Medium where the pressure is measured, defines how pressure becomes height
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum Medium {
+    Water,
+    Air,
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct VerticalSpeedSettings {
+    pub medium: Medium,
+    /// Water density in kg/m3, about 997 for fresh and 1025 for salt water
+    pub fluid_density: f32,
+    /// Crossover time between pressure and accelerometer, in seconds.
+    /// Longer values trust the accelerometer for longer periods.
+    pub time_constant: f32,
+}
+
+impl Default for VerticalSpeedSettings {
+    fn default() -> Self {
+        Self {
+            medium: Medium::Water,
+            fluid_density: 1025.0,
+            time_constant: 1.0,
+        }
+    }
+}
+
+#[derive(Default)]
+struct VerticalSpeedEstimator {
+    settings: VerticalSpeedSettings,
+    /// Estimated height in meters, positive up
+    height: f32,
+    /// Estimated vertical speed in m/s, positive up
+    speed: f32,
+    /// Gravity direction in the vehicle frame, low-pass filtered from the accelerometer
+    down: Option<[f32; 3]>,
+    last_update: Option<Instant>,
+}
+
+lazy_static! {
+    static ref ESTIMATOR: Mutex<VerticalSpeedEstimator> = Default::default();
+}
+
+impl VerticalSpeedEstimator {
+    /// Height above the pressure reference in meters, positive up
+    fn pressure_height(&self, pressure: f32) -> f32 {
+        match self.settings.medium {
+            Medium::Water => -water_depth(pressure, self.settings.fluid_density),
+            Medium::Air => 44330.0 * (1.0 - (pressure / SEA_LEVEL_PRESSURE).powf(1.0 / 5.255)),
+        }
+    }
+
+    /// Vertical acceleration in m/s2, positive up, from the accelerometer's specific force
+    fn vertical_acceleration(&mut self, accel: [f32; 3], dt: f32) -> f32 {
+        let magnitude = accel.iter().map(|value| value.powi(2)).sum::<f32>().sqrt();
+        if magnitude == 0.0 {
+            return 0.0;
+        }
+        let measured_down = accel.map(|value| -value / magnitude);
+
+        let alpha = dt / (self.settings.time_constant + dt);
+        let down = match self.down {
+            Some(down) => {
+                let mixed =
+                    [0, 1, 2].map(|axis| down[axis] + alpha * (measured_down[axis] - down[axis]));
+                let norm = mixed.iter().map(|value| value.powi(2)).sum::<f32>().sqrt();
+                mixed.map(|value| value / norm)
+            }
+            None => measured_down,
+        };
+        self.down = Some(down);
+
+        // At rest the specific force is opposite to gravity, which cancels both terms
+        let force_down: f32 = (0..3).map(|axis| accel[axis] * down[axis]).sum();
+        -(force_down + GRAVITY)
+    }
+
+    fn update(&mut self, pressure: f32, accel: Option<[f32; 3]>, instant: Instant) -> f32 {
+        let measured_height = self.pressure_height(pressure);
+
+        let dt = self
+            .last_update
+            .map(|last| instant.duration_since(last).as_secs_f32());
+        self.last_update = Some(instant);
+
+        // Restarts from the measured height on the first sample or after a long pause
+        let Some(dt) = dt.filter(|dt| (0.0..MAX_INTERVAL).contains(dt) && *dt > 0.0) else {
+            self.height = measured_height;
+            self.speed = 0.0;
+            self.down = None;
+            return self.speed;
+        };
+
+        let acceleration = accel
+            .map(|accel| self.vertical_acceleration(accel, dt))
+            .unwrap_or(0.0);
+
+        // Second order complementary filter, critically damped at the time constant
+        let error = measured_height - self.height;
+        let height_gain = 2.0 / self.settings.time_constant;
+        let speed_gain = 1.0 / self.settings.time_constant.powi(2);
+        self.height += (self.speed + height_gain * error) * dt;
+        self.speed += (acceleration + speed_gain * error) * dt;
+
+        self.speed
+    }
+}
+
+/// Feeds a monitor sample, returns the updated vertical speed in m/s, positive up
+pub fn update(pressure: f32, accel: Option<[f32; 3]>, instant: Instant) -> Option<f32> {
+    match ESTIMATOR.lock() {
+        Ok(mut estimator) => Some(estimator.update(pressure, accel, instant)),
+        Err(error) => {
+            log::error!("Vertical speed: {error}");
+            None
+        }
+    }
+}
+
+/// Depth in meters below the sea level pressure, with the configured fluid density
+pub fn depth(pressure: f32) -> f32 {
+    water_depth(pressure, settings().fluid_density)
+}
+
+/// Depth in meters of a water column of this density, from its pressure in kPa
+fn water_depth(pressure: f32, fluid_density: f32) -> f32 {
+    (pressure - SEA_LEVEL_PRESSURE) * 1000.0 / (fluid_density * GRAVITY)
+}
+
+pub fn settings() -> VerticalSpeedSettings {
+    ESTIMATOR
+        .lock()
+        .map(|estimator| estimator.settings.clone())
+        .unwrap_or_default()
+}
+
+/// Applies new settings, restarting the estimation
+pub fn set_settings(settings: VerticalSpeedSettings) {
+    if let Ok(mut estimator) = ESTIMATOR.lock() {
+        *estimator = VerticalSpeedEstimator {
+            settings,
+            ..Default::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Pressure in kPa at `depth` meters of sea water
+    fn pressure_at(depth: f32) -> f32 {
+        SEA_LEVEL_PRESSURE + depth * 1025.0 * GRAVITY / 1000.0
+    }
+
+    #[test]
+    fn pressure_to_height() {
+        assert!((water_depth(pressure_at(10.0), 1025.0) - 10.0).abs() < 1e-3);
+        assert_eq!(water_depth(SEA_LEVEL_PRESSURE, 997.0), 0.0);
+
+        let air = VerticalSpeedEstimator {
+            settings: VerticalSpeedSettings {
+                medium: Medium::Air,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(air.pressure_height(SEA_LEVEL_PRESSURE), 0.0);
+        // About 8.4 m per hPa close to the sea level
+        let height = air.pressure_height(SEA_LEVEL_PRESSURE - 0.1);
+        assert!((8.0..9.0).contains(&height), "{height}");
+    }
+
+    #[test]
+    fn follows_a_steady_descent() {
+        let mut estimator = VerticalSpeedEstimator::default();
+        let start = Instant::now();
+        let at_rest = Some([0.0, 0.0, -GRAVITY]);
+        assert_eq!(estimator.update(pressure_at(0.0), at_rest, start), 0.0);
+
+        // 0.5 m/s down for ten time constants, the accelerometer at rest once moving
+        let mut speed = 0.0;
+        for n in 1..=1000 {
+            let depth = 0.5 * n as f32 / 100.0;
+            let instant = start + Duration::from_millis(10 * n);
+            speed = estimator.update(pressure_at(depth), at_rest, instant);
+        }
+        assert!((speed + 0.5).abs() < 0.01, "{speed}");
+        assert!((estimator.height + 5.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn stays_still_at_rest_and_restarts_after_pauses() {
+        let mut estimator = VerticalSpeedEstimator::default();
+        let start = Instant::now();
+        for n in 0..200 {
+            let instant = start + Duration::from_millis(10 * n);
+            let speed = estimator.update(pressure_at(3.0), Some([0.0, 0.0, -GRAVITY]), instant);
+            assert!(speed.abs() < 1e-4, "{speed}");
+        }
+
+        // A jump after a pause longer than `MAX_INTERVAL` is taken as the new height
+        let instant = start + Duration::from_secs(5);
+        assert_eq!(estimator.update(pressure_at(8.0), None, instant), 0.0);
+        assert!((estimator.height + 8.0).abs() < 1e-3);
+        assert_eq!(estimator.down, None);
+
+        // Repeated timestamps restart as well instead of dividing by zero
+        assert_eq!(estimator.update(pressure_at(9.0), None, instant), 0.0);
+    }
+}
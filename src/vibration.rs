@@ -0,0 +1,370 @@
+use lazy_static::lazy_static;
+use paperclip::actix::Apiv2Schema;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Amount of peaks reported for each axis
+const PEAKS_PER_AXIS: usize = 5;
+
+/// Smallest analysis window, shorter tapered windows are all zeros
+pub const MIN_WINDOW_SIZE: usize = 4;
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum WindowFunction {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+impl WindowFunction {
+    fn coefficients(&self, size: usize) -> Vec<f32> {
+        let last = (size.max(2) - 1) as f32;
+        (0..size)
+            .map(|n| {
+                let phase = 2.0 * std::f32::consts::PI * n as f32 / last;
+                match self {
+                    Self::Rectangular => 1.0,
+                    Self::Hann => 0.5 - 0.5 * phase.cos(),
+                    Self::Hamming => 0.54 - 0.46 * phase.cos(),
+                    Self::Blackman => 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Apiv2Schema, Debug, Serialize, Deserialize, Clone)]
+pub struct VibrationSettings {
+    pub enable: bool,
+    /// Sampling rate in Hz, limited by the monitor's rate
+    pub sample_rate: f64,
+    /// Samples per analysis window, must be a power of two of at least `MIN_WINDOW_SIZE`
+    pub window_size: usize,
+    pub window: WindowFunction,
+    /// Broadcasts every new analysis over websocket
+    pub stream: bool,
+}
+
+impl Default for VibrationSettings {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            sample_rate: 100.0,
+            window_size: 256,
+            window: WindowFunction::Hann,
+            stream: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Peak {
+    pub frequency: f32,
+    pub power: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AxisSpectrum {
+    /// RMS level of the axis with its mean removed, same unit as the sensor
+    pub rms: f32,
+    /// Strongest local maxima of the spectrum, in descending power
+    pub peaks: Vec<Peak>,
+    /// One-sided power spectral density, sensor unit squared per Hz
+    pub power: Vec<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TriaxialSpectrum {
+    pub x: AxisSpectrum,
+    pub y: AxisSpectrum,
+    pub z: AxisSpectrum,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct VibrationAnalysis {
+    /// Effective sample rate measured over the window, in Hz
+    pub sample_rate: f32,
+    pub window_size: usize,
+    /// Frequency of each spectrum bin, in Hz
+    pub frequencies: Vec<f32>,
+    pub accelerometer: TriaxialSpectrum,
+    pub gyroscope: TriaxialSpectrum,
+}
+
+#[derive(Default)]
+struct VibrationAnalyzer {
+    settings: VibrationSettings,
+    accelerometer: [Vec<f32>; 3],
+    gyroscope: [Vec<f32>; 3],
+    first_sample: Option<Instant>,
+    next_sample: Option<Instant>,
+    plan: Option<Arc<dyn Fft<f32>>>,
+    latest: Option<VibrationAnalysis>,
+}
+
+lazy_static! {
+    static ref ANALYZER: Mutex<VibrationAnalyzer> = Default::default();
+}
+
+impl VibrationAnalyzer {
+    fn clear(&mut self) {
+        self.accelerometer.iter_mut().for_each(Vec::clear);
+        self.gyroscope.iter_mut().for_each(Vec::clear);
+        self.first_sample = None;
+        self.next_sample = None;
+    }
+
+    fn push(
+        &mut self,
+        accelerometer: [f32; 3],
+        gyroscope: [f32; 3],
+        instant: Instant,
+    ) -> Option<VibrationAnalysis> {
+        if !self.settings.enable {
+            return None;
+        }
+
+        // Decimates the monitor's samples to the configured rate
+        let period = Duration::from_secs_f64(1.0 / self.settings.sample_rate);
+        if self.next_sample.is_some_and(|next| instant < next) {
+            return None;
+        }
+        self.next_sample = Some(match self.next_sample {
+            // Keeps the schedule, unless the monitor fell behind it
+            Some(next) if instant < next + period => next + period,
+            _ => instant + period,
+        });
+
+        let first_sample = *self.first_sample.get_or_insert(instant);
+        for axis in 0..3 {
+            self.accelerometer[axis].push(accelerometer[axis]);
+            self.gyroscope[axis].push(gyroscope[axis]);
+        }
+
+        if self.accelerometer[0].len() < self.settings.window_size {
+            return None;
+        }
+
+        let elapsed = instant.duration_since(first_sample).as_secs_f32();
+        let sample_rate = if elapsed > 0.0 {
+            (self.settings.window_size - 1) as f32 / elapsed
+        } else {
+            self.settings.sample_rate as f32
+        };
+
+        let analysis = self.analyze(sample_rate);
+        self.clear();
+        self.latest = Some(analysis.clone());
+
+        self.settings.stream.then_some(analysis)
+    }
+
+    fn analyze(&mut self, sample_rate: f32) -> VibrationAnalysis {
+        let window_size = self.settings.window_size;
+        let plan = match &self.plan {
+            Some(plan) if plan.len() == window_size => plan.clone(),
+            _ => {
+                let plan = FftPlanner::new().plan_fft_forward(window_size);
+                self.plan = Some(plan.clone());
+                plan
+            }
+        };
+        let window = self.settings.window.coefficients(window_size);
+
+        let spectrum = |samples: &[Vec<f32>; 3]| TriaxialSpectrum {
+            x: axis_spectrum(&samples[0], &window, plan.as_ref(), sample_rate),
+            y: axis_spectrum(&samples[1], &window, plan.as_ref(), sample_rate),
+            z: axis_spectrum(&samples[2], &window, plan.as_ref(), sample_rate),
+        };
+
+        VibrationAnalysis {
+            sample_rate,
+            window_size,
+            frequencies: (0..=window_size / 2)
+                .map(|bin| bin as f32 * sample_rate / window_size as f32)
+                .collect(),
+            accelerometer: spectrum(&self.accelerometer),
+            gyroscope: spectrum(&self.gyroscope),
+        }
+    }
+}
+
+fn axis_spectrum(
+    samples: &[f32],
+    window: &[f32],
+    plan: &dyn Fft<f32>,
+    sample_rate: f32,
+) -> AxisSpectrum {
+    let size = samples.len();
+    let mean = samples.iter().sum::<f32>() / size as f32;
+    let rms = (samples
+        .iter()
+        .map(|sample| (sample - mean).powi(2))
+        .sum::<f32>()
+        / size as f32)
+        .sqrt();
+
+    let mut buffer: Vec<Complex<f32>> = samples
+        .iter()
+        .zip(window)
+        .map(|(sample, coefficient)| Complex::new((sample - mean) * coefficient, 0.0))
+        .collect();
+    plan.process(&mut buffer);
+
+    // Scaled by the window's energy, doubled on bins that fold their negative frequency
+    let scale = 1.0
+        / (sample_rate
+            * window
+                .iter()
+                .map(|coefficient| coefficient.powi(2))
+                .sum::<f32>());
+    let power: Vec<f32> = buffer[..=size / 2]
+        .iter()
+        .enumerate()
+        .map(|(bin, value)| {
+            let folding = if bin == 0 || bin == size / 2 {
+                1.0
+            } else {
+                2.0
+            };
+            value.norm_sqr() * scale * folding
+        })
+        .collect();
+
+    let mut peaks: Vec<Peak> = (1..power.len().saturating_sub(1))
+        .filter(|&bin| power[bin] > power[bin - 1] && power[bin] >= power[bin + 1])
+        .map(|bin| Peak {
+            frequency: bin as f32 * sample_rate / size as f32,
+            power: power[bin],
+        })
+        .collect();
+    peaks.sort_by(|a, b| b.power.total_cmp(&a.power));
+    peaks.truncate(PEAKS_PER_AXIS);
+
+    AxisSpectrum { rms, peaks, power }
+}
+
+/// Feeds a monitor sample, returns the new analysis when a window completes and streaming is enabled
+pub fn push(
+    accelerometer: [f32; 3],
+    gyroscope: [f32; 3],
+    instant: Instant,
+) -> Option<VibrationAnalysis> {
+    match ANALYZER.lock() {
+        Ok(mut analyzer) => analyzer.push(accelerometer, gyroscope, instant),
+        Err(error) => {
+            log::error!("Vibration: {error}");
+            None
+        }
+    }
+}
+
+pub fn latest() -> Option<VibrationAnalysis> {
+    ANALYZER.lock().ok()?.latest.clone()
+}
+
+pub fn settings() -> VibrationSettings {
+    ANALYZER
+        .lock()
+        .map(|analyzer| analyzer.settings.clone())
+        .unwrap_or_default()
+}
+
+/// Applies new settings, discarding the window being collected
+pub fn set_settings(settings: VibrationSettings) {
+    if let Ok(mut analyzer) = ANALYZER.lock() {
+        analyzer.settings = settings;
+        analyzer.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analyzer(window_size: usize, window: WindowFunction) -> VibrationAnalyzer {
+        VibrationAnalyzer {
+            settings: VibrationSettings {
+                enable: true,
+                sample_rate: 100.0,
+                window_size,
+                window,
+                stream: true,
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Feeds `samples` to the accelerometer's x axis at exactly 100 Hz
+    fn feed(analyzer: &mut VibrationAnalyzer, samples: &[f32]) -> Option<VibrationAnalysis> {
+        let start = Instant::now();
+        let mut analysis = None;
+        for (n, sample) in samples.iter().enumerate() {
+            let instant = start + Duration::from_millis(10 * n as u64);
+            analysis = analyzer.push([*sample, 0.0, 0.0], [0.0; 3], instant);
+        }
+        analysis
+    }
+
+    #[test]
+    fn sine_peak_and_rms() {
+        let mut analyzer = analyzer(256, WindowFunction::Hann);
+        // 12.5 Hz falls on bin 32 of 256 samples at 100 Hz
+        let samples: Vec<f32> = (0..256)
+            .map(|n| 2.0 * (2.0 * std::f32::consts::PI * 12.5 * n as f32 / 100.0).sin() + 9.8)
+            .collect();
+        let analysis = feed(&mut analyzer, &samples).expect("window completes");
+
+        assert_eq!(analysis.frequencies.len(), 129);
+        let x = &analysis.accelerometer.x;
+        assert!((x.rms - 2.0 / 2f32.sqrt()).abs() < 1e-3, "rms {}", x.rms);
+        assert!((x.peaks[0].frequency - 12.5).abs() < 0.1);
+        assert!(analysis.accelerometer.y.peaks.is_empty());
+        assert_eq!(analysis.accelerometer.y.rms, 0.0);
+    }
+
+    #[test]
+    fn rectangular_power_sums_to_variance() {
+        let mut analyzer = analyzer(64, WindowFunction::Rectangular);
+        let samples: Vec<f32> = (0..64).map(|n| ((n * 7919) % 13) as f32 - 6.0).collect();
+        let analysis = feed(&mut analyzer, &samples).unwrap();
+
+        let x = &analysis.accelerometer.x;
+        let resolution = analysis.sample_rate / 64.0;
+        let total = x.power.iter().sum::<f32>() * resolution;
+        assert!((total - x.rms.powi(2)).abs() < 1e-3 * x.rms.powi(2));
+    }
+
+    #[test]
+    fn decimates_to_sample_rate() {
+        let mut analyzer = analyzer(4, WindowFunction::Hann);
+        let start = Instant::now();
+        // Twice the configured rate, every other sample is dropped
+        for n in 0..6 {
+            let instant = start + Duration::from_millis(5 * n);
+            assert!(analyzer.push([0.0; 3], [0.0; 3], instant).is_none());
+        }
+        assert_eq!(analyzer.accelerometer[0].len(), 3);
+        let analysis = analyzer.push([0.0; 3], [0.0; 3], start + Duration::from_millis(30));
+        assert!((analysis.unwrap().sample_rate - 100.0).abs() < 1e-3);
+        // The window restarts empty
+        assert!(analyzer.accelerometer[0].is_empty());
+    }
+
+    #[test]
+    fn disabled_or_minimal_windows() {
+        let mut disabled = analyzer(4, WindowFunction::Hann);
+        disabled.settings.enable = false;
+        assert!(feed(&mut disabled, &[1.0; 8]).is_none());
+        assert!(disabled.accelerometer[0].is_empty());
+        assert!(disabled.latest.is_none());
+
+        let mut shortest = analyzer(MIN_WINDOW_SIZE, WindowFunction::Blackman);
+        let analysis = feed(&mut shortest, &[3.0; MIN_WINDOW_SIZE]).unwrap();
+        assert_eq!(analysis.accelerometer.x.power, vec![0.0; 3]);
+        assert!(analysis.accelerometer.x.peaks.is_empty());
+    }
+}